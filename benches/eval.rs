@@ -0,0 +1,15 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use rusty_space::bench::Bodies;
+
+/// Evaluates the properties of a few thousand bodies, as for one frame.
+fn frame(c: &mut Criterion) {
+    let mut bodies = Bodies::new(5000);
+    let mut group = c.benchmark_group("5000 bodies");
+    group.bench_function("eval", |b| b.iter(|| bodies.eval(black_box(100.))));
+    group
+        .bench_function("compiled", |b| b.iter(|| bodies.run(black_box(100.))));
+    group.finish();
+}
+
+criterion_group!(benches, frame);
+criterion_main!(benches);
@@ -22,7 +22,7 @@ fn peek_char(input: &str) -> Option<char> {
     input.chars().next()
 }
 
-fn source(mut input: &str) -> (&str, TokenTree) {
+fn source(mut input: &str) -> (&str, TokenTree<'_>) {
     let mut tokens = vec![];
     while !input.is_empty() {
         input = if let Some((next_input, token)) = token(input) {
@@ -59,7 +59,7 @@ enum TokenTree<'src> {
     Tree(Vec<TokenTree<'src>>),
 }
 
-fn token(input: &str) -> Option<(&str, Token)> {
+fn token(input: &str) -> Option<(&str, Token<'_>)> {
     if let Some(res) = ident(whitespace(input)) {
         return Some(res);
     }
@@ -88,7 +88,7 @@ fn whitespace(mut input: &str) -> &str {
     input
 }
 
-fn ident(mut input: &str) -> Option<(&str, Token)> {
+fn ident(mut input: &str) -> Option<(&str, Token<'_>)> {
     let start = input;
     if matches!(peek_char(input), Some(_x @ ('a'..='z' | 'A'..='Z'))) {
         input = advance_char(input);
@@ -104,7 +104,7 @@ fn ident(mut input: &str) -> Option<(&str, Token)> {
     }
 }
 
-fn number(mut input: &str) -> Option<(&str, Token)> {
+fn number(mut input: &str) -> Option<(&str, Token<'_>)> {
     let start = input;
     if matches!(peek_char(input), Some(_x @ ('-' | '+' | '.' | '0'..='9'))) {
         input = advance_char(input);
@@ -121,7 +121,7 @@ fn number(mut input: &str) -> Option<(&str, Token)> {
     }
 }
 
-fn lparen(mut input: &str) -> Option<(&str, Token)> {
+fn lparen(mut input: &str) -> Option<(&str, Token<'_>)> {
     if matches!(peek_char(input), Some('(')) {
         input = advance_char(input);
         Some((input, Token::LParen))
@@ -130,7 +130,7 @@ fn lparen(mut input: &str) -> Option<(&str, Token)> {
     }
 }
 
-fn rparen(mut input: &str) -> Option<(&str, Token)> {
+fn rparen(mut input: &str) -> Option<(&str, Token<'_>)> {
     if matches!(peek_char(input), Some(')')) {
         input = advance_char(input);
         Some((input, Token::RParen))
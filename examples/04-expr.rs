@@ -32,7 +32,7 @@ enum Expression<'src> {
     Add(Box<Expression<'src>>, Box<Expression<'src>>),
 }
 
-fn expr(input: &str) -> Option<(&str, Expression)> {
+fn expr(input: &str) -> Option<(&str, Expression<'_>)> {
     if let Some(res) = add(input) {
         return Some(res);
     }
@@ -44,7 +44,7 @@ fn expr(input: &str) -> Option<(&str, Expression)> {
     None
 }
 
-fn paren(input: &str) -> Option<(&str, Expression)> {
+fn paren(input: &str) -> Option<(&str, Expression<'_>)> {
     let next_input = lparen(whitespace(input))?;
 
     let (next_input, expr) = expr(next_input)?;
@@ -54,7 +54,7 @@ fn paren(input: &str) -> Option<(&str, Expression)> {
     Some((next_input, expr))
 }
 
-fn add_term(input: &str) -> Option<(&str, Expression)> {
+fn add_term(input: &str) -> Option<(&str, Expression<'_>)> {
     let (next_input, lhs) = term(input)?;
 
     let next_input = plus(whitespace(next_input))?;
@@ -62,7 +62,7 @@ fn add_term(input: &str) -> Option<(&str, Expression)> {
     Some((next_input, lhs))
 }
 
-fn add(mut input: &str) -> Option<(&str, Expression)> {
+fn add(mut input: &str) -> Option<(&str, Expression<'_>)> {
     let mut left = None;
     while let Some((next_input, expr)) = add_term(input) {
         if let Some(prev_left) = left {
@@ -80,7 +80,7 @@ fn add(mut input: &str) -> Option<(&str, Expression)> {
     Some((next_input, Expression::Add(Box::new(left), Box::new(rhs))))
 }
 
-fn term(input: &str) -> Option<(&str, Expression)> {
+fn term(input: &str) -> Option<(&str, Expression<'_>)> {
     if let Some(res) = paren(input) {
         return Some(res);
     }
@@ -92,7 +92,7 @@ fn term(input: &str) -> Option<(&str, Expression)> {
     None
 }
 
-fn token(input: &str) -> Option<(&str, Expression)> {
+fn token(input: &str) -> Option<(&str, Expression<'_>)> {
     if let Some(res) = ident(whitespace(input)) {
         return Some(res);
     }
@@ -109,7 +109,7 @@ fn whitespace(mut input: &str) -> &str {
     input
 }
 
-fn ident(mut input: &str) -> Option<(&str, Expression)> {
+fn ident(mut input: &str) -> Option<(&str, Expression<'_>)> {
     let start = input;
     if matches!(peek_char(input), Some(_x @ ('a'..='z' | 'A'..='Z'))) {
         input = advance_char(input);
@@ -130,7 +130,7 @@ fn ident(mut input: &str) -> Option<(&str, Expression)> {
     }
 }
 
-fn number(mut input: &str) -> Option<(&str, Expression)> {
+fn number(mut input: &str) -> Option<(&str, Expression<'_>)> {
     let start = input;
     if matches!(peek_char(input), Some(_x @ ('-' | '+' | '.' | '0'..='9'))) {
         input = advance_char(input);
@@ -38,11 +38,11 @@ enum Expression<'src> {
     Add(Box<Expression<'src>>, Box<Expression<'src>>),
 }
 
-fn term(i: &str) -> IResult<&str, Expression> {
+fn term(i: &str) -> IResult<&str, Expression<'_>> {
     alt((number, ident, parens))(i)
 }
 
-fn ident(input: &str) -> IResult<&str, Expression> {
+fn ident(input: &str) -> IResult<&str, Expression<'_>> {
     let (r, res) = delimited(multispace0, identifier, multispace0)(input)?;
     Ok((r, Expression::Value(Token::Ident(res))))
 }
@@ -54,7 +54,7 @@ fn identifier(input: &str) -> IResult<&str, &str> {
     ))(input)
 }
 
-fn number(input: &str) -> IResult<&str, Expression> {
+fn number(input: &str) -> IResult<&str, Expression<'_>> {
     let (r, v) = delimited(multispace0, recognize_float, multispace0)(input)?;
     Ok((
         r,
@@ -67,7 +67,7 @@ fn number(input: &str) -> IResult<&str, Expression> {
     ))
 }
 
-fn parens(i: &str) -> IResult<&str, Expression> {
+fn parens(i: &str) -> IResult<&str, Expression<'_>> {
     delimited(
         multispace0,
         delimited(tag("("), expr, tag(")")),
@@ -75,7 +75,7 @@ fn parens(i: &str) -> IResult<&str, Expression> {
     )(i)
 }
 
-fn expr(i: &str) -> IResult<&str, Expression> {
+fn expr(i: &str) -> IResult<&str, Expression<'_>> {
     let (i, init) = term(i)?;
 
     fold_many0(
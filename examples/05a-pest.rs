@@ -93,7 +93,7 @@ fn parse_sum(sum: Pair<Rule>) -> Expression {
 }
 
 fn parse_string(input: &str) -> Option<Expression> {
-    let parsed = ExprParser::parse(Rule::file, &input)
+    let parsed = ExprParser::parse(Rule::file, input)
         .expect("unsuccessful parse") // unwrap the parse result
         .next()
         .unwrap(); // get and unwrap the `file` rule; never fails
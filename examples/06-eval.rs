@@ -10,9 +10,7 @@ use nom::{
 };
 
 fn main() {
-    fn ex_eval<'src>(
-        input: &'src str,
-    ) -> Result<f64, nom::Err<nom::error::Error<&'src str>>> {
+    fn ex_eval(input: &str) -> Result<f64, nom::Err<nom::error::Error<&str>>> {
         expr(input).map(|(_, e)| eval(e))
     }
 
@@ -45,11 +43,11 @@ fn eval(expr: Expression) -> f64 {
     }
 }
 
-fn term(i: &str) -> IResult<&str, Expression> {
+fn term(i: &str) -> IResult<&str, Expression<'_>> {
     alt((number, ident, parens))(i)
 }
 
-fn ident(input: &str) -> IResult<&str, Expression> {
+fn ident(input: &str) -> IResult<&str, Expression<'_>> {
     let (r, res) = delimited(multispace0, identifier, multispace0)(input)?;
     Ok((r, Expression::Ident(res)))
 }
@@ -61,7 +59,7 @@ fn identifier(input: &str) -> IResult<&str, &str> {
     ))(input)
 }
 
-fn number(input: &str) -> IResult<&str, Expression> {
+fn number(input: &str) -> IResult<&str, Expression<'_>> {
     let (r, v) = delimited(multispace0, recognize_float, multispace0)(input)?;
     Ok((
         r,
@@ -74,7 +72,7 @@ fn number(input: &str) -> IResult<&str, Expression> {
     ))
 }
 
-fn parens(i: &str) -> IResult<&str, Expression> {
+fn parens(i: &str) -> IResult<&str, Expression<'_>> {
     delimited(
         multispace0,
         delimited(tag("("), expr, tag(")")),
@@ -82,7 +80,7 @@ fn parens(i: &str) -> IResult<&str, Expression> {
     )(i)
 }
 
-fn expr(i: &str) -> IResult<&str, Expression> {
+fn expr(i: &str) -> IResult<&str, Expression<'_>> {
     let (i, init) = term(i)?;
 
     fold_many0(
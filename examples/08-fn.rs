@@ -10,9 +10,7 @@ use nom::{
 };
 
 fn main() {
-    fn ex_eval<'src>(
-        input: &'src str,
-    ) -> Result<f64, nom::Err<nom::error::Error<&'src str>>> {
+    fn ex_eval(input: &str) -> Result<f64, nom::Err<nom::error::Error<&str>>> {
         expr(input).map(|(_, e)| eval(e))
     }
 
@@ -96,11 +94,11 @@ fn eval(expr: Expression) -> f64 {
     }
 }
 
-fn factor(i: &str) -> IResult<&str, Expression> {
+fn factor(i: &str) -> IResult<&str, Expression<'_>> {
     alt((number, func_call, ident, parens))(i)
 }
 
-fn func_call(i: &str) -> IResult<&str, Expression> {
+fn func_call(i: &str) -> IResult<&str, Expression<'_>> {
     let (r, ident) = delimited(multispace0, identifier, multispace0)(i)?;
     // println!("func_invoke ident: {}", ident);
     let (r, args) = delimited(
@@ -119,7 +117,7 @@ fn func_call(i: &str) -> IResult<&str, Expression> {
     Ok((r, Expression::FnInvoke(ident, args)))
 }
 
-fn ident(input: &str) -> IResult<&str, Expression> {
+fn ident(input: &str) -> IResult<&str, Expression<'_>> {
     let (r, res) = delimited(multispace0, identifier, multispace0)(input)?;
     Ok((r, Expression::Ident(res)))
 }
@@ -131,7 +129,7 @@ fn identifier(input: &str) -> IResult<&str, &str> {
     ))(input)
 }
 
-fn number(input: &str) -> IResult<&str, Expression> {
+fn number(input: &str) -> IResult<&str, Expression<'_>> {
     let (r, v) = delimited(multispace0, recognize_float, multispace0)(input)?;
     Ok((
         r,
@@ -144,7 +142,7 @@ fn number(input: &str) -> IResult<&str, Expression> {
     ))
 }
 
-fn parens(i: &str) -> IResult<&str, Expression> {
+fn parens(i: &str) -> IResult<&str, Expression<'_>> {
     delimited(
         multispace0,
         delimited(tag("("), expr, tag(")")),
@@ -152,7 +150,7 @@ fn parens(i: &str) -> IResult<&str, Expression> {
     )(i)
 }
 
-fn term(i: &str) -> IResult<&str, Expression> {
+fn term(i: &str) -> IResult<&str, Expression<'_>> {
     let (i, init) = factor(i)?;
 
     fold_many0(
@@ -171,7 +169,7 @@ fn term(i: &str) -> IResult<&str, Expression> {
     )(i)
 }
 
-fn expr(i: &str) -> IResult<&str, Expression> {
+fn expr(i: &str) -> IResult<&str, Expression<'_>> {
     let (i, init) = term(i)?;
 
     fold_many0(
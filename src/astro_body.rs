@@ -1,55 +1,15 @@
-use std::collections::HashMap;
-
-use crate::parser::{eval, Arg, Command, Property};
+use crate::{
+    color::Color,
+    functions::FunctionRegistry,
+    kepler::{equatorial_to_ecliptic, Elements},
+    scene::{Animation, BodySpec, ReferencePlane},
+};
 
 use three_d::*;
 use three_d_asset::geometry::TriMesh;
 use three_d_asset::io::RawAssets;
 
-fn parse_astro_command<'a, 'src>(
-    command: &'a Command<'src>,
-) -> Option<(String, &'a [Command<'src>])> {
-    if let Command::Com(v) = command {
-        if v.len() < 3 || !matches!(v[0], Arg::Str("astro")) {
-            return None;
-        }
-        let block = &v[2];
-        let name = &v[1];
-        let name = if let Arg::Str(s) = *name {
-            s.to_owned()
-        } else {
-            return None;
-        };
-        let block = if let Arg::Block(s) = block {
-            s
-        } else {
-            return None;
-        };
-        Some((name, block))
-    } else {
-        None
-    }
-}
-
-pub(crate) fn scan_textures(
-    command: &Command,
-    textures: &mut Vec<String>,
-) -> Option<()> {
-    let (_name, block) = parse_astro_command(command)?;
-    for com in block {
-        match com {
-            Command::Prop("texture", Property::Str(value)) => {
-                textures.push(value.clone());
-            }
-            Command::Com(_) => {
-                scan_textures(&com, textures);
-            }
-            _ => (),
-        }
-    }
-    None
-}
-
+#[allow(clippy::large_enum_variant)]
 pub(crate) enum Object {
     Color(Gm<Mesh, ColorMaterial>),
     Physical(Gm<Mesh, PhysicalMaterial>),
@@ -65,118 +25,73 @@ impl AsRef<dyn three_d::Object> for Object {
 }
 
 pub(crate) struct AstroBody {
-    #[allow(dead_code)]
     pub name: String,
     pub radius: f32,
-    pub semimajor_axis: f32,
+    pub elements: Elements,
+    /// The mean motion along the orbit, in radians per day.
     pub omega: f32,
     pub rotation_omega: f32,
+    /// The angular speed of the axis around the normal of the orbit.
+    pub precession_omega: f32,
+    /// The angles the mean anomaly has advanced since `t = 0`, the body has
+    /// turned around its axis and the axis has turned westward around the
+    /// normal of the orbit, which advance at every frame by the current
+    /// angular speeds. The mean anomaly of open trajectories grows without
+    /// bound, so it is kept in `f64`.
+    pub orbit_angle: f64,
+    pub rotation_angle: f32,
+    pub precession_angle: f32,
+    pub orientation: Orientation,
+    /// The properties that change over time.
+    pub animations: Vec<Animation>,
     pub model: Object,
     pub orbit_model: Option<Gm<Mesh, PhysicalMaterial>>,
+    /// Whether the orbit line is an open trajectory of [`trajectory`]
+    /// rather than the circle of [`ring`], as the eccentricity at `t = 0`
+    /// decides.
+    pub open_orbit: bool,
+    /// The bands of the ring around the equator, from the inside out.
+    pub ring_models: Vec<Gm<Mesh, PhysicalMaterial>>,
     pub children: Vec<AstroBody>,
 }
 
+/// The graphics resources needed to create the models of bodies.
 pub(crate) struct BodyContext<'a> {
     pub context: &'a Context,
     pub loaded: &'a mut RawAssets,
     pub mesh: &'a TriMesh,
-    variables: HashMap<String, f64>,
-}
-
-impl<'a> BodyContext<'a> {
-    pub fn new(
-        context: &'a Context,
-        loaded: &'a mut RawAssets,
-        mesh: &'a TriMesh,
-    ) -> Self {
-        Self {
-            context,
-            loaded,
-            mesh,
-            variables: HashMap::new(),
-        }
-    }
 }
 
+/// Creates the bodies of `specs`, whose expressions can call the functions
+/// in `functions`, as when the scene was loaded.
 pub(crate) fn load_astro_bodies(
-    commands: &[Command],
+    specs: &[BodySpec],
     context: &mut BodyContext,
-) -> Vec<AstroBody> {
-    let mut bodies = vec![];
-    for command in commands {
-        if let Some(body) = load_astro_body(command, context) {
-            bodies.push(body);
-        }
-        if let Command::Def(name, expr) = command {
-            context
-                .variables
-                .insert((*name).to_owned(), eval(expr, &context.variables));
-            println!("variables: {:?}", context.variables);
-        }
-    }
-    bodies
+    functions: &FunctionRegistry,
+) -> Result<Vec<AstroBody>, String> {
+    specs
+        .iter()
+        .map(|spec| load_astro_body(spec, context, functions))
+        .collect()
 }
 
 pub(crate) fn load_astro_body(
-    command: &Command,
+    spec: &BodySpec,
     context: &mut BodyContext,
-) -> Option<AstroBody> {
-    let (name, block) = parse_astro_command(command)?;
-    let mut texture = None;
-    let mut radius = 0.1;
-    let mut semimajor_axis = 1.;
-    let mut omega = 1.;
-    let mut rotation_omega = 0.;
-    let mut star = false;
-    let mut children = vec![];
-    for com in block {
-        match com {
-            Command::Prop("texture", Property::Str(value)) => {
-                texture = Some(value);
-            }
-            Command::Prop("radius", Property::Expr(ref value)) => {
-                radius = eval(value, &context.variables) as f32;
-            }
-            Command::Prop("semimajor_axis", Property::Expr(ref value)) => {
-                semimajor_axis = eval(value, &context.variables) as f32;
-            }
-            Command::Prop("orbit_period", Property::Expr(ref value)) => {
-                omega = 2. * std::f32::consts::PI
-                    / eval(value, &context.variables) as f32;
-            }
-            Command::Prop("rotation_period", Property::Expr(ref value)) => {
-                rotation_omega = 2. * std::f32::consts::PI
-                    / eval(value, &context.variables) as f32;
-            }
-            Command::Prop("star", Property::Expr(ref value)) => {
-                star = eval(value, &context.variables) != 0.;
-            }
-            Command::Prop(prop, _) => {
-                println!("Unknown property {prop:?}");
-            }
-            Command::Com(_) => {
-                if let Some(child) = load_astro_body(&com, context) {
-                    children.push(child);
-                }
-            }
-            Command::Def(name, expr) => {
-                context
-                    .variables
-                    .insert((*name).to_owned(), eval(expr, &context.variables));
-                println!("variables: {:?}", context.variables);
-            }
-        }
-    }
-
-    let model = if let Some(texture) = texture {
-        let mesh = Mesh::new(&context.context, &context.mesh);
-        if star {
+    functions: &FunctionRegistry,
+) -> Result<AstroBody, String> {
+    let animations = spec
+        .animations(functions)
+        .map_err(|e| format!("body `{}`: {e}", spec.name))?;
+    let model = if let Some(texture) = &spec.texture {
+        let mesh = Mesh::new(context.context, context.mesh);
+        if spec.star {
             let mut model = Gm::new(
                 mesh,
                 ColorMaterial {
                     texture: Some(
                         Texture2D::new(
-                            &context.context,
+                            context.context,
                             &context.loaded.deserialize(texture).unwrap(),
                         )
                         .into(),
@@ -190,7 +105,7 @@ pub(crate) fn load_astro_body(
             let mut model = Gm::new(
                 mesh,
                 PhysicalMaterial::new(
-                    &context.context,
+                    context.context,
                     &CpuMaterial {
                         roughness: 0.6,
                         metallic: 0.6,
@@ -212,9 +127,9 @@ pub(crate) fn load_astro_body(
         let mut mesh_sun = uv_sphere(32);
         mesh_sun.transform(&Matrix4::from_scale(0.3)).unwrap();
         let mut model_sun = Gm::new(
-            Mesh::new(&context.context, &mesh_sun),
+            Mesh::new(context.context, &mesh_sun),
             PhysicalMaterial::new(
-                &context.context,
+                context.context,
                 &CpuMaterial {
                     roughness: 0.6,
                     metallic: 0.6,
@@ -230,61 +145,345 @@ pub(crate) fn load_astro_body(
         Object::Physical(model_sun)
     };
 
-    let orbit_model = if 0. < semimajor_axis {
-        let mut orbit = Gm::new(
-            Mesh::new(&context.context, &ring(64, 0.005)),
+    let open_orbit = 1. <= spec.eccentricity;
+    let orbit_model = if 0. < spec.semimajor_axis {
+        let mesh = if open_orbit {
+            trajectory(&trajectory_points(spec.eccentricity), 0.005)
+        } else {
+            ring(128, 0.005)
+        };
+        let orbit = Gm::new(
+            Mesh::new(context.context, &mesh),
             PhysicalMaterial::new_transparent(
-                &context.context,
+                context.context,
                 &CpuMaterial {
-                    albedo: Color {
-                        r: 0,
-                        g: 255,
-                        b: 0,
-                        a: 200,
-                    },
+                    albedo: srgba(
+                        spec.orbit_color.unwrap_or(Color::rgba(0, 255, 0, 200)),
+                    ),
                     ..Default::default()
                 },
             ),
         );
-        orbit.set_transformation(
-            Mat4::from_scale(semimajor_axis) * Mat4::from_angle_z(Deg(90.)),
-        );
         Some(orbit)
     } else {
         None
     };
 
-    println!(
-        "Adding body {name} radius: {radius}, semimajor_axis: {semimajor_axis}, rotation_omega: {rotation_omega}"
-    );
-    Some(AstroBody {
-        name,
-        radius,
-        semimajor_axis,
+    let bands = spec.ring_colors.len() as f32;
+    let ring_models = spec
+        .ring_colors
+        .iter()
+        .enumerate()
+        .map(|(i, &color)| {
+            let width = (RING_OUTER - RING_INNER) / bands;
+            let inner = RING_INNER + i as f32 * width;
+            let mut band = Gm::new(
+                Mesh::new(context.context, &annulus(64, inner, inner + width)),
+                PhysicalMaterial::new_transparent(
+                    context.context,
+                    &CpuMaterial {
+                        albedo: srgba(color),
+                        ..Default::default()
+                    },
+                ),
+            );
+            band.material.render_states.cull = Cull::None;
+            band
+        })
+        .collect();
+
+    let omega = spec.orbit_period.map_or(1., angular_speed);
+    let rotation_omega = spec.rotation_period.map_or(0., angular_speed);
+    let precession_omega = spec.precession_period.map_or(0., angular_speed);
+    let elements = Elements {
+        semimajor_axis: spec.semimajor_axis,
+        eccentricity: spec.eccentricity,
+        inclination: spec.inclination,
+        longitude_of_ascending_node: spec.longitude_of_ascending_node,
+        argument_of_periapsis: spec.argument_of_periapsis,
+        mean_anomaly_at_epoch: spec.mean_anomaly_at_epoch,
+    };
+    Ok(AstroBody {
+        name: spec.name.clone(),
+        radius: spec.radius as f32,
+        elements,
         omega,
         rotation_omega,
+        precession_omega,
+        orbit_angle: 0.,
+        rotation_angle: spec.prime_meridian as f32,
+        precession_angle: 0.,
+        orientation: Orientation {
+            axis: axis(spec, &elements),
+            axis_from_obliquity: spec.spin_axis.is_none()
+                && spec.spin_axis_right_ascension.is_none()
+                && spec.spin_axis_declination.is_none(),
+            reference_plane: spec.reference_plane,
+            laplace_radius: spec.laplace_radius,
+            tilt_children: spec.tilt_children,
+        },
+        animations,
         model,
         orbit_model,
-        children,
+        open_orbit,
+        ring_models,
+        children: load_astro_bodies(&spec.children, context, functions)?,
     })
 }
 
-pub(crate) fn apply_transform(
-    body: &mut AstroBody,
-    parent_transform: &Matrix4<f32>,
-    frame_time: f64,
-) {
-    let rotation = parent_transform
-        * Matrix4::from_angle_y(Rad(frame_time as f32 * body.omega))
-        * Matrix4::from_translation(Vec3::new(body.semimajor_axis, 0., 0.));
-
-    let origin = rotation
-        .transform_point(Point3::new(0., 0., 0.))
-        .to_homogeneous()
-        .truncate();
-
-    let revolution = Matrix4::from_translation(origin)
-        * Matrix4::from_angle_y(Rad(frame_time as f32 * body.rotation_omega))
+/// The inner and outer radii of rings, relative to the radius of the body.
+const RING_INNER: f32 = 1.2;
+const RING_OUTER: f32 = 2.3;
+
+fn srgba(color: Color) -> Srgba {
+    Srgba::new(color.r, color.g, color.b, color.a)
+}
+
+/// How a body and the orbits of its children are oriented.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Orientation {
+    /// The axis the body turns around at `t = 0`, of length 1, in the frame
+    /// of the scene, or in the reference plane of its orbit if
+    /// `axis_from_obliquity`.
+    pub axis: Vec3,
+    pub axis_from_obliquity: bool,
+    pub reference_plane: Option<ReferencePlane>,
+    pub laplace_radius: Option<f64>,
+    pub tilt_children: bool,
+}
+
+/// Returns the axis of a body from its `spin_axis`, else its right
+/// ascension and declination, else its obliquity to the orbit of
+/// `elements`. A `spin_axis` with no direction is the y axis.
+fn axis(spec: &BodySpec, elements: &Elements) -> Vec3 {
+    let ra = spec.spin_axis_right_ascension;
+    let dec = spec.spin_axis_declination;
+    if let Some([x, y, z]) = spec.spin_axis {
+        let axis = vec3(x as f32, y as f32, z as f32).normalize();
+        if axis.magnitude().is_finite() {
+            axis
+        } else {
+            Vec3::unit_y()
+        }
+    } else if ra.is_some() || dec.is_some() {
+        let ra = ra.unwrap_or(0.);
+        let dec = dec.unwrap_or(std::f64::consts::FRAC_PI_2);
+        to_render(equatorial_to_ecliptic(ra, dec))
+    } else {
+        // Leaning about the line of nodes.
+        orbit_orientation(elements)
+            * Quaternion::from_angle_x(Rad(-spec.obliquity as f32))
+            * Vec3::unit_y()
+    }
+}
+
+/// Where a body is in the frame of the scene, and how the planes the orbits
+/// of its children can be measured from are oriented.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Frame {
+    pub position: Vec3,
+    /// The rotations from the reference plane of the scene to the equator
+    /// of the body, which takes the x axis to its ascending node, and to
+    /// the plane of its orbit.
+    pub equator: Quaternion<f32>,
+    pub orbit: Quaternion<f32>,
+    pub laplace_radius: Option<f64>,
+    pub tilt_children: bool,
+}
+
+impl Frame {
+    /// The frame of the scene, which the bodies at the top orbit in.
+    pub fn scene() -> Self {
+        Self {
+            position: Vec3::zero(),
+            equator: Quaternion::one(),
+            orbit: Quaternion::one(),
+            laplace_radius: None,
+            tilt_children: false,
+        }
+    }
+
+    /// Returns the rotation from the reference plane of the scene to
+    /// `plane` of this body, for an orbit at `distance` from it.
+    fn plane(&self, plane: ReferencePlane, distance: f64) -> Quaternion<f32> {
+        match plane {
+            ReferencePlane::Ecliptic => Quaternion::one(),
+            ReferencePlane::ParentEquator => self.equator,
+            ReferencePlane::Laplace => {
+                let equator = self.equator * Vec3::unit_y();
+                let orbit = self.orbit * Vec3::unit_y();
+                let node = equator.cross(orbit);
+                if node.magnitude2() == 0. {
+                    return self.equator;
+                }
+                // The flattening of the body pulls the plane towards its
+                // equator, and its parent towards its orbit, which balance
+                // around the Laplace radius (Tremaine et al. 2009).
+                let obliquity = equator.angle(orbit).0 as f64;
+                let pull = self
+                    .laplace_radius
+                    .map_or(0., |radius| (radius / distance).powi(5));
+                let warp = 0.5
+                    * (2. * obliquity)
+                        .sin()
+                        .atan2((2. * obliquity).cos() + 2. * pull);
+                let normal = Quaternion::from_axis_angle(
+                    node.normalize(),
+                    Rad(warp as f32),
+                ) * equator;
+                pole_orientation(normal)
+            }
+        }
+    }
+}
+
+/// Returns the frame of a body with `elements` and `orientation` at
+/// `mean_anomaly`, after its axis has turned westward by
+/// `precession_angle` around the normal of its orbit, and the rotation
+/// from the reference plane of the scene to that of its orbit.
+fn body_frame(
+    elements: &Elements,
+    mean_anomaly: f64,
+    orientation: &Orientation,
+    precession_angle: f32,
+    parent: &Frame,
+) -> (Frame, Quaternion<f32>) {
+    let plane =
+        orientation
+            .reference_plane
+            .unwrap_or(if parent.tilt_children {
+                ReferencePlane::ParentEquator
+            } else {
+                ReferencePlane::Ecliptic
+            });
+    let reference = parent.plane(plane, elements.semimajor_axis);
+    let position = parent.position
+        + reference * to_render(elements.position(mean_anomaly));
+    let orbit = reference * orbit_orientation(elements);
+    let axis = if orientation.axis_from_obliquity {
+        reference * orientation.axis
+    } else {
+        orientation.axis
+    };
+    let normal = orbit * Vec3::unit_y();
+    let axis =
+        Quaternion::from_axis_angle(normal, Rad(-precession_angle)) * axis;
+    let frame = Frame {
+        position,
+        equator: pole_orientation(axis),
+        orbit,
+        laplace_radius: orientation.laplace_radius,
+        tilt_children: orientation.tilt_children,
+    };
+    (frame, reference)
+}
+
+/// Returns the rotation from the reference plane of the scene to the plane
+/// with normal `normal`, which takes the x axis to its ascending node.
+fn pole_orientation(normal: Vec3) -> Quaternion<f32> {
+    let node = Vec3::unit_y().cross(normal);
+    // Planes parallel to the reference plane keep its reference direction.
+    let longitude = if node.magnitude2() == 0. {
+        0.
+    } else {
+        (-node.z).atan2(node.x)
+    };
+    let inclination = normal.y.clamp(-1., 1.).acos();
+    Quaternion::from_angle_y(Rad(longitude))
+        * Quaternion::from_angle_x(Rad(inclination))
+}
+
+/// Returns the rotation from the reference plane to the plane of the orbit
+/// of `elements`, which keeps the ascending node on the x axis.
+fn orbit_orientation(elements: &Elements) -> Quaternion<f32> {
+    // The turns of `Elements::position` before the periapsis, about the
+    // axes they become in the frame of the scene.
+    Quaternion::from_angle_y(Rad(elements.longitude_of_ascending_node as f32))
+        * Quaternion::from_angle_x(Rad(elements.inclination as f32))
+}
+
+/// Returns the angular speed of a period in days, in radians per day.
+fn angular_speed(period: f64) -> f32 {
+    std::f32::consts::TAU / period as f32
+}
+
+/// Evaluates the properties that change over time at time `t`, and turns
+/// the body and its children by their angular speeds over `dt`, both in
+/// days.
+pub(crate) fn advance(body: &mut AstroBody, t: f64, dt: f64) {
+    for animation in &mut body.animations {
+        // A property keeps its last value where its expression fails, and
+        // only the first failure is reported.
+        let value = match animation.eval(t) {
+            Ok(value) => value,
+            Err(e) => {
+                if !animation.failed {
+                    animation.failed = true;
+                    let name = animation.schema.name;
+                    report(&format!(
+                        "body `{}`: `{name}` at t = {t} d: {e}",
+                        body.name
+                    ));
+                }
+                continue;
+            }
+        };
+        match animation.schema.name {
+            "radius" => body.radius = value as f32,
+            "semimajor_axis" => body.elements.semimajor_axis = value,
+            "eccentricity" => body.elements.eccentricity = value,
+            "inclination" => body.elements.inclination = value,
+            "longitude_of_ascending_node" => {
+                body.elements.longitude_of_ascending_node = value
+            }
+            "argument_of_periapsis" => {
+                body.elements.argument_of_periapsis = value
+            }
+            "mean_anomaly_at_epoch" => {
+                body.elements.mean_anomaly_at_epoch = value
+            }
+            "orbit_period" => body.omega = angular_speed(value),
+            "rotation_period" => body.rotation_omega = angular_speed(value),
+            "precession_period" => body.precession_omega = angular_speed(value),
+            _ => (),
+        }
+    }
+    body.orbit_angle += dt * body.omega as f64;
+    if body.elements.eccentricity < 1. {
+        body.orbit_angle %= std::f64::consts::TAU;
+    }
+    let tau = std::f32::consts::TAU;
+    body.rotation_angle =
+        (body.rotation_angle + dt as f32 * body.rotation_omega) % tau;
+    body.precession_angle =
+        (body.precession_angle + dt as f32 * body.precession_omega) % tau;
+    for child in &mut body.children {
+        advance(child, t, dt);
+    }
+}
+
+/// Reports an error found while the scene is running.
+fn report(message: &str) {
+    #[cfg(target_arch = "wasm32")]
+    log::warn!("{message}");
+    #[cfg(not(target_arch = "wasm32"))]
+    eprintln!("{message}");
+}
+
+pub(crate) fn apply_transform(body: &mut AstroBody, parent: &Frame) {
+    let mean_anomaly = body.elements.mean_anomaly_at_epoch + body.orbit_angle;
+    let (frame, reference) = body_frame(
+        &body.elements,
+        mean_anomaly,
+        &body.orientation,
+        body.precession_angle,
+        parent,
+    );
+    let equator = Matrix4::from_translation(frame.position)
+        * Matrix4::from(frame.equator);
+
+    let revolution = equator
+        * Matrix4::from_angle_y(Rad(body.rotation_angle))
         * Matrix4::from_scale(body.radius)
         * Matrix4::from_angle_x(Deg(-90.));
 
@@ -292,20 +491,81 @@ pub(crate) fn apply_transform(
         Object::Color(model) => model.set_transformation(revolution),
         Object::Physical(model) => model.set_transformation(revolution),
     }
-    // println!("Applying transform to {}: {origin:?}", body.name);
+    for band in &mut body.ring_models {
+        band.set_transformation(equator * Matrix4::from_scale(body.radius));
+    }
     for child in &mut body.children {
-        apply_transform(child, &rotation, frame_time);
+        apply_transform(child, &frame);
     }
 
     if let Some(ref mut orbit) = body.orbit_model {
+        // A line of the other kind cannot be stretched into the orbit, so
+        // it is hidden while an animated eccentricity crosses 1.
+        let transform = if body.open_orbit == (1. <= body.elements.eccentricity)
+        {
+            orbit_transform(&body.elements)
+        } else {
+            Mat4::from_scale(0.)
+        };
         orbit.set_transformation(
-            parent_transform
-                * Mat4::from_scale(body.semimajor_axis)
-                * Mat4::from_angle_z(Deg(90.)),
+            Matrix4::from_translation(parent.position)
+                * Matrix4::from(reference)
+                * transform,
         );
     }
 }
 
+/// Returns a point of the reference frame of orbits in the frame of the
+/// scene, where the reference plane is the xz plane with y its north.
+fn to_render([x, y, z]: [f64; 3]) -> Vec3 {
+    vec3(x as f32, z as f32, -y as f32)
+}
+
+/// Returns the transformation that stretches the circle of [`ring`] into
+/// the ellipse of an orbit, with the parent body at a focus, or that scales
+/// the points of [`trajectory_points`] to an open trajectory.
+fn orbit_transform(elements: &Elements) -> Mat4 {
+    let a = elements.semimajor_axis as f32;
+    let e = elements.eccentricity as f32;
+    let orientation = Mat4::from(orbit_orientation(elements))
+        * Mat4::from_angle_y(Rad(elements.argument_of_periapsis as f32));
+    if 1. <= e {
+        // Open trajectories keep their periapsis distance in
+        // `semimajor_axis`.
+        return orientation * Mat4::from_scale(a);
+    }
+    let b = a * (1. - e * e).sqrt();
+    orientation
+        * Mat4::from_translation(vec3(-a * e, 0., 0.))
+        * Mat4::from_nonuniform_scale(a, a, b)
+        * Mat4::from_angle_z(Deg(90.))
+}
+
+/// How far from the parent open trajectories are drawn, in periapsis
+/// distances.
+const TRAJECTORY_LENGTH: f64 = 30.;
+
+/// Returns points along the open trajectory of eccentricity `eccentricity`
+/// with a periapsis distance of 1, in the frame of the scene, up to
+/// [`TRAJECTORY_LENGTH`] on both sides of the periapsis.
+fn trajectory_points(eccentricity: f64) -> Vec<Vec3> {
+    let unit = Elements {
+        semimajor_axis: 1.,
+        eccentricity,
+        inclination: 0.,
+        longitude_of_ascending_node: 0.,
+        argument_of_periapsis: 0.,
+        mean_anomaly_at_epoch: 0.,
+    };
+    let end = unit.anomaly_at_distance(TRAJECTORY_LENGTH);
+    (-64..=64)
+        .map(|i| {
+            let [x, y] = unit.in_plane(end * i as f64 / 64.);
+            to_render([x, y, 0.])
+        })
+        .collect()
+}
+
 ///
 /// Returns a sphere mesh with radius 1 and center in `(0, 0, 0)` with UV mapping as longitude and latitude.
 ///
@@ -413,7 +673,7 @@ fn ring(angle_subdivisions: u32, ring_thickness: f32) -> TriMesh {
         indices.push((j) as u16);
         indices
             .push((angle_subdivisions + (j + 1) % angle_subdivisions) as u16);
-        indices.push(((1) * angle_subdivisions + j) as u16);
+        indices.push((angle_subdivisions + j) as u16);
     }
     let offset = 2 * angle_subdivisions;
     for j in 0..angle_subdivisions {
@@ -437,3 +697,423 @@ fn ring(angle_subdivisions: u32, ring_thickness: f32) -> TriMesh {
     mesh.compute_normals();
     mesh
 }
+
+///
+/// Returns a line mesh through `points` in the xz plane, which unlike [`ring`] is not closed.
+/// It has "fake" thickness by a cross section with a shape of "+".
+///
+fn trajectory(points: &[Vec3], thickness: f32) -> TriMesh {
+    let n = points.len();
+    let mut positions = Vec::new();
+    let mut indices = Vec::new();
+    // Across the line within the plane, from the neighbouring points.
+    let across = (0..n).map(|j| {
+        let along = points[(j + 1).min(n - 1)] - points[j.saturating_sub(1)];
+        vec3(along.z, 0., -along.x).normalize()
+    });
+    let across: Vec<_> = across.collect();
+    for i in 0..2 {
+        for side in [-thickness, thickness] {
+            for (j, point) in points.iter().enumerate() {
+                let offset = if i == 0 { Vec3::unit_y() } else { across[j] };
+                positions.push(point + side * offset);
+            }
+        }
+        let offset = 2 * i * n;
+        for j in 0..n - 1 {
+            let (a, b) = (offset + j, offset + n + j);
+            indices.extend([a, a + 1, b + 1, a, b + 1, b].map(|k| k as u16));
+        }
+    }
+    let mut mesh = TriMesh {
+        positions: Positions::F32(positions),
+        indices: Indices::U16(indices),
+        ..Default::default()
+    };
+    mesh.compute_normals();
+    mesh
+}
+
+///
+/// Returns a flat ring mesh in the xz plane, between radii `inner` and `outer` around `(0, 0, 0)`.
+///
+fn annulus(angle_subdivisions: u32, inner: f32, outer: f32) -> TriMesh {
+    let mut positions = Vec::new();
+    let mut indices = Vec::new();
+    for j in 0..angle_subdivisions {
+        let angle =
+            2.0 * std::f32::consts::PI * j as f32 / angle_subdivisions as f32;
+        let (sin, cos) = angle.sin_cos();
+        positions.push(Vec3::new(inner * cos, 0., inner * sin));
+        positions.push(Vec3::new(outer * cos, 0., outer * sin));
+
+        let j1 = (j + 1) % angle_subdivisions;
+        indices.push((2 * j) as u16);
+        indices.push((2 * j1) as u16);
+        indices.push((2 * j + 1) as u16);
+
+        indices.push((2 * j + 1) as u16);
+        indices.push((2 * j1) as u16);
+        indices.push((2 * j1 + 1) as u16);
+    }
+    let normals = vec![Vec3::unit_y(); positions.len()];
+    TriMesh {
+        positions: Positions::F32(positions),
+        indices: Indices::U16(indices),
+        normals: Some(normals),
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::kepler::eccentric_anomaly;
+
+    #[test]
+    fn test_orbit_transform() {
+        let elements = Elements {
+            semimajor_axis: 1.5,
+            eccentricity: 0.6,
+            inclination: 0.4,
+            longitude_of_ascending_node: 2.,
+            argument_of_periapsis: -1.,
+            mean_anomaly_at_epoch: 0.,
+        };
+        let transform = orbit_transform(&elements);
+        for mean_anomaly in [0., 0.5, 2., 3., 4.5] {
+            // The point of the circle of `ring` at `π + E`.
+            let anomaly = eccentric_anomaly(mean_anomaly, 0.6) as f32;
+            let angle = std::f32::consts::PI + anomaly;
+            let on_ring = transform.transform_point(Point3::new(
+                0.,
+                angle.cos(),
+                angle.sin(),
+            ));
+            let position = to_render(elements.position(mean_anomaly));
+            assert!(
+                (on_ring.to_vec() - position).magnitude() < 1e-5,
+                "{on_ring:?} != {position:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_open_orbit_transform() {
+        for eccentricity in [1., 1.2, 5.] {
+            let elements = Elements {
+                semimajor_axis: 0.25,
+                eccentricity,
+                inclination: 2.1,
+                longitude_of_ascending_node: 0.4,
+                argument_of_periapsis: 1.,
+                mean_anomaly_at_epoch: 0.,
+            };
+            let transform = orbit_transform(&elements);
+            let points = trajectory_points(eccentricity);
+            // The middle point is the periapsis, and the ends are as far
+            // from the parent as drawn.
+            assert_eq!(points.len() % 2, 1);
+            let periapsis = transform
+                .transform_point(Point3::from_vec(points[points.len() / 2]));
+            let position = to_render(elements.position(0.));
+            assert!((periapsis.to_vec() - position).magnitude() < 1e-5);
+            for end in [points[0], points[points.len() - 1]] {
+                let distance = end.magnitude() as f64;
+                assert!((distance - TRAJECTORY_LENGTH).abs() < 1e-4);
+            }
+            // The body moves along the line on either side.
+            let line: Vec<_> = points
+                .iter()
+                .map(|&point| {
+                    transform.transform_point(Point3::from_vec(point)).to_vec()
+                })
+                .collect();
+            for mean_anomaly in [-3., 0.5, 2.] {
+                let position = to_render(elements.position(mean_anomaly));
+                let nearest = line
+                    .windows(2)
+                    .map(|segment| {
+                        let along = segment[1] - segment[0];
+                        let s = (position - segment[0]).dot(along)
+                            / along.magnitude2();
+                        let closest = segment[0] + s.clamp(0., 1.) * along;
+                        (position - closest).magnitude()
+                    })
+                    .fold(f32::INFINITY, f32::min);
+                assert!(nearest < 1e-3, "e = {eccentricity}: {nearest}");
+            }
+        }
+        let mesh = trajectory(&trajectory_points(1.5), 0.005);
+        assert_eq!(mesh.positions.len(), 4 * 129);
+        assert_eq!(mesh.indices.len(), Some(2 * 6 * 128));
+    }
+
+    fn elements(inclination: f64, node: f64) -> Elements {
+        Elements {
+            semimajor_axis: 1.,
+            eccentricity: 0.,
+            inclination,
+            longitude_of_ascending_node: node,
+            argument_of_periapsis: 0.,
+            mean_anomaly_at_epoch: 0.,
+        }
+    }
+
+    fn close(a: Vec3, b: Vec3, degrees: f32) -> bool {
+        a.angle(b).0.to_degrees() < degrees
+    }
+
+    /// Returns the direction at a right ascension and declination in
+    /// degrees, in the frame of the scene.
+    fn radec(ra: f64, dec: f64) -> Vec3 {
+        to_render(equatorial_to_ecliptic(ra.to_radians(), dec.to_radians()))
+    }
+
+    #[test]
+    fn test_axis() {
+        use std::f64::consts::FRAC_PI_2;
+        let earth = BodySpec {
+            obliquity: (84_381.406f64 / 3600.).to_radians(),
+            ..BodySpec::default()
+        };
+        let ecliptic = elements(0., 0.);
+        // The obliquity leans the axis as far as the pole of the equator.
+        let pole = BodySpec {
+            spin_axis_declination: Some(FRAC_PI_2),
+            ..BodySpec::default()
+        };
+        let axis = axis(&earth, &ecliptic);
+        assert!(close(axis, super::axis(&pole, &ecliptic), 1e-3));
+        assert!(close(axis, radec(0., 90.), 1e-3));
+        // A vector overrides the angles.
+        let upright = BodySpec {
+            spin_axis: Some([0., 2., 0.]),
+            ..pole.clone()
+        };
+        assert_eq!(super::axis(&upright, &ecliptic), Vec3::unit_y());
+
+        // The obliquity is measured from the normal of an inclined orbit.
+        let inclined = elements(0.3, 1.);
+        let normal = orbit_orientation(&inclined) * Vec3::unit_y();
+        let angle = super::axis(&earth, &inclined).angle(normal).0 as f64;
+        assert!((angle - earth.obliquity).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_pole_orientation() {
+        // The equator of a plane is its orbit with the same elements.
+        for (inclination, node) in [(0.3, 1.), (2., -2.5), (0., 0.)] {
+            let orbit = orbit_orientation(&elements(inclination, node));
+            let pole = pole_orientation(orbit * Vec3::unit_y());
+            for v in [Vec3::unit_x(), Vec3::unit_y(), Vec3::unit_z()] {
+                assert!((pole * v - orbit * v).magnitude() < 1e-5);
+            }
+        }
+        assert_eq!(pole_orientation(Vec3::unit_y()), Quaternion::one());
+    }
+
+    #[test]
+    fn test_precession() {
+        let earth = Orientation {
+            axis: to_render(equatorial_to_ecliptic(0., 1.5)),
+            axis_from_obliquity: false,
+            reference_plane: None,
+            laplace_radius: None,
+            tilt_children: false,
+        };
+        let ecliptic = elements(0., 0.);
+        let scene = Frame::scene();
+        let axis = |angle| {
+            let (frame, _) = body_frame(&ecliptic, 0., &earth, angle, &scene);
+            frame.equator * Vec3::unit_y()
+        };
+        // The axis keeps its angle to the normal of the orbit, and turns
+        // westward, clockwise seen from the north.
+        for angle in [0., 1., 3.] {
+            let lean = axis(angle).angle(Vec3::unit_y()).0;
+            assert!((lean - axis(0.).angle(Vec3::unit_y()).0).abs() < 1e-5);
+        }
+        assert!(axis(0.).cross(axis(0.1)).y < 0.);
+        assert!(close(axis(std::f32::consts::TAU), axis(0.), 1e-2));
+    }
+
+    /// Returns the frame of a body, with its orbit at `t = 0`.
+    fn frame(
+        parent: &Frame,
+        elements: &Elements,
+        orientation: &Orientation,
+    ) -> Frame {
+        body_frame(elements, 0., orientation, 0., parent).0
+    }
+
+    #[test]
+    fn test_reference_planes() {
+        let pole = |ra, dec| Orientation {
+            axis: radec(ra, dec),
+            axis_from_obliquity: false,
+            reference_plane: None,
+            laplace_radius: None,
+            tilt_children: false,
+        };
+        let moon = |reference_plane| Orientation {
+            reference_plane,
+            ..pole(0., 90.)
+        };
+        let inclined = elements(0.3, 1.);
+        let jupiter =
+            frame(&Frame::scene(), &elements(0.02, 1.75), &pole(268., 64.5));
+        let equator = jupiter.equator * Vec3::unit_y();
+
+        // Unset, the plane is the equator of a parent that tilts its
+        // children, and the ecliptic otherwise.
+        let tilted = Frame {
+            tilt_children: true,
+            ..jupiter
+        };
+        for (parent, plane) in [
+            (&tilted, ReferencePlane::ParentEquator),
+            (&jupiter, ReferencePlane::Ecliptic),
+        ] {
+            let unset = frame(parent, &inclined, &moon(None));
+            let set = frame(parent, &inclined, &moon(Some(plane)));
+            assert!((unset.position - set.position).magnitude() < 1e-9);
+        }
+        let ecliptic =
+            frame(&tilted, &inclined, &moon(Some(ReferencePlane::Ecliptic)));
+        let normal = orbit_orientation(&inclined) * Vec3::unit_y();
+        assert!(close(ecliptic.orbit * Vec3::unit_y(), normal, 1e-3));
+        // Without a Laplace radius the planes are the orbit of the parent,
+        // and with a large one its equator.
+        let plane = jupiter.plane(ReferencePlane::Laplace, 0.01);
+        assert!(close(
+            plane * Vec3::unit_y(),
+            jupiter.orbit * Vec3::unit_y(),
+            1e-3
+        ));
+        let flattened = Frame {
+            laplace_radius: Some(1.),
+            ..jupiter
+        };
+        let plane = flattened.plane(ReferencePlane::Laplace, 0.01);
+        assert!(close(plane * Vec3::unit_y(), equator, 1e-3));
+    }
+
+    /// A regression test of the Laplace planes of moons of the planets,
+    /// which are built from the pole and orbit of the planet and one
+    /// Laplace radius per planet, from its `J2` and, for Saturn, the orbit
+    /// of Titan (Tremaine et al. 2009). The moons have their mean elements
+    /// of JPL at J2000, but the positions are where these frames put them
+    /// when the test was written, not ephemerides, so it only catches
+    /// changes to the frames.
+    #[test]
+    fn test_laplace_positions_regression() {
+        let au = 1.495_978_707e8;
+        let deg = f64::to_radians;
+        struct Moon {
+            name: &'static str,
+            /// The elements in kilometers and degrees, with the node
+            /// measured from that of the plane on the ecliptic rather than
+            /// on the equator of the ICRF, as JPL does.
+            elements: [f64; 6],
+            /// The position from the planet, in kilometers in the frame of
+            /// the ecliptic of J2000.
+            position: [f64; 3],
+        }
+        let planet = |inclination, node, ra, dec, laplace_radius| {
+            let orbit = Elements {
+                semimajor_axis: 0.,
+                ..elements(deg(inclination), deg(node))
+            };
+            let orientation = Orientation {
+                axis: radec(ra, dec),
+                axis_from_obliquity: false,
+                reference_plane: None,
+                laplace_radius: Some(laplace_radius),
+                tilt_children: false,
+            };
+            frame(&Frame::scene(), &orbit, &orientation)
+        };
+        // The pole of the Earth and the ecliptic of J2000; the poles of
+        // the IAU at J2000 and the mean orbits of Saturn and Neptune.
+        let earth = planet(0., 0., 0., 90., 0.000_358);
+        let saturn = planet(2.485, 113.665, 40.589, 83.537, 0.0192);
+        let neptune = planet(1.770, 131.784, 299.334, 42.950, 0.010_63);
+        let systems = [
+            (
+                earth,
+                // Far beyond the Laplace radius the plane is the ecliptic,
+                // tilted a hair towards the equator about the September
+                // equinox, which is its ascending node.
+                Moon {
+                    name: "Moon",
+                    elements: [384_400., 0.0554, 5.16, 305.08, 318.15, 135.27],
+                    position: [-292_454., -270_672., 35_660.],
+                },
+            ),
+            (
+                saturn,
+                // The plane of Titan is within half a degree of the equator.
+                Moon {
+                    name: "Titan",
+                    elements: [
+                        1_221_870., 0.0288, 0.306, 344.072, 180.532, 163.31,
+                    ],
+                    position: [-951_877., 759_750., -305_593.],
+                },
+            ),
+            (
+                saturn,
+                // The plane of Iapetus is 15° from the equator.
+                Moon {
+                    name: "Iapetus",
+                    elements: [
+                        3_560_820., 0.0286, 8.298, 288.102, 271.606, 201.789,
+                    ],
+                    position: [-3_265_673., -1_325_741., 970_796.],
+                },
+            ),
+            (
+                neptune,
+                // Triton orbits backwards, 23° from its plane.
+                Moon {
+                    name: "Triton",
+                    elements: [
+                        354_759., 0., 156.865, 153.068, 344.046, 264.775,
+                    ],
+                    position: [155_519., -167_629., -271_234.],
+                },
+            ),
+        ];
+        for (planet, moon) in systems {
+            let [a, e, i, node, w, m] = moon.elements;
+            let elements = Elements {
+                semimajor_axis: a / au,
+                eccentricity: e,
+                inclination: deg(i),
+                longitude_of_ascending_node: deg(node),
+                argument_of_periapsis: deg(w),
+                mean_anomaly_at_epoch: deg(m),
+            };
+            let orientation = Orientation {
+                axis: Vec3::unit_y(),
+                axis_from_obliquity: true,
+                reference_plane: Some(ReferencePlane::Laplace),
+                laplace_radius: None,
+                tilt_children: false,
+            };
+            let (frame, _) = body_frame(
+                &elements,
+                elements.mean_anomaly_at_epoch,
+                &orientation,
+                0.,
+                &planet,
+            );
+            let expected = to_render(moon.position.map(|x| x / au));
+            let error = frame.position.angle(expected).0.to_degrees();
+            assert!(error < 1e-3, "{}: {error}°", moon.name);
+            let distance = frame.position.magnitude() / expected.magnitude();
+            assert!((distance - 1.).abs() < 1e-5, "{}", moon.name);
+        }
+    }
+}
@@ -0,0 +1,85 @@
+//! The commands of the `rusty-space` binary.
+
+use crate::{
+    diagnostics::Diagnostics,
+    format::format_source,
+    functions::FunctionRegistry,
+    interchange::{load_scene_file, serialize, SceneFormat},
+    lsp,
+    run::run,
+    schema::{reference, BODY_PROPERTIES},
+};
+
+/// `rusty-space fmt [--check] FILE...` rewrites scene files in the
+/// canonical layout. With `--check`, it lists the files that would change
+/// instead and fails if there are any.
+fn fmt(args: &[String]) -> Result<(), Box<dyn std::error::Error + 'static>> {
+    let check = args.iter().any(|arg| arg == "--check");
+    let paths: Vec<_> = args.iter().filter(|arg| *arg != "--check").collect();
+    if paths.is_empty() {
+        return Err("usage: rusty-space fmt [--check] FILE...".into());
+    }
+    let mut failed = 0;
+    for path in paths {
+        let source = std::fs::read_to_string(path)
+            .map_err(|e| format!("cannot read `{path}`: {e}"))?;
+        let mut diagnostics = Diagnostics::new(path.as_str(), &source);
+        let Some(formatted) = format_source(&source, &mut diagnostics) else {
+            diagnostics.emit();
+            failed += 1;
+            continue;
+        };
+        if formatted == source {
+            continue;
+        }
+        if check {
+            println!("{path} is not formatted");
+            failed += 1;
+        } else {
+            std::fs::write(path, formatted)
+                .map_err(|e| format!("cannot write `{path}`: {e}"))?;
+        }
+    }
+    match failed {
+        0 => Ok(()),
+        1 => Err("1 file was not formatted".into()),
+        n => Err(format!("{n} files were not formatted").into()),
+    }
+}
+
+/// `rusty-space convert INPUT OUTPUT` loads a scene and writes it in the
+/// format given by the extension of `OUTPUT`.
+async fn convert(
+    args: &[String],
+) -> Result<(), Box<dyn std::error::Error + 'static>> {
+    let [input, output] = args else {
+        return Err("usage: rusty-space convert INPUT OUTPUT".into());
+    };
+    let scene = load_scene_file(input).await?;
+    let text = serialize(&scene, SceneFormat::of(output))?;
+    std::fs::write(output, text)
+        .map_err(|e| format!("cannot write `{output}`: {e}"))?;
+    Ok(())
+}
+
+/// Runs the command in `args`, without the name of the binary: `fmt`,
+/// `convert`, `lsp` or `properties`, or else shows the scene file given,
+/// `assets/sol.txt` by default.
+pub async fn run_cli(
+    args: &[String],
+) -> Result<(), Box<dyn std::error::Error + 'static>> {
+    match args.first().map(String::as_str) {
+        Some("fmt") => return fmt(&args[1..]),
+        Some("convert") => return convert(&args[1..]).await,
+        Some("lsp") => return lsp::serve(),
+        Some("properties") => {
+            print!("{}", reference(BODY_PROPERTIES));
+            return Ok(());
+        }
+        _ => (),
+    }
+    let file_name = args.first().map_or("assets/sol.txt", String::as_str);
+    let scene = load_scene_file(file_name).await?;
+    run(scene, FunctionRegistry::builtins()).await?;
+    Ok(())
+}
@@ -0,0 +1,103 @@
+use std::fmt::{self, Display};
+
+use serde::{Deserialize, Serialize};
+
+/// A color with 8 bits per channel. Scenes in JSON, TOML and RON write it
+/// as in the scene language, e.g. `"#ff8800"`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+    pub a: u8,
+}
+
+impl Color {
+    pub const fn rgba(r: u8, g: u8, b: u8, a: u8) -> Self {
+        Self { r, g, b, a }
+    }
+
+    /// Parses the hex digits of `#rgb`, `#rgba`, `#rrggbb` or `#rrggbbaa`,
+    /// without the `#`.
+    pub fn from_hex(hex: &str) -> Option<Self> {
+        if !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return None;
+        }
+        let digit = |i| u8::from_str_radix(&hex[i..i + 1], 16).unwrap();
+        let pair = |i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap();
+        match hex.len() {
+            3 | 4 => {
+                let channel = |i| digit(i) * 17;
+                let a = if hex.len() == 4 { channel(3) } else { 255 };
+                Some(Self::rgba(channel(0), channel(1), channel(2), a))
+            }
+            6 | 8 => {
+                let a = if hex.len() == 8 { pair(6) } else { 255 };
+                Some(Self::rgba(pair(0), pair(2), pair(4), a))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Writes `#rrggbb`, or `#rrggbbaa` if the color is not opaque.
+impl Display for Color {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{:02x}{:02x}{:02x}", self.r, self.g, self.b)?;
+        if self.a != 255 {
+            write!(f, "{:02x}", self.a)?;
+        }
+        Ok(())
+    }
+}
+
+impl TryFrom<String> for Color {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.strip_prefix('#')
+            .and_then(Self::from_hex)
+            .ok_or_else(|| format!("invalid color `{s}`"))
+    }
+}
+
+impl From<Color> for String {
+    fn from(color: Color) -> Self {
+        color.to_string()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_hex() {
+        assert_eq!(
+            Color::from_hex("ff8800"),
+            Some(Color::rgba(255, 136, 0, 255))
+        );
+        assert_eq!(Color::from_hex("f80"), Some(Color::rgba(255, 136, 0, 255)));
+        assert_eq!(
+            Color::from_hex("f808"),
+            Some(Color::rgba(255, 136, 0, 136))
+        );
+        assert_eq!(
+            Color::from_hex("00ff00c8"),
+            Some(Color::rgba(0, 255, 0, 200))
+        );
+        assert_eq!(
+            Color::from_hex("ff88"),
+            Some(Color::rgba(255, 255, 136, 136))
+        );
+        assert_eq!(Color::from_hex("ff880"), None);
+        assert_eq!(Color::from_hex("gg8800"), None);
+        assert_eq!(Color::rgba(255, 136, 0, 255).to_string(), "#ff8800");
+        assert_eq!(Color::rgba(0, 255, 0, 200).to_string(), "#00ff00c8");
+        assert_eq!(
+            Color::try_from("ff8800".to_owned()),
+            Err("invalid color `ff8800`".to_owned())
+        );
+    }
+}
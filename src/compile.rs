@@ -0,0 +1,1059 @@
+use std::collections::HashMap;
+
+use crate::{
+    functions::{Arity, NativeFunction},
+    parser::{
+        additive, boolean, call_native, compare, equals, eval, multiplicative,
+        negate, pieces, power_of, push_value, Env, EvalError, EvalResult,
+        Expression, Function, Operand, Piece, Value, MAX_CALL_DEPTH,
+    },
+    units::Dim,
+};
+
+/// An expression compiled once into a tree of closures, for evaluating it
+/// many times with different inputs. Names are resolved when compiling:
+/// inputs are read from slots, and anything that depends on no input is
+/// evaluated right away. The results are the same as those of [`eval`]
+/// with the inputs as variables.
+pub(crate) struct Compiled<'src> {
+    root: Node<'src>,
+    /// The bodies of the user-defined functions the expression calls.
+    functions: Vec<Node<'src>>,
+    inputs: usize,
+}
+
+impl<'src> Compiled<'src> {
+    /// Evaluates the expression with the values of the inputs, in the
+    /// order their names were given to [`compile`].
+    pub fn run(&self, inputs: &[Value]) -> EvalResult<'src> {
+        assert_eq!(inputs.len(), self.inputs, "wrong number of inputs");
+        self.root.run(&Ctx {
+            inputs,
+            args: &[],
+            functions: &self.functions,
+            depth: 0,
+        })
+    }
+
+    /// Returns the value of an expression that depends on none of its
+    /// inputs.
+    #[cfg(test)]
+    pub fn constant(&self) -> Option<&EvalResult<'src>> {
+        match &self.root.kind {
+            Kind::Const(result) => Some(result),
+            _ => None,
+        }
+    }
+}
+
+/// Compiles `expr`, with the variables and functions of `env` and the
+/// inputs named in `inputs`, which shadow the variables.
+pub(crate) fn compile<'src>(
+    expr: &Expression<'src>,
+    env: &Env<'_, 'src>,
+    inputs: &[&str],
+) -> Compiled<'src> {
+    let mut compiler = Compiler {
+        env,
+        inputs,
+        params: &[],
+        functions: vec![],
+        indices: HashMap::new(),
+    };
+    let root = compiler.compile(expr);
+    Compiled {
+        root,
+        functions: compiler.functions.into_iter().flatten().collect(),
+        inputs: inputs.len(),
+    }
+}
+
+type Op<'src> =
+    Box<dyn Fn(&Ctx<'_, 'src>) -> EvalResult<'src> + Send + Sync + 'src>;
+
+/// A compiled expression node.
+struct Node<'src> {
+    /// The span of the expression, for errors.
+    span: Option<&'src str>,
+    kind: Kind<'src>,
+}
+
+enum Kind<'src> {
+    Const(EvalResult<'src>),
+    Input(usize),
+    /// An argument of the user-defined function the node is in.
+    Arg(usize),
+    Op(Op<'src>),
+}
+
+impl<'src> Node<'src> {
+    fn run(&self, ctx: &Ctx<'_, 'src>) -> EvalResult<'src> {
+        match &self.kind {
+            Kind::Const(result) => result.clone(),
+            Kind::Input(i) => Ok(ctx.inputs[*i].clone()),
+            Kind::Arg(i) => Ok(ctx.args[*i].clone()),
+            Kind::Op(op) => op(ctx),
+        }
+    }
+
+    fn is_const(&self) -> bool {
+        matches!(self.kind, Kind::Const(_))
+    }
+}
+
+/// What a compiled expression is evaluated with.
+#[derive(Clone, Copy)]
+struct Ctx<'c, 'src> {
+    inputs: &'c [Value],
+    args: &'c [Value],
+    functions: &'c [Node<'src>],
+    depth: usize,
+}
+
+impl<'src> Operand<'src> for (&Node<'src>, &Ctx<'_, 'src>) {
+    fn value(&self) -> EvalResult<'src> {
+        self.0.run(self.1)
+    }
+
+    fn span(&self) -> Option<&'src str> {
+        self.0.span
+    }
+}
+
+type Unary<'src> =
+    for<'n, 'c> fn((&'n Node<'src>, &'n Ctx<'c, 'src>)) -> EvalResult<'src>;
+
+type Binary<'src> = for<'n, 'c> fn(
+    (&'n Node<'src>, &'n Ctx<'c, 'src>),
+    (&'n Node<'src>, &'n Ctx<'c, 'src>),
+) -> EvalResult<'src>;
+
+/// A part of a string with `${...}` interpolations.
+enum Part<'src> {
+    Text(String),
+    Node(Node<'src>),
+    Invalid(EvalError<'src>),
+}
+
+struct Compiler<'c, 'a, 'src> {
+    env: &'c Env<'a, 'src>,
+    inputs: &'c [&'c str],
+    /// The parameters of the function whose body is being compiled.
+    params: &'a [&'src str],
+    /// The bodies of the functions called so far, which are `None` while
+    /// they are being compiled.
+    functions: Vec<Option<Node<'src>>>,
+    indices: HashMap<&'src str, usize>,
+}
+
+impl<'a, 'src> Compiler<'_, 'a, 'src> {
+    fn compile(&mut self, expr: &Expression<'src>) -> Node<'src> {
+        let kind = match expr {
+            Expression::Ident(id) if *id != "pi" => {
+                if let Some(i) = self.params.iter().position(|p| p == id) {
+                    Kind::Arg(i)
+                } else if let Some(i) = self.inputs.iter().position(|n| n == id)
+                {
+                    Kind::Input(i)
+                } else {
+                    Kind::Const(eval(expr, self.env))
+                }
+            }
+            Expression::StrLiteral(s) if s.contains("${") => {
+                self.interpolation(s)
+            }
+            Expression::FnInvoke(name, args) if *name != "if" => {
+                let natives = self.env.natives();
+                if let Some(native) = natives.get(name) {
+                    self.native(name, native, args, expr.span())
+                } else if let Some(function) = self.env.function(name) {
+                    self.call(name, function, args)
+                } else {
+                    Kind::Const(eval(expr, self.env))
+                }
+            }
+            Expression::Add(lhs, rhs) => {
+                self.binary(lhs, rhs, |l, r| additive(l, r, |a, b| a + b))
+            }
+            Expression::Sub(lhs, rhs) => {
+                self.binary(lhs, rhs, |l, r| additive(l, r, |a, b| a - b))
+            }
+            Expression::Mul(lhs, rhs) => self.binary(lhs, rhs, |l, r| {
+                multiplicative(l, r, |a, b| a * b, Dim::checked_mul)
+            }),
+            Expression::Div(lhs, rhs) => self.binary(lhs, rhs, |l, r| {
+                multiplicative(l, r, |a, b| a / b, Dim::checked_div)
+            }),
+            Expression::Mod(lhs, rhs) => {
+                self.binary(lhs, rhs, |l, r| additive(l, r, |a, b| a % b))
+            }
+            Expression::Pow(lhs, rhs) => {
+                self.binary(lhs, rhs, |l, r| power_of(l, r))
+            }
+            Expression::Neg(ex) => self.unary(ex, |operand| negate(operand)),
+            Expression::Lt(lhs, rhs) => {
+                self.binary(lhs, rhs, |l, r| compare(l, r, f64::lt))
+            }
+            Expression::Le(lhs, rhs) => {
+                self.binary(lhs, rhs, |l, r| compare(l, r, f64::le))
+            }
+            Expression::Gt(lhs, rhs) => {
+                self.binary(lhs, rhs, |l, r| compare(l, r, f64::gt))
+            }
+            Expression::Ge(lhs, rhs) => {
+                self.binary(lhs, rhs, |l, r| compare(l, r, f64::ge))
+            }
+            Expression::Eq(lhs, rhs) => {
+                self.binary(lhs, rhs, |l, r| Ok(Value::Bool(equals(l, r)?)))
+            }
+            Expression::Ne(lhs, rhs) => {
+                self.binary(lhs, rhs, |l, r| Ok(Value::Bool(!equals(l, r)?)))
+            }
+            Expression::And(lhs, rhs) => self.binary(lhs, rhs, |l, r| {
+                Ok(Value::Bool(boolean(l)? && boolean(r)?))
+            }),
+            Expression::Or(lhs, rhs) => self.binary(lhs, rhs, |l, r| {
+                Ok(Value::Bool(boolean(l)? || boolean(r)?))
+            }),
+            Expression::Not(ex) => {
+                self.unary(ex, |operand| Ok(Value::Bool(!boolean(operand)?)))
+            }
+            Expression::Cond(cond, then, els) => self.cond(cond, then, els),
+            Expression::List(items) => self.list(items),
+            // Literals, properties and misused `if`.
+            _ => Kind::Const(eval(expr, self.env)),
+        };
+        Node {
+            span: expr.span(),
+            kind,
+        }
+    }
+
+    /// Returns an operation, which is evaluated right away if all its
+    /// operands are constants.
+    fn fold(&self, constant: bool, op: Op<'src>) -> Kind<'src> {
+        if !constant {
+            return Kind::Op(op);
+        }
+        Kind::Const(op(&Ctx {
+            inputs: &[],
+            args: &[],
+            functions: &[],
+            depth: 0,
+        }))
+    }
+
+    fn unary(&mut self, ex: &Expression<'src>, f: Unary<'src>) -> Kind<'src> {
+        let ex = self.compile(ex);
+        let constant = ex.is_const();
+        self.fold(constant, Box::new(move |ctx| f((&ex, ctx))))
+    }
+
+    fn binary(
+        &mut self,
+        lhs: &Expression<'src>,
+        rhs: &Expression<'src>,
+        f: Binary<'src>,
+    ) -> Kind<'src> {
+        let lhs = self.compile(lhs);
+        let rhs = self.compile(rhs);
+        let constant = lhs.is_const() && rhs.is_const();
+        self.fold(constant, Box::new(move |ctx| f((&lhs, ctx), (&rhs, ctx))))
+    }
+
+    fn cond(
+        &mut self,
+        cond: &Expression<'src>,
+        then: &Expression<'src>,
+        els: &Expression<'src>,
+    ) -> Kind<'src> {
+        let cond = self.compile(cond);
+        // Only the branch that is taken is compiled.
+        if let Kind::Const(Ok(Value::Bool(b))) = cond.kind {
+            return self.compile(if b { then } else { els }).kind;
+        }
+        let then = self.compile(then);
+        let els = self.compile(els);
+        let constant = cond.is_const() && then.is_const() && els.is_const();
+        let op: Op = Box::new(move |ctx| {
+            if boolean((&cond, ctx))? {
+                then.run(ctx)
+            } else {
+                els.run(ctx)
+            }
+        });
+        self.fold(constant, op)
+    }
+
+    fn list(&mut self, items: &[Expression<'src>]) -> Kind<'src> {
+        let items: Vec<_> =
+            items.iter().map(|item| self.compile(item)).collect();
+        let constant = items.iter().all(Node::is_const);
+        let op: Op = Box::new(move |ctx| {
+            let values = items.iter().map(|item| item.run(ctx));
+            Ok(Value::List(values.collect::<Result<_, _>>()?))
+        });
+        self.fold(constant, op)
+    }
+
+    fn interpolation(&mut self, s: &'src str) -> Kind<'src> {
+        let parts: Vec<_> = pieces(s)
+            .into_iter()
+            .map(|piece| match piece {
+                Piece::Text(text) => Part::Text(text),
+                Piece::Expr(ex) => Part::Node(self.compile(&ex)),
+                Piece::Invalid(error) => Part::Invalid(error),
+            })
+            .collect();
+        let constant = parts.iter().all(|part| match part {
+            Part::Node(node) => node.is_const(),
+            _ => true,
+        });
+        let op: Op = Box::new(move |ctx| {
+            let mut out = String::new();
+            for part in &parts {
+                match part {
+                    Part::Text(text) => out += text,
+                    Part::Node(node) => push_value(&mut out, node.run(ctx)?),
+                    Part::Invalid(error) => return Err(error.clone()),
+                }
+            }
+            Ok(Value::Str(out))
+        });
+        self.fold(constant, op)
+    }
+
+    /// Compiles a call to a function implemented in Rust, which is assumed
+    /// to always give the same result for the same arguments.
+    fn native(
+        &mut self,
+        name: &'src str,
+        native: &NativeFunction,
+        args: &[Expression<'src>],
+        span: Option<&'src str>,
+    ) -> Kind<'src> {
+        if !native.arity.accepts(args.len()) {
+            return Kind::Const(Err(EvalError::WrongArity {
+                name,
+                expected: native.arity,
+                found: args.len(),
+            }));
+        }
+        let args: Vec<_> = args.iter().map(|arg| self.compile(arg)).collect();
+        let constant = args.iter().all(Node::is_const);
+        let native = native.clone();
+        let op: Op = Box::new(move |ctx| {
+            call_native(&native, args.iter().map(|arg| (arg, ctx)), span)
+        });
+        self.fold(constant, op)
+    }
+
+    /// Compiles a call to a user-defined function, whose body is compiled
+    /// once however many times it is called.
+    fn call(
+        &mut self,
+        name: &'src str,
+        function: &'a Function<'src>,
+        args: &[Expression<'src>],
+    ) -> Kind<'src> {
+        if args.len() != function.params.len() {
+            return Kind::Const(Err(EvalError::WrongArity {
+                name,
+                expected: Arity::exact(function.params.len()),
+                found: args.len(),
+            }));
+        }
+        let index = self.function(name, function);
+        let args: Vec<_> = args.iter().map(|arg| self.compile(arg)).collect();
+        Kind::Op(Box::new(move |ctx| {
+            if MAX_CALL_DEPTH <= ctx.depth {
+                return Err(EvalError::RecursionLimit(name));
+            }
+            let values = args
+                .iter()
+                .map(|arg| arg.run(ctx))
+                .collect::<Result<Vec<_>, _>>()?;
+            ctx.functions[index].run(&Ctx {
+                args: &values,
+                depth: ctx.depth + 1,
+                ..*ctx
+            })
+        }))
+    }
+
+    /// Returns the index of the compiled body of a function, compiling it
+    /// if it has not been yet.
+    fn function(
+        &mut self,
+        name: &'src str,
+        function: &'a Function<'src>,
+    ) -> usize {
+        if let Some(&index) = self.indices.get(name) {
+            return index;
+        }
+        let index = self.functions.len();
+        self.functions.push(None);
+        self.indices.insert(name, index);
+        let params = std::mem::replace(&mut self.params, &function.params);
+        let body = self.compile(&function.body);
+        self.params = params;
+        self.functions[index] = Some(body);
+        index
+    }
+}
+
+/// Rewrites `expr` into one that depends only on `inputs`, so it can be
+/// kept and evaluated apart from `env`: user-defined functions are inlined
+/// and everything that does not depend on an input is replaced by its
+/// value. Returns `None` if nothing in `expr` depends on an input, in which
+/// case it is evaluated as usual.
+pub(crate) fn resolve<'src>(
+    expr: &Expression<'src>,
+    env: &Env<'_, 'src>,
+    inputs: &[&str],
+) -> Result<Option<Expression<'src>>, EvalError<'src>> {
+    let resolver = Resolver { inputs };
+    let frame = Frame {
+        env: *env,
+        params: &[],
+        varying: HashMap::new(),
+    };
+    if !resolver.depends(expr, &frame, &mut vec![]) {
+        return Ok(None);
+    }
+    resolver.resolve(expr, &frame).map(Some)
+}
+
+struct Resolver<'c> {
+    inputs: &'c [&'c str],
+}
+
+/// The function being inlined, or the expression itself at the top.
+struct Frame<'f, 'src> {
+    /// The environment with the arguments that do not depend on an input.
+    env: Env<'f, 'src>,
+    params: &'f [&'src str],
+    /// The resolved arguments that depend on an input.
+    varying: HashMap<&'src str, Expression<'src>>,
+}
+
+impl<'src> Frame<'_, 'src> {
+    /// Returns whether the name is a parameter whose argument depends on an
+    /// input, or `None` if it is not a parameter.
+    fn param(&self, name: &str) -> Option<bool> {
+        self.params
+            .contains(&name)
+            .then(|| self.varying.contains_key(name))
+    }
+}
+
+impl<'c> Resolver<'c> {
+    /// Returns whether `expr` depends on an input. `visited` has the
+    /// functions whose bodies have been checked.
+    fn depends<'src>(
+        &self,
+        expr: &Expression<'src>,
+        frame: &Frame<'_, 'src>,
+        visited: &mut Vec<&'src str>,
+    ) -> bool {
+        self.mentions(expr, &|name| frame.param(name), frame, visited)
+    }
+
+    fn mentions<'src>(
+        &self,
+        expr: &Expression<'src>,
+        param: &dyn Fn(&str) -> Option<bool>,
+        frame: &Frame<'_, 'src>,
+        visited: &mut Vec<&'src str>,
+    ) -> bool {
+        let mut any = |exprs: &[&Expression<'src>]| {
+            exprs
+                .iter()
+                .any(|ex| self.mentions(ex, param, frame, visited))
+        };
+        match expr {
+            Expression::Ident(id) => {
+                param(id).unwrap_or_else(|| self.inputs.contains(id))
+            }
+            Expression::StrLiteral(s) => pieces(s).iter().any(|piece| {
+                matches!(piece, Piece::Expr(ex)
+                    if self.mentions(ex, param, frame, visited))
+            }),
+            Expression::FnInvoke(name, args) => {
+                let args: Vec<_> = args.iter().collect();
+                if any(&args) {
+                    return true;
+                }
+                if frame.env.natives().contains(name) || visited.contains(name)
+                {
+                    return false;
+                }
+                let Some(function) = frame.env.function(name) else {
+                    return false;
+                };
+                visited.push(name);
+                // The arguments do not depend on an input, so only the
+                // names the body reads from the scopes can.
+                let params = &function.params;
+                let param =
+                    |name: &str| params.contains(&name).then_some(false);
+                self.mentions(&function.body, &param, frame, visited)
+            }
+            Expression::NumLiteral(_)
+            | Expression::Quantity(..)
+            | Expression::BoolLiteral(_)
+            | Expression::Color(_)
+            | Expression::Field(..) => false,
+            Expression::List(items) => items
+                .iter()
+                .any(|ex| self.mentions(ex, param, frame, visited)),
+            Expression::Neg(ex) | Expression::Not(ex) => any(&[ex]),
+            Expression::Add(lhs, rhs)
+            | Expression::Sub(lhs, rhs)
+            | Expression::Mul(lhs, rhs)
+            | Expression::Div(lhs, rhs)
+            | Expression::Mod(lhs, rhs)
+            | Expression::Pow(lhs, rhs)
+            | Expression::Lt(lhs, rhs)
+            | Expression::Le(lhs, rhs)
+            | Expression::Gt(lhs, rhs)
+            | Expression::Ge(lhs, rhs)
+            | Expression::Eq(lhs, rhs)
+            | Expression::Ne(lhs, rhs)
+            | Expression::And(lhs, rhs)
+            | Expression::Or(lhs, rhs) => any(&[lhs, rhs]),
+            Expression::Cond(cond, then, els) => any(&[cond, then, els]),
+        }
+    }
+
+    fn resolve<'src>(
+        &self,
+        expr: &Expression<'src>,
+        frame: &Frame<'_, 'src>,
+    ) -> Result<Expression<'src>, EvalError<'src>> {
+        if !self.depends(expr, frame, &mut vec![]) {
+            return match expr {
+                // Literals are kept as written.
+                Expression::NumLiteral(_)
+                | Expression::Quantity(..)
+                | Expression::BoolLiteral(_)
+                | Expression::Color(_) => Ok(expr.clone()),
+                Expression::StrLiteral(s) if !s.contains("${") => {
+                    Ok(expr.clone())
+                }
+                _ => literal(eval(expr, &frame.env)?, expr.span()),
+            };
+        }
+        let resolve = |ex: &Expression<'src>| self.resolve(ex, frame);
+        let binary =
+            |lhs: &Expression<'src>,
+             rhs: &Expression<'src>,
+             op: fn(Box<_>, Box<_>) -> Expression<'src>| {
+                Ok(op(Box::new(resolve(lhs)?), Box::new(resolve(rhs)?)))
+            };
+        match expr {
+            Expression::Ident(id) => {
+                Ok(frame.varying.get(id).cloned().unwrap_or(expr.clone()))
+            }
+            Expression::StrLiteral(_) => {
+                Err(EvalError::VaryingString { at: expr.span() })
+            }
+            Expression::FnInvoke(name, args) => {
+                if let Some(native) = frame.env.natives().get(name) {
+                    if !native.arity.accepts(args.len()) {
+                        return Err(EvalError::WrongArity {
+                            name,
+                            expected: native.arity,
+                            found: args.len(),
+                        });
+                    }
+                    let args = args.iter().map(resolve);
+                    return Ok(Expression::FnInvoke(
+                        name,
+                        args.collect::<Result<_, _>>()?,
+                    ));
+                }
+                self.inline(name, args, frame)
+            }
+            Expression::Add(lhs, rhs) => binary(lhs, rhs, Expression::Add),
+            Expression::Sub(lhs, rhs) => binary(lhs, rhs, Expression::Sub),
+            Expression::Mul(lhs, rhs) => binary(lhs, rhs, Expression::Mul),
+            Expression::Div(lhs, rhs) => binary(lhs, rhs, Expression::Div),
+            Expression::Mod(lhs, rhs) => binary(lhs, rhs, Expression::Mod),
+            Expression::Pow(lhs, rhs) => binary(lhs, rhs, Expression::Pow),
+            Expression::Lt(lhs, rhs) => binary(lhs, rhs, Expression::Lt),
+            Expression::Le(lhs, rhs) => binary(lhs, rhs, Expression::Le),
+            Expression::Gt(lhs, rhs) => binary(lhs, rhs, Expression::Gt),
+            Expression::Ge(lhs, rhs) => binary(lhs, rhs, Expression::Ge),
+            Expression::Eq(lhs, rhs) => binary(lhs, rhs, Expression::Eq),
+            Expression::Ne(lhs, rhs) => binary(lhs, rhs, Expression::Ne),
+            Expression::And(lhs, rhs) => binary(lhs, rhs, Expression::And),
+            Expression::Or(lhs, rhs) => binary(lhs, rhs, Expression::Or),
+            Expression::List(items) => Ok(Expression::List(
+                items.iter().map(resolve).collect::<Result<_, _>>()?,
+            )),
+            Expression::Neg(ex) => Ok(Expression::Neg(Box::new(resolve(ex)?))),
+            Expression::Not(ex) => Ok(Expression::Not(Box::new(resolve(ex)?))),
+            Expression::Cond(cond, then, els) => {
+                // Only the branch that is taken is kept.
+                if !self.depends(cond, frame, &mut vec![]) {
+                    let b = boolean((&**cond, &frame.env))?;
+                    return resolve(if b { then } else { els });
+                }
+                Ok(Expression::Cond(
+                    Box::new(resolve(cond)?),
+                    Box::new(resolve(then)?),
+                    Box::new(resolve(els)?),
+                ))
+            }
+            // Nothing else can depend on an input.
+            _ => unreachable!(),
+        }
+    }
+
+    /// Replaces a call to a user-defined function by its body, with the
+    /// arguments that depend on an input substituted for the parameters.
+    fn inline<'src>(
+        &self,
+        name: &'src str,
+        args: &[Expression<'src>],
+        frame: &Frame<'_, 'src>,
+    ) -> Result<Expression<'src>, EvalError<'src>> {
+        let Some(function) = frame.env.function(name) else {
+            return Err(EvalError::UnknownFunction(name));
+        };
+        if args.len() != function.params.len() {
+            return Err(EvalError::WrongArity {
+                name,
+                expected: Arity::exact(function.params.len()),
+                found: args.len(),
+            });
+        }
+        // Inlining ends where evaluating would.
+        if MAX_CALL_DEPTH <= frame.env.depth() {
+            return Err(EvalError::RecursionLimit(name));
+        }
+        let mut values = HashMap::new();
+        let mut varying = HashMap::new();
+        for (param, arg) in function.params.iter().zip(args) {
+            if self.depends(arg, frame, &mut vec![]) {
+                varying.insert(*param, self.resolve(arg, frame)?);
+            } else {
+                values.insert(*param, eval(arg, &frame.env)?);
+            }
+        }
+        let frame = Frame {
+            env: frame.env.with_args(&values),
+            params: &function.params,
+            varying,
+        };
+        self.resolve(&function.body, &frame)
+    }
+}
+
+/// Returns an expression that evaluates to `value`.
+pub(crate) fn literal<'src>(
+    value: Value,
+    at: Option<&'src str>,
+) -> Result<Expression<'src>, EvalError<'src>> {
+    let (n, dim) = match value {
+        Value::Num(n) => (n, Dim::NONE),
+        Value::Quantity(n, dim) => (n, dim),
+        Value::Bool(b) => return Ok(Expression::BoolLiteral(b)),
+        Value::Str(_) => return Err(EvalError::VaryingString { at }),
+        Value::Vec3(v, dim) => {
+            let components = v.map(|x| literal(Value::quantity(x, dim), at));
+            let components = components.into_iter().collect::<Result<_, _>>();
+            return Ok(Expression::FnInvoke("vec3", components?));
+        }
+        Value::Color(color) => {
+            let channel = |c: u8| Expression::NumLiteral(c.into());
+            let mut channels =
+                vec![channel(color.r), channel(color.g), channel(color.b)];
+            if color.a == 255 {
+                return Ok(Expression::FnInvoke("rgb", channels));
+            }
+            channels.push(Expression::NumLiteral(color.a as f64 / 255.));
+            return Ok(Expression::FnInvoke("rgba", channels));
+        }
+        Value::List(items) => {
+            let items = items.into_iter().map(|item| literal(item, at));
+            return Ok(Expression::List(items.collect::<Result<_, _>>()?));
+        }
+    };
+    let number = |n: f64| {
+        let num = |n| Box::new(Expression::NumLiteral(n));
+        if n.is_nan() {
+            Expression::Div(num(0.), num(0.))
+        } else if n.is_infinite() && 0. < n {
+            Expression::Div(num(1.), num(0.))
+        } else if n.is_infinite() {
+            Expression::Neg(Box::new(Expression::Div(num(1.), num(0.))))
+        } else if n.is_sign_negative() {
+            Expression::Neg(num(-n))
+        } else {
+            Expression::NumLiteral(n)
+        }
+    };
+    let quantity = |unit| {
+        let quantity = Expression::Quantity(n.abs(), unit);
+        if n.is_sign_negative() {
+            Expression::Neg(Box::new(quantity))
+        } else {
+            quantity
+        }
+    };
+    Ok(match (dim.length, dim.time, dim.mass) {
+        (0, 0, 0) => number(n),
+        (1, 0, 0) if n.is_finite() => quantity("AU"),
+        (0, 1, 0) if n.is_finite() => quantity("d"),
+        (0, 0, 1) if n.is_finite() => quantity("Msun"),
+        (length, time, mass) => {
+            let mut ex = number(n);
+            for (power, unit) in [(length, "AU"), (time, "d"), (mass, "Msun")] {
+                if power != 0 {
+                    let unit = Expression::Pow(
+                        Box::new(Expression::Quantity(1., unit)),
+                        Box::new(number(power.into())),
+                    );
+                    ex = Expression::Mul(Box::new(ex), Box::new(unit));
+                }
+            }
+            ex
+        }
+    })
+}
+
+/// Bodies whose properties depend on the time, for the benchmarks in
+/// `benches`, which can only reach what the library exports.
+#[cfg(any(test, feature = "bench"))]
+#[doc(hidden)]
+pub mod bench {
+    use super::*;
+    use crate::{
+        functions::FunctionRegistry,
+        parser::{command, expr, Command, Scope},
+    };
+
+    const DEFINITIONS: &[&str] = &[
+        "fn wobble(x, period) = x * (1 + 0.1 * sin(2 * pi * t / period))",
+        "fn kepler(a) = 365.25 d * sqrt((a / 1 AU)^3)",
+    ];
+
+    const PROPERTIES: &[&str] = &[
+        "wobble(6371 km, 3) * (1 + i / 1000)",
+        "1.5 AU * (1 + 0.001 * i) - 0.00001 AU * t",
+        "kepler(1 AU + i * 0.01 AU)",
+        "clamp(t / 100 * i, 0, 10) * (i % 2 == 0 ? 1 : -1)",
+        "hypot(cos(t / 27.3), sin(t / 27.3), 0.05 * i)",
+    ];
+
+    pub struct Bodies {
+        count: usize,
+        /// The definitions, and the inputs of the body being evaluated.
+        scopes: Vec<Scope<'static>>,
+        expressions: Vec<Expression<'static>>,
+        compiled: Vec<Compiled<'static>>,
+    }
+
+    impl Bodies {
+        /// Returns `count` bodies, each with properties that depend on the
+        /// time `t` and on the index `i` of the body.
+        pub fn new(count: usize) -> Self {
+            let mut definitions = Scope::default();
+            for def in DEFINITIONS {
+                let Ok((_, Command::FnDef(name, params, body))) = command(def)
+                else {
+                    unreachable!("not a function definition: {def}")
+                };
+                definitions
+                    .functions
+                    .insert(name.to_owned(), Function { params, body });
+            }
+            let scopes = vec![definitions, Scope::default()];
+            let expressions: Vec<_> = PROPERTIES
+                .iter()
+                .map(|property| expr(property).unwrap().1)
+                .collect();
+            let env =
+                Env::with_functions(&scopes[..1], FunctionRegistry::builtins());
+            let compiled = expressions
+                .iter()
+                .map(|ex| compile(ex, &env, &["t", "i"]))
+                .collect();
+            Self {
+                count,
+                scopes,
+                expressions,
+                compiled,
+            }
+        }
+
+        /// Evaluates every property of every body at time `t` with
+        /// [`eval`], and returns the sum of the values.
+        pub fn eval(&mut self, t: f64) -> f64 {
+            let mut sum = 0.;
+            for i in 0..self.count {
+                let vars = &mut self.scopes[1].vars;
+                vars.insert("t".to_owned(), Value::Num(t));
+                vars.insert("i".to_owned(), Value::Num(i as f64));
+                let env = Env::with_functions(
+                    &self.scopes,
+                    FunctionRegistry::builtins(),
+                );
+                for ex in &self.expressions {
+                    sum += eval(ex, &env).unwrap().as_quantity().unwrap().0;
+                }
+            }
+            sum
+        }
+
+        /// Evaluates every property of every body at time `t` with the
+        /// compiled expressions, and returns the sum of the values.
+        pub fn run(&self, t: f64) -> f64 {
+            let mut sum = 0.;
+            for i in 0..self.count {
+                let inputs = [Value::Num(t), Value::Num(i as f64)];
+                for compiled in &self.compiled {
+                    let value = compiled.run(&inputs).unwrap();
+                    sum += value.as_quantity().unwrap().0;
+                }
+            }
+            sum
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use proptest::prelude::*;
+
+    use super::*;
+    use crate::{
+        format::format_expression,
+        parser::{command, expr, Command, Scope},
+    };
+
+    /// Variables, functions, and a body with properties.
+    fn definitions() -> Scope<'static> {
+        let mut scope = Scope::default();
+        for def in [
+            "fn sq(x) = x * x",
+            "fn fact(n) = n <= 1 ? 1 : n * fact(n - 1)",
+            "fn forever(n) = forever(n + 1)",
+            "fn later(n) = t + n",
+        ] {
+            let Ok((_, Command::FnDef(name, params, body))) = command(def)
+            else {
+                panic!("not a function definition: {def}");
+            };
+            scope
+                .functions
+                .insert(name.to_owned(), Function { params, body });
+        }
+        scope
+            .vars
+            .insert("a".to_owned(), Value::Quantity(2., Dim::LENGTH));
+        scope.vars.insert("b".to_owned(), Value::Bool(true));
+        scope.properties =
+            Some([("radius".to_owned(), Value::Num(0.5))].into());
+        scope
+    }
+
+    /// Evaluates `ex` both with [`eval`] and compiled, with inputs `t` and
+    /// `x`, and checks that the results are the same. NaN is not equal to
+    /// itself, so they are compared as printed.
+    fn check(ex: &Expression<'_>, t: Value, x: Value) -> EvalResult<'static> {
+        let mut inputs = Scope::default();
+        inputs.vars.insert("t".to_owned(), t.clone());
+        inputs.vars.insert("x".to_owned(), x.clone());
+        let scopes = [definitions(), inputs];
+        let expected = eval(ex, &Env::new(&scopes));
+        let compiled = compile(ex, &Env::new(&scopes[..1]), &["t", "x"]);
+        let found = compiled.run(&[t, x]);
+        assert_eq!(format!("{found:?}"), format!("{expected:?}"), "{ex:?}");
+        // Errors borrow from the expression.
+        expected.map_err(|_| EvalError::UnknownName("error"))
+    }
+
+    #[test]
+    fn test_same_as_eval() {
+        let days = |t| Value::Quantity(t, Dim::TIME);
+        for s in [
+            "1 + 2 * t",
+            "sqrt(t) + max(1, t, 3)",
+            "a * t / 1 d",
+            "t > 2 d ? a : 1 AU",
+            "sq(t / 1 d) + fact(5)",
+            "later(1 d) - t",
+            "self.radius * x",
+            "x == 2 && b || t < 0 d",
+            "\"at ${t / 1 d} days\"",
+            "\"${t\"",
+            // Errors, which must also be the same.
+            "t + 1",
+            "sq(t, 1)",
+            "forever(t)",
+            "unknown(t)",
+            "x ? 1 : 2",
+            "max(t, 1 AU)",
+            "(a / 1 AU)^(t / 1 d)",
+        ] {
+            let (_, ex) = expr(s).unwrap();
+            for t in [0., 2.5, 10.] {
+                check(&ex, days(t), Value::Num(t)).ok();
+            }
+        }
+        let (_, ex) = expr("sq(t) + x").unwrap();
+        assert_eq!(
+            check(&ex, Value::Num(3.), Value::Num(1.)),
+            Ok(Value::Num(10.))
+        );
+    }
+
+    #[test]
+    fn test_folding() {
+        let scopes = [definitions()];
+        let env = Env::new(&scopes);
+        let compiled = |s| compile(&expr(s).unwrap().1, &env, &["t"]);
+        assert_eq!(
+            compiled("sqrt(4) + a / 1 AU").constant(),
+            Some(&Ok(Value::Num(4.)))
+        );
+        assert_eq!(compiled("sqrt(t)").constant(), None);
+        // Only the branch that is taken matters.
+        assert_eq!(compiled("b ? 1 : t").constant(), Some(&Ok(Value::Num(1.))));
+        // Errors are only reported when the expression is evaluated.
+        let error = compiled("t > 0 ? 1 : missing");
+        assert_eq!(error.constant(), None);
+        assert_eq!(error.run(&[Value::Num(1.)]), Ok(Value::Num(1.)));
+        assert_eq!(
+            error.run(&[Value::Num(-1.)]),
+            Err(EvalError::UnknownName("missing"))
+        );
+    }
+
+    #[test]
+    fn test_resolve() {
+        let scopes = [definitions()];
+        let env = Env::new(&scopes);
+        let resolved = |s| {
+            let (_, ex) = expr(s).unwrap();
+            let resolved = resolve(&ex, &env, &["t"]);
+            resolved.map(|ex| ex.map(|ex| format_expression(&ex)))
+        };
+        assert_eq!(resolved("sq(a / 1 AU) + fact(3)"), Ok(None));
+        assert_eq!(
+            resolved("b ? later(1 d) : 2 d"),
+            Ok(Some("t + 1 d".to_owned()))
+        );
+        assert_eq!(
+            resolved("a * sq(t / 1 d) + fact(3) * 1 AU"),
+            Ok(Some("2 AU * (t / 1 d * (t / 1 d)) + 6 AU".to_owned()))
+        );
+        assert_eq!(
+            resolved("later(-a / 1 AU * 1 d) * a^2"),
+            Ok(Some("(t + -2 d) * (4 * 1 AU^2)".to_owned()))
+        );
+        assert_eq!(resolved("b ? t : missing"), Ok(Some("t".to_owned())));
+        assert_eq!(
+            resolved("forever(t)"),
+            Err(EvalError::RecursionLimit("forever"))
+        );
+        assert_eq!(
+            resolved("\"${t}\""),
+            Err(EvalError::VaryingString { at: Some("${t}") })
+        );
+
+        // The resolved expression evaluates to the same as the original
+        // one, without the definitions.
+        for s in [
+            "a * sq(t / 1 d)",
+            "later(fact(3) * 1 d) / t",
+            "t > 1 d",
+            "later(-a / 1 AU * 1 d) * a^2",
+        ] {
+            let (_, ex) = expr(s).unwrap();
+            let source = format_expression(
+                &resolve(&ex, &env, &["t"]).unwrap().unwrap(),
+            );
+            let (_, resolved) = expr(&source).unwrap();
+            let original = compile(&ex, &env, &["t"]);
+            let resolved = compile(&resolved, &Env::new(&[]), &["t"]);
+            for t in [0., 2.5, -10.] {
+                let t = [Value::Quantity(t, Dim::TIME)];
+                assert_eq!(resolved.run(&t), original.run(&t), "{s}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_bench_workload() {
+        let mut bodies = bench::Bodies::new(100);
+        for t in [0., 1000.] {
+            assert_eq!(bodies.run(t), bodies.eval(t));
+        }
+    }
+
+    fn expression() -> impl Strategy<Value = Expression<'static>> {
+        let name = || prop::sample::select(vec!["t", "x", "a", "b", "c"]);
+        let leaf = prop_oneof![
+            name().prop_map(Expression::Ident),
+            (-3. ..3f64).prop_map(Expression::NumLiteral),
+            (0. ..3f64, prop::sample::select(vec!["AU", "d"]))
+                .prop_map(|(n, unit)| Expression::Quantity(n, unit)),
+            any::<bool>().prop_map(Expression::BoolLiteral),
+        ];
+        leaf.prop_recursive(4, 32, 3, move |inner| {
+            let pair = || (inner.clone(), inner.clone());
+            let boxed = |(lhs, rhs)| (Box::new(lhs), Box::new(rhs));
+            prop_oneof![
+                (
+                    prop::sample::select(vec!["sq", "fact", "max", "sqrt"]),
+                    prop::collection::vec(inner.clone(), 0..3)
+                )
+                    .prop_map(|(name, args)| Expression::FnInvoke(name, args)),
+                pair()
+                    .prop_map(boxed)
+                    .prop_map(|(l, r)| Expression::Add(l, r)),
+                pair()
+                    .prop_map(boxed)
+                    .prop_map(|(l, r)| Expression::Mul(l, r)),
+                pair()
+                    .prop_map(boxed)
+                    .prop_map(|(l, r)| Expression::Div(l, r)),
+                pair()
+                    .prop_map(boxed)
+                    .prop_map(|(l, r)| Expression::Pow(l, r)),
+                pair()
+                    .prop_map(boxed)
+                    .prop_map(|(l, r)| Expression::Lt(l, r)),
+                pair()
+                    .prop_map(boxed)
+                    .prop_map(|(l, r)| Expression::Eq(l, r)),
+                pair()
+                    .prop_map(boxed)
+                    .prop_map(|(l, r)| Expression::Or(l, r)),
+                inner.clone().prop_map(|ex| Expression::Neg(Box::new(ex))),
+                (inner.clone(), inner.clone(), inner.clone()).prop_map(
+                    |(cond, then, els)| Expression::Cond(
+                        Box::new(cond),
+                        Box::new(then),
+                        Box::new(els)
+                    )
+                ),
+            ]
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn test_compiled_is_eval(
+            ex in expression(),
+            t in -3. ..3f64,
+            x in any::<bool>(),
+        ) {
+            check(&ex, Value::Num(t), Value::Bool(x)).ok();
+        }
+    }
+}
@@ -0,0 +1,260 @@
+use std::fmt::{self, Display};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Level {
+    Error,
+    Warning,
+}
+
+impl Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Error => write!(f, "error"),
+            Self::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+/// A byte range in the source text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    /// Locates `slice` in `source`. The AST borrows every name from the
+    /// source string, so the position of a slice is its pointer offset.
+    /// Returns `None` if `slice` does not point into `source`.
+    pub fn of(source: &str, slice: &str) -> Option<Self> {
+        let start =
+            (slice.as_ptr() as usize).checked_sub(source.as_ptr() as usize)?;
+        let end = start + slice.len();
+        if end <= source.len() {
+            Some(Self { start, end })
+        } else {
+            None
+        }
+    }
+
+    /// Returns 1-based line and column numbers of the start of the span.
+    pub fn line_col(&self, source: &str) -> (usize, usize) {
+        let before = &source[..self.start];
+        let line = before.matches('\n').count() + 1;
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        let col = source[line_start..self.start].chars().count() + 1;
+        (line, col)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Diagnostic {
+    pub level: Level,
+    pub message: String,
+    /// Index of the file the diagnostic belongs to.
+    pub file: usize,
+    pub span: Option<Span>,
+}
+
+struct SourceFile<'src> {
+    name: String,
+    source: &'src str,
+}
+
+/// Collects errors and warnings found while parsing and evaluating a scene
+/// and the files it includes, and renders them with a source excerpt.
+pub struct Diagnostics<'src> {
+    files: Vec<SourceFile<'src>>,
+    items: Vec<Diagnostic>,
+}
+
+impl<'src> Diagnostics<'src> {
+    pub fn new(file_name: impl Into<String>, source: &'src str) -> Self {
+        let mut diagnostics = Self {
+            files: vec![],
+            items: vec![],
+        };
+        diagnostics.add_file(file_name, source);
+        diagnostics
+    }
+
+    /// Registers another source file, so that diagnostics pointing into it
+    /// are rendered with its name.
+    pub fn add_file(
+        &mut self,
+        file_name: impl Into<String>,
+        source: &'src str,
+    ) {
+        self.files.push(SourceFile {
+            name: file_name.into(),
+            source,
+        });
+    }
+
+    /// Diagnostics whose location is not in any file are attributed to the
+    /// first one.
+    pub(crate) fn push(
+        &mut self,
+        level: Level,
+        at: &str,
+        message: impl Into<String>,
+    ) {
+        let (file, span) = self
+            .files
+            .iter()
+            .enumerate()
+            .find_map(|(i, file)| Some((i, Span::of(file.source, at)?)))
+            .map_or((0, None), |(file, span)| (file, Some(span)));
+        let item = Diagnostic {
+            level,
+            message: message.into(),
+            file,
+            span,
+        };
+        // Commands inherited from a template are evaluated for every body
+        // using it, but their problems only need to be reported once.
+        if !self.items.contains(&item) {
+            self.items.push(item);
+        }
+    }
+
+    pub fn error(&mut self, at: &str, message: impl Into<String>) {
+        self.push(Level::Error, at, message);
+    }
+
+    pub fn warning(&mut self, at: &str, message: impl Into<String>) {
+        self.push(Level::Warning, at, message);
+    }
+
+    pub(crate) fn items(&self) -> &[Diagnostic] {
+        &self.items
+    }
+
+    pub fn error_count(&self) -> usize {
+        self.items
+            .iter()
+            .filter(|item| item.level == Level::Error)
+            .count()
+    }
+
+    fn file_error_count(&self, file: usize) -> usize {
+        self.items
+            .iter()
+            .filter(|item| item.level == Level::Error && item.file == file)
+            .count()
+    }
+
+    /// Renders a single diagnostic in the style of rustc, e.g.
+    ///
+    /// ```text
+    /// error: unknown function `sqr`
+    ///  --> assets/sol.txt:3:13
+    ///   |
+    /// 3 |     radius: sqr(2)
+    ///   |             ^^^
+    /// ```
+    pub(crate) fn render(&self, item: &Diagnostic) -> String {
+        let SourceFile { name, source } = &self.files[item.file];
+        let mut out = format!("{}: {}\n", item.level, item.message);
+        let Some(span) = item.span else {
+            out += &format!(" --> {name}\n");
+            return out;
+        };
+        let (line, col) = span.line_col(source);
+        let line_start = source[..span.start].rfind('\n').map_or(0, |i| i + 1);
+        let text = source[line_start..]
+            .lines()
+            .next()
+            .unwrap_or("")
+            .trim_end_matches('\r');
+        let underline = source[span.start..span.end]
+            .lines()
+            .next()
+            .map_or(0, |s| s.chars().count())
+            .max(1);
+        let gutter = " ".repeat(line.to_string().len());
+        out += &format!("{gutter}--> {name}:{line}:{col}\n");
+        out += &format!("{gutter} |\n");
+        out += &format!("{line} | {text}\n");
+        out += &format!(
+            "{gutter} | {}{}\n",
+            " ".repeat(col - 1),
+            "^".repeat(underline)
+        );
+        out
+    }
+
+    /// Prints the diagnostics collected so far to stderr (the browser console
+    /// on the web), followed by the number of errors in each file, and clears
+    /// them.
+    pub fn emit(&mut self) {
+        let mut messages: Vec<_> =
+            self.items.iter().map(|item| self.render(item)).collect();
+        for (i, file) in self.files.iter().enumerate() {
+            match self.file_error_count(i) {
+                0 => (),
+                1 => messages.push(format!("{}: 1 error found\n", file.name)),
+                n => {
+                    messages.push(format!("{}: {n} errors found\n", file.name))
+                }
+            }
+        }
+        for message in messages {
+            #[cfg(target_arch = "wasm32")]
+            log::warn!("{message}");
+            #[cfg(not(target_arch = "wasm32"))]
+            eprintln!("{message}");
+        }
+        self.items.clear();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_span_of() {
+        let source = "a = 1\nb = c";
+        assert_eq!(
+            Span::of(source, &source[10..]),
+            Some(Span { start: 10, end: 11 })
+        );
+        assert_eq!(Span::of(source, &String::from("c")), None);
+        assert_eq!(Span { start: 10, end: 11 }.line_col(source), (2, 5));
+    }
+
+    #[test]
+    fn test_render() {
+        let source = "AU = 1\nastro Sun {\n    radius: sqr(2)\n}\n";
+        let mut diagnostics = Diagnostics::new("sol.txt", source);
+        let at = &source[source.find("sqr").unwrap()..][..3];
+        diagnostics.error(at, "unknown function `sqr`");
+        assert_eq!(diagnostics.error_count(), 1);
+        assert_eq!(
+            diagnostics.render(&diagnostics.items()[0]),
+            "error: unknown function `sqr`
+ --> sol.txt:3:13
+  |
+3 |     radius: sqr(2)
+  |             ^^^
+"
+        );
+    }
+
+    #[test]
+    fn test_render_included_file() {
+        let main = "include \"moons.txt\"\n";
+        let moons = "astro Io {\n  radius: )\n}\n";
+        let mut diagnostics = Diagnostics::new("main.txt", main);
+        diagnostics.add_file("moons.txt", moons);
+        diagnostics.error(&moons[moons.find(')').unwrap()..][..1], "oops");
+        diagnostics.error(&main[9..18], "cannot read");
+        assert_eq!(diagnostics.items()[0].file, 1);
+        assert!(diagnostics
+            .render(&diagnostics.items()[0])
+            .contains(" --> moons.txt:2:11\n"));
+        assert_eq!(diagnostics.items()[1].file, 0);
+        assert_eq!(diagnostics.file_error_count(1), 1);
+    }
+}
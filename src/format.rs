@@ -0,0 +1,529 @@
+use crate::{
+    diagnostics::Diagnostics,
+    parser::{escape, parse_scene, Arg, Command, Expression, Property},
+};
+
+const INDENT: &str = "    ";
+
+/// Formats a scene file in the canonical layout, or returns `None` if it
+/// has syntax errors, which are reported to `diagnostics`.
+pub(crate) fn format_source<'src>(
+    source: &'src str,
+    diagnostics: &mut Diagnostics<'src>,
+) -> Option<String> {
+    let errors = diagnostics.error_count();
+    let commands = parse_scene(source, diagnostics);
+    if diagnostics.error_count() > errors {
+        return None;
+    }
+    Some(format_commands(&commands))
+}
+
+/// Prints commands with one statement per line, blocks indented by four
+/// spaces and a blank line around each statement that has a block.
+pub(crate) fn format_commands(commands: &[Command]) -> String {
+    let mut out = String::new();
+    write_commands(&mut out, commands, 0);
+    out
+}
+
+fn has_block(command: &Command) -> bool {
+    match command {
+        Command::Com(args) => {
+            args.iter().any(|arg| matches!(arg, Arg::Block(_)))
+        }
+        Command::For(..) => true,
+        _ => false,
+    }
+}
+
+/// Whether a blank line goes before `commands[i]`. Comments directly above
+/// a statement with a block are kept together with it.
+fn blank_line_before(commands: &[Command], i: usize) -> bool {
+    let Some(prev) = commands[..i]
+        .iter()
+        .rev()
+        .find(|command| !matches!(command, Command::TrailingComment(_)))
+    else {
+        return false;
+    };
+    let next = commands[i..].iter().find(|command| {
+        !matches!(command, Command::Comment(_) | Command::TrailingComment(_))
+    });
+    has_block(prev)
+        || !matches!(prev, Command::Comment(_)) && next.is_some_and(has_block)
+}
+
+fn write_commands(out: &mut String, commands: &[Command], depth: usize) {
+    for (i, command) in commands.iter().enumerate() {
+        if let Command::TrailingComment(comment) = command {
+            // Goes at the end of the line written last, if there is one.
+            if out.ends_with('\n') {
+                out.pop();
+                out.push(' ');
+            } else {
+                out.push_str(&INDENT.repeat(depth));
+            }
+            out.push_str(comment);
+            out.push('\n');
+            continue;
+        }
+        if blank_line_before(commands, i) {
+            out.push('\n');
+        }
+        out.push_str(&INDENT.repeat(depth));
+        write_command(out, command, depth);
+        out.push('\n');
+    }
+}
+
+fn write_block(out: &mut String, block: &[Command], depth: usize) {
+    if block.is_empty() {
+        out.push_str("{}");
+        return;
+    }
+    out.push_str("{\n");
+    write_commands(out, block, depth + 1);
+    out.push_str(&INDENT.repeat(depth));
+    out.push('}');
+}
+
+/// Whether `args` are those of an `astro` or `template` block, whose name
+/// can be followed by the template it inherits from.
+fn names_a_template(args: &[Arg]) -> bool {
+    matches!(
+        args,
+        [
+            Arg::Str("astro" | "template"),
+            Arg::Str(_) | Arg::Quoted(_),
+            ..
+        ]
+    )
+}
+
+fn write_command(out: &mut String, command: &Command, depth: usize) {
+    match command {
+        Command::Com(args) => {
+            // A template name anywhere but after the name of an `astro` or
+            // `template` block would be read back as something else, and
+            // the parser never puts one there.
+            let legal = |i: usize, arg: &Arg| {
+                !matches!(arg, Arg::Base(_)) || i == 2 && names_a_template(args)
+            };
+            let args =
+                args.iter().enumerate().filter(|&(i, arg)| legal(i, arg));
+            for (i, (_, arg)) in args.enumerate() {
+                if i > 0 {
+                    out.push(' ');
+                }
+                match arg {
+                    Arg::Str(s) => out.push_str(s),
+                    Arg::Quoted(s) => out.push_str(&format!("\"{s}\"")),
+                    Arg::Base(name) => out.push_str(&format!(": {name}")),
+                    Arg::Block(block) => write_block(out, block, depth),
+                }
+            }
+        }
+        Command::Prop(name, Property::Str(s)) => {
+            out.push_str(&format!("{name}: \"{}\"", escape(s)))
+        }
+        Command::Prop(name, Property::Expr(ex)) => {
+            out.push_str(&format!("{name}: {}", format_expression(ex)))
+        }
+        Command::Def(name, ex) => {
+            out.push_str(&format!("{name} = {}", format_expression(ex)))
+        }
+        Command::Let(name, ex) => {
+            out.push_str(&format!("let {name} = {}", format_expression(ex)))
+        }
+        Command::Const(name, ex) => {
+            out.push_str(&format!("const {name} = {}", format_expression(ex)))
+        }
+        Command::FnDef(name, params, body) => out.push_str(&format!(
+            "fn {name}({}) = {}",
+            params.join(", "),
+            format_expression(body)
+        )),
+        Command::Include(path) => out.push_str(&format!("include \"{path}\"")),
+        Command::For(var, start, end, body) => {
+            out.push_str(&format!(
+                "for {var} in {}..{} ",
+                format_expression(start),
+                format_expression(end)
+            ));
+            write_block(out, body, depth);
+        }
+        Command::Comment(s)
+        | Command::TrailingComment(s)
+        | Command::Error(s) => out.push_str(s),
+    }
+}
+
+/// Binding strength of each kind of expression, from the conditional
+/// operator up to literals and calls.
+const COND: u8 = 0;
+const OR: u8 = 1;
+const AND: u8 = 2;
+const COMPARISON: u8 = 3;
+const SUM: u8 = 4;
+const TERM: u8 = 5;
+const UNARY: u8 = 6;
+const POWER: u8 = 7;
+const ATOM: u8 = 8;
+
+fn precedence(ex: &Expression) -> u8 {
+    match ex {
+        Expression::Cond(..) => COND,
+        Expression::Or(..) => OR,
+        Expression::And(..) => AND,
+        Expression::Lt(..)
+        | Expression::Le(..)
+        | Expression::Gt(..)
+        | Expression::Ge(..)
+        | Expression::Eq(..)
+        | Expression::Ne(..) => COMPARISON,
+        Expression::Add(..) | Expression::Sub(..) => SUM,
+        Expression::Mul(..) | Expression::Div(..) | Expression::Mod(..) => TERM,
+        Expression::Neg(_) | Expression::Not(_) => UNARY,
+        Expression::Pow(..) => POWER,
+        _ => ATOM,
+    }
+}
+
+/// Prints an expression with only the parentheses needed to parse it back
+/// to the same tree.
+pub(crate) fn format_expression(ex: &Expression) -> String {
+    let mut out = String::new();
+    write_expression(&mut out, ex, COND);
+    out
+}
+
+/// Writes `ex`, in parentheses if it binds less tightly than `min`.
+fn write_expression(out: &mut String, ex: &Expression, min: u8) {
+    let parens = precedence(ex) < min;
+    if parens {
+        out.push('(');
+    }
+    let mut binary = |lhs, op: &str, rhs, lhs_min, rhs_min| {
+        write_expression(out, lhs, lhs_min);
+        out.push_str(op);
+        write_expression(out, rhs, rhs_min);
+    };
+    match ex {
+        Expression::Ident(name) => out.push_str(name),
+        Expression::NumLiteral(n) => out.push_str(&n.to_string()),
+        Expression::Quantity(n, unit) => out.push_str(&format!("{n} {unit}")),
+        Expression::BoolLiteral(b) => out.push_str(&b.to_string()),
+        Expression::StrLiteral(s) => out.push_str(&format!("\"{s}\"")),
+        Expression::Color(s) => out.push_str(s),
+        Expression::List(items) => {
+            out.push('[');
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push_str(", ");
+                }
+                write_expression(out, item, COND);
+            }
+            out.push(']');
+        }
+        Expression::Field(body, name) => {
+            out.push_str(&format!("{body}.{name}"))
+        }
+        Expression::FnInvoke(name, args) => {
+            out.push_str(name);
+            out.push('(');
+            for (i, arg) in args.iter().enumerate() {
+                if i > 0 {
+                    out.push_str(", ");
+                }
+                write_expression(out, arg, COND);
+            }
+            out.push(')');
+        }
+        Expression::Cond(cond, then, els) => {
+            write_expression(out, cond, OR);
+            out.push_str(" ? ");
+            write_expression(out, then, COND);
+            out.push_str(" : ");
+            write_expression(out, els, COND);
+        }
+        Expression::Or(lhs, rhs) => binary(lhs, " || ", rhs, OR, AND),
+        Expression::And(lhs, rhs) => binary(lhs, " && ", rhs, AND, COMPARISON),
+        // Comparisons do not chain, so both sides bind more tightly.
+        Expression::Lt(lhs, rhs) => binary(lhs, " < ", rhs, SUM, SUM),
+        Expression::Le(lhs, rhs) => binary(lhs, " <= ", rhs, SUM, SUM),
+        Expression::Gt(lhs, rhs) => binary(lhs, " > ", rhs, SUM, SUM),
+        Expression::Ge(lhs, rhs) => binary(lhs, " >= ", rhs, SUM, SUM),
+        Expression::Eq(lhs, rhs) => binary(lhs, " == ", rhs, SUM, SUM),
+        Expression::Ne(lhs, rhs) => binary(lhs, " != ", rhs, SUM, SUM),
+        Expression::Add(lhs, rhs) => binary(lhs, " + ", rhs, SUM, TERM),
+        Expression::Sub(lhs, rhs) => binary(lhs, " - ", rhs, SUM, TERM),
+        Expression::Mul(lhs, rhs) => binary(lhs, " * ", rhs, TERM, UNARY),
+        Expression::Div(lhs, rhs) => binary(lhs, " / ", rhs, TERM, UNARY),
+        Expression::Mod(lhs, rhs) => binary(lhs, " % ", rhs, TERM, UNARY),
+        // The base of a power is a single factor, and the exponent may
+        // have a sign.
+        Expression::Pow(lhs, rhs) => binary(lhs, "^", rhs, ATOM, UNARY),
+        Expression::Neg(ex) => {
+            out.push('-');
+            write_expression(out, ex, UNARY);
+        }
+        Expression::Not(ex) => {
+            out.push('!');
+            write_expression(out, ex, UNARY);
+        }
+    }
+    if parens {
+        out.push(')');
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parser::{commands, expr, pieces, Piece};
+    use proptest::prelude::*;
+
+    /// Checks that `source` is printed as `expected`, and that printing
+    /// does not change what is parsed.
+    fn assert_formats(source: &str, expected: &str) {
+        let (_, parsed) = commands(source).unwrap();
+        let printed = format_commands(&parsed);
+        assert_eq!(printed, expected);
+        let (rest, reparsed) = commands(&printed).unwrap();
+        assert_eq!(rest, "");
+        assert_eq!(reparsed, parsed);
+    }
+
+    #[test]
+    fn test_format_expression() {
+        for (source, expected) in [
+            ("1+2*3", "1 + 2 * 3"),
+            ("(1+2)*3", "(1 + 2) * 3"),
+            ("1-(2-3)", "1 - (2 - 3)"),
+            ("(1-2)-3", "1 - 2 - 3"),
+            ("-2^2", "-2^2"),
+            ("(-2)^2", "(-2)^2"),
+            ("2^3^2", "2^3^2"),
+            ("(2^3)^2", "(2^3)^2"),
+            ("2^-1", "2^-1"),
+            ("(a<b)==(c<d)", "(a < b) == (c < d)"),
+            ("!(a&&b)||c", "!(a && b) || c"),
+            ("a?b:c?d:e", "a ? b : c ? d : e"),
+            ("(a?b:c)?d:e", "(a ? b : c) ? d : e"),
+            ("if(x>0,1,2)", "x > 0 ? 1 : 2"),
+            ("pow( 2 ,3 )", "pow(2, 3)"),
+            ("parent.radius*1737/6371", "parent.radius * 1737 / 6371"),
+            ("6371km", "6371 km"),
+            ("\"Moon ${i}\"", "\"Moon ${i}\""),
+        ] {
+            let (_, ex) = expr(source).unwrap();
+            let printed = format_expression(&ex);
+            assert_eq!(printed, expected);
+            assert_eq!(expr(&printed), Ok(("", ex)));
+        }
+    }
+
+    #[test]
+    fn test_format_commands() {
+        assert_formats(
+            "a=1 # one\n\n\n  fn f(x)=x*2\nastro Sun{ // the star\n\
+            radius :0.1\n  astro Moon:Rock{}\n}\n# rocks\n\
+            for i in 0..3{astro \"Rock${i}\" {\n}}\ninclude \"b.txt\"\n",
+            "a = 1 # one\nfn f(x) = x * 2\n\nastro Sun { // the star\n    \
+            radius: 0.1\n\n    astro Moon : Rock {}\n}\n\n# rocks\n\
+            for i in 0..3 {\n    astro \"Rock${i}\" {}\n}\n\n\
+            include \"b.txt\"\n",
+        );
+        assert_formats(
+            "for i in 0..2 { astro \"R${i}\" { radius: i } }\n",
+            "for i in 0..2 {\n    astro \"R${i}\" {\n        radius: i\n    }\n}\n",
+        );
+        // A template name where the parser could not have put it is left
+        // out, since it would be read back as a property.
+        let ring = Command::Com(vec![Arg::Str("ring"), Arg::Base("Rock")]);
+        assert_eq!(format_commands(&[ring]), "ring\n");
+    }
+
+    fn name() -> impl Strategy<Value = &'static str> {
+        prop::sample::select(vec!["a", "b", "radius", "x1", "_n"])
+    }
+
+    fn expression() -> impl Strategy<Value = Expression<'static>> {
+        let leaf = prop_oneof![
+            name().prop_map(Expression::Ident),
+            (0. ..1e6f64).prop_map(Expression::NumLiteral),
+            (
+                0. ..1e6f64,
+                prop::sample::select(vec!["km", "AU", "d", "h"])
+            )
+                .prop_map(|(n, unit)| Expression::Quantity(n, unit)),
+            any::<bool>().prop_map(Expression::BoolLiteral),
+            prop::sample::select(vec![
+                "",
+                "sun.jpg",
+                "Rock ${i}",
+                r#"\"${i}\" \${i} C:\\"#
+            ])
+            .prop_map(Expression::StrLiteral),
+            (prop::sample::select(vec!["self", "parent"]), name())
+                .prop_map(|(body, name)| Expression::Field(body, name)),
+            prop::sample::select(vec!["#fff", "#ff8800", "#ff880080"])
+                .prop_map(Expression::Color),
+        ];
+        leaf.prop_recursive(4, 32, 3, |inner| {
+            let pair = || (inner.clone(), inner.clone());
+            let boxed = |(lhs, rhs)| (Box::new(lhs), Box::new(rhs));
+            prop_oneof![
+                (name(), prop::collection::vec(inner.clone(), 0..3))
+                    .prop_map(|(name, args)| Expression::FnInvoke(name, args)),
+                prop::collection::vec(inner.clone(), 0..3)
+                    .prop_map(Expression::List),
+                pair()
+                    .prop_map(boxed)
+                    .prop_map(|(l, r)| Expression::Add(l, r)),
+                pair()
+                    .prop_map(boxed)
+                    .prop_map(|(l, r)| Expression::Sub(l, r)),
+                pair()
+                    .prop_map(boxed)
+                    .prop_map(|(l, r)| Expression::Mul(l, r)),
+                pair()
+                    .prop_map(boxed)
+                    .prop_map(|(l, r)| Expression::Div(l, r)),
+                pair()
+                    .prop_map(boxed)
+                    .prop_map(|(l, r)| Expression::Mod(l, r)),
+                pair()
+                    .prop_map(boxed)
+                    .prop_map(|(l, r)| Expression::Pow(l, r)),
+                pair()
+                    .prop_map(boxed)
+                    .prop_map(|(l, r)| Expression::Lt(l, r)),
+                pair()
+                    .prop_map(boxed)
+                    .prop_map(|(l, r)| Expression::Ge(l, r)),
+                pair()
+                    .prop_map(boxed)
+                    .prop_map(|(l, r)| Expression::Eq(l, r)),
+                pair()
+                    .prop_map(boxed)
+                    .prop_map(|(l, r)| Expression::And(l, r)),
+                pair()
+                    .prop_map(boxed)
+                    .prop_map(|(l, r)| Expression::Or(l, r)),
+                inner.clone().prop_map(|ex| Expression::Neg(Box::new(ex))),
+                inner.clone().prop_map(|ex| Expression::Not(Box::new(ex))),
+                (inner.clone(), inner.clone(), inner.clone()).prop_map(
+                    |(cond, then, els)| Expression::Cond(
+                        Box::new(cond),
+                        Box::new(then),
+                        Box::new(els)
+                    )
+                ),
+            ]
+        })
+    }
+
+    fn comment() -> impl Strategy<Value = &'static str> {
+        prop::sample::select(vec!["# note", "// x = 1 {", "/* a\n  b */"])
+    }
+
+    /// A statement, possibly followed by a comment on the same line.
+    fn statement() -> impl Strategy<Value = Vec<Command<'static>>> {
+        let simple = prop_oneof![
+            (name(), expression())
+                .prop_filter(
+                    "plain strings are parsed as Property::Str",
+                    |(_, ex)| !matches!(
+                        ex,
+                        Expression::StrLiteral(s)
+                            if matches!(&pieces(s)[..], [Piece::Text(_)])
+                    )
+                )
+                .prop_map(|(name, ex)| Command::Prop(name, Property::Expr(ex))),
+            (
+                name(),
+                prop::sample::select(vec!["moon.png", r#"a "${b}" \c"#])
+            )
+                .prop_map(|(name, s)| {
+                    Command::Prop(name, Property::Str(s.to_owned()))
+                }),
+            (name(), expression())
+                .prop_map(|(name, ex)| Command::Def(name, ex)),
+            (name(), expression())
+                .prop_map(|(name, ex)| Command::Let(name, ex)),
+            (name(), expression())
+                .prop_map(|(name, ex)| Command::Const(name, ex)),
+            (name(), prop::collection::vec(name(), 0..3), expression())
+                .prop_map(|(name, params, ex)| Command::FnDef(
+                    name, params, ex
+                )),
+            Just(Command::Include("moons.txt")),
+            (
+                prop::sample::select(vec!["astro", "template"]),
+                name(),
+                name()
+            )
+                .prop_map(|(keyword, name, base)| Command::Com(
+                    vec![Arg::Str(keyword), Arg::Str(name), Arg::Base(base)]
+                )),
+        ];
+        let leaf = prop_oneof![
+            (simple, prop::option::of(comment())).prop_map(
+                |(command, comment)| {
+                    let mut commands = vec![command];
+                    commands.extend(comment.map(Command::TrailingComment));
+                    commands
+                }
+            ),
+            comment().prop_map(|comment| vec![Command::Comment(comment)]),
+        ];
+        leaf.prop_recursive(3, 16, 4, |inner| {
+            let block =
+                prop::collection::vec(inner, 0..4).prop_map(|statements| {
+                    statements.into_iter().flatten().collect::<Vec<_>>()
+                });
+            prop_oneof![
+                (name(), prop::option::of(name()), block.clone()).prop_map(
+                    |(name, base, block)| {
+                        let mut args = vec![Arg::Str("astro"), Arg::Str(name)];
+                        args.extend(base.map(Arg::Base));
+                        args.push(Arg::Block(block));
+                        vec![Command::Com(args)]
+                    }
+                ),
+                (expression(), expression(), block).prop_map(
+                    |(start, end, body)| {
+                        vec![Command::For("i", start, end, body)]
+                    }
+                ),
+            ]
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn test_expression_round_trip(ex in expression()) {
+            let printed = format_expression(&ex);
+            prop_assert_eq!(expr(&printed), Ok(("", ex)));
+        }
+
+        #[test]
+        fn test_commands_round_trip(
+            statements in prop::collection::vec(statement(), 0..6)
+        ) {
+            let parsed: Vec<_> = statements.into_iter().flatten().collect();
+            let printed = format_commands(&parsed);
+            prop_assert_eq!(commands(&printed), Ok(("", parsed)));
+        }
+    }
+
+    #[test]
+    fn test_format_is_idempotent() {
+        let source = include_str!("../assets/sol.txt");
+        let (_, parsed) = commands(source).unwrap();
+        let printed = format_commands(&parsed);
+        assert_eq!(printed, source);
+    }
+}
@@ -0,0 +1,332 @@
+use std::{
+    collections::HashMap,
+    fmt::{self, Display},
+    sync::{Arc, OnceLock},
+};
+
+use crate::{
+    color::Color,
+    parser::{EvalError, Value},
+    units::Dim,
+};
+
+/// How many arguments a function takes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Arity {
+    pub min: usize,
+    /// `None` for functions taking any number of arguments from `min` on.
+    pub max: Option<usize>,
+}
+
+impl Arity {
+    pub const fn exact(n: usize) -> Self {
+        Self {
+            min: n,
+            max: Some(n),
+        }
+    }
+
+    pub const fn between(min: usize, max: usize) -> Self {
+        Self {
+            min,
+            max: Some(max),
+        }
+    }
+
+    pub const fn at_least(min: usize) -> Self {
+        Self { min, max: None }
+    }
+
+    pub fn accepts(&self, n: usize) -> bool {
+        self.min <= n && self.max.is_none_or(|max| n <= max)
+    }
+}
+
+impl Display for Arity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let plural = |n| if n == 1 { "" } else { "s" };
+        match self.max {
+            Some(max) if max == self.min => {
+                write!(f, "{max} argument{}", plural(max))
+            }
+            Some(max) if max == self.min + 1 => {
+                write!(f, "{} or {max} arguments", self.min)
+            }
+            Some(max) => write!(f, "{} to {max} arguments", self.min),
+            None => {
+                write!(f, "at least {} argument{}", self.min, plural(self.min))
+            }
+        }
+    }
+}
+
+/// The Rust implementation of a function, called with the values of its
+/// arguments once their number has been checked against its [`Arity`].
+pub(crate) type NativeFn =
+    dyn Fn(&[Value]) -> Result<Value, EvalError<'static>> + Send + Sync;
+
+#[derive(Clone)]
+pub(crate) struct NativeFunction {
+    pub arity: Arity,
+    /// Shared so that compiled expressions can hold on to it.
+    pub f: Arc<NativeFn>,
+}
+
+impl fmt::Debug for NativeFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NativeFunction")
+            .field("arity", &self.arity)
+            .finish_non_exhaustive()
+    }
+}
+
+/// The functions implemented in Rust that expressions can call. Scene files
+/// cannot redefine them.
+///
+/// An application embedding the scene language can add its own:
+///
+/// ```
+/// use rusty_space::{
+///     load_scene_with_functions, parse_scene, Arity, Diagnostics,
+///     FunctionRegistry, Value,
+/// };
+///
+/// let mut functions = FunctionRegistry::with_builtins();
+/// functions.register("cube", Arity::exact(1), |args| {
+///     Ok(Value::Num(args[0].as_num()?.powi(3)))
+/// });
+/// let source = "astro Rock {\n    radius: cube(0.2)\n}\n";
+/// let mut diagnostics = Diagnostics::new("scene.txt", source);
+/// let commands = parse_scene(source, &mut diagnostics);
+/// load_scene_with_functions(&commands, &mut diagnostics, &functions);
+/// assert_eq!(diagnostics.error_count(), 0);
+/// ```
+#[derive(Debug, Default, Clone)]
+pub struct FunctionRegistry {
+    functions: HashMap<String, NativeFunction>,
+}
+
+impl FunctionRegistry {
+    /// Adds a function, replacing any function of the same name.
+    pub fn register(
+        &mut self,
+        name: &str,
+        arity: Arity,
+        f: impl Fn(&[Value]) -> Result<Value, EvalError<'static>>
+            + Send
+            + Sync
+            + 'static,
+    ) {
+        self.functions.insert(
+            name.to_owned(),
+            NativeFunction {
+                arity,
+                f: Arc::new(f),
+            },
+        );
+    }
+
+    pub(crate) fn get(&self, name: &str) -> Option<&NativeFunction> {
+        self.functions.get(name)
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.functions.contains_key(name)
+    }
+
+    /// Returns the names of the functions in alphabetical order.
+    pub fn names(&self) -> Vec<&str> {
+        let mut names: Vec<_> =
+            self.functions.keys().map(String::as_str).collect();
+        names.sort_unstable();
+        names
+    }
+
+    /// Returns a registry with the built-in math functions, to which an
+    /// application can add its own.
+    pub fn with_builtins() -> Self {
+        let mut registry = Self::default();
+        for (name, f) in [
+            ("sqrt", f64::sqrt as fn(f64) -> f64),
+            ("sin", f64::sin),
+            ("cos", f64::cos),
+            ("tan", f64::tan),
+            ("asin", f64::asin),
+            ("acos", f64::acos),
+            ("atan", f64::atan),
+            ("exp", f64::exp),
+            ("log10", f64::log10),
+        ] {
+            registry.register(name, Arity::exact(1), move |args| {
+                Ok(Value::Num(f(args[0].as_num()?)))
+            });
+        }
+        for (name, f) in [
+            ("atan2", f64::atan2 as fn(f64, f64) -> f64),
+            ("pow", f64::powf),
+        ] {
+            registry.register(name, Arity::exact(2), move |args| {
+                Ok(Value::Num(f(args[0].as_num()?, args[1].as_num()?)))
+            });
+        }
+        // The natural logarithm, or the logarithm to the given base.
+        registry.register("log", Arity::between(1, 2), |args| {
+            let x = args[0].as_num()?;
+            Ok(Value::Num(match args.get(1) {
+                Some(base) => x.log(base.as_num()?),
+                None => x.ln(),
+            }))
+        });
+        registry.register("min", Arity::at_least(1), |args| {
+            let (values, dim) = same_dim(args)?;
+            let min = values.into_iter().fold(f64::INFINITY, f64::min);
+            Ok(Value::quantity(min, dim))
+        });
+        registry.register("max", Arity::at_least(1), |args| {
+            let (values, dim) = same_dim(args)?;
+            let max = values.into_iter().fold(f64::NEG_INFINITY, f64::max);
+            Ok(Value::quantity(max, dim))
+        });
+        registry.register("clamp", Arity::exact(3), |args| {
+            let (values, dim) = same_dim(args)?;
+            let [x, min, max] = values[..] else {
+                unreachable!("the arity has been checked")
+            };
+            Ok(Value::quantity(x.max(min).min(max), dim))
+        });
+        registry.register("hypot", Arity::at_least(1), |args| {
+            let (values, dim) = same_dim(args)?;
+            let sum: f64 = values.iter().map(|v| v * v).sum();
+            Ok(Value::quantity(sum.sqrt(), dim))
+        });
+        registry.register("vec3", Arity::exact(3), |args| {
+            let (values, dim) = same_dim(args)?;
+            let [x, y, z] = values[..] else {
+                unreachable!("the arity has been checked")
+            };
+            Ok(Value::Vec3([x, y, z], dim))
+        });
+        registry.register("rgb", Arity::exact(3), |args| rgba(args, 1.));
+        registry.register("rgba", Arity::exact(4), |args| {
+            rgba(&args[..3], args[3].as_num()?)
+        });
+        registry
+    }
+
+    /// The registry used when none is given.
+    pub fn builtins() -> &'static Self {
+        static BUILTINS: OnceLock<FunctionRegistry> = OnceLock::new();
+        BUILTINS.get_or_init(Self::with_builtins)
+    }
+}
+
+/// Returns the magnitudes of arguments that must all have the same
+/// dimension, and that dimension.
+fn same_dim(args: &[Value]) -> Result<(Vec<f64>, Dim), EvalError<'static>> {
+    let mut dim = None;
+    let mut values = vec![];
+    for arg in args {
+        let (value, found) = arg.as_quantity()?;
+        match dim {
+            Some(expected) if expected != found => {
+                return Err(EvalError::DimensionMismatch {
+                    expected,
+                    found,
+                    at: None,
+                })
+            }
+            _ => dim = Some(found),
+        }
+        values.push(value);
+    }
+    Ok((values, dim.unwrap_or(Dim::NONE)))
+}
+
+/// Returns the color with red, green and blue from 0 to 255 and an opacity
+/// from 0 to 1, each clamped to its range as in CSS.
+fn rgba(channels: &[Value], alpha: f64) -> Result<Value, EvalError<'static>> {
+    let to_u8 = |x: f64| x.round().clamp(0., 255.) as u8;
+    let mut rgb = [0; 3];
+    for (channel, value) in rgb.iter_mut().zip(channels) {
+        *channel = to_u8(value.as_num()?);
+    }
+    let [r, g, b] = rgb;
+    Ok(Value::Color(Color::rgba(r, g, b, to_u8(alpha * 255.))))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn call(name: &str, args: &[Value]) -> Result<Value, EvalError<'static>> {
+        let function = FunctionRegistry::builtins().get(name).unwrap();
+        assert!(function.arity.accepts(args.len()));
+        (function.f)(args)
+    }
+
+    #[test]
+    fn test_arity() {
+        assert!(Arity::at_least(1).accepts(5));
+        assert!(!Arity::at_least(1).accepts(0));
+        assert!(!Arity::between(1, 2).accepts(3));
+        assert_eq!(Arity::exact(1).to_string(), "1 argument");
+        assert_eq!(Arity::exact(3).to_string(), "3 arguments");
+        assert_eq!(Arity::between(1, 2).to_string(), "1 or 2 arguments");
+        assert_eq!(Arity::between(1, 4).to_string(), "1 to 4 arguments");
+        assert_eq!(Arity::at_least(1).to_string(), "at least 1 argument");
+    }
+
+    #[test]
+    fn test_builtins() {
+        let num = Value::Num;
+        let e = std::f64::consts::E;
+        assert_eq!(call("log", &[num(e)]), Ok(num(1.)));
+        assert_eq!(call("log", &[num(8.), num(2.)]), Ok(num(3.)));
+        assert_eq!(call("min", &[num(3.), num(1.), num(2.)]), Ok(num(1.)));
+        assert_eq!(call("max", &[num(3.)]), Ok(num(3.)));
+        assert_eq!(call("clamp", &[num(5.), num(0.), num(1.)]), Ok(num(1.)));
+        assert_eq!(call("hypot", &[num(3.), num(4.)]), Ok(num(5.)));
+        let au = |v| Value::Quantity(v, Dim::LENGTH);
+        assert_eq!(call("max", &[au(1.), au(2.)]), Ok(au(2.)));
+        assert_eq!(
+            call("min", &[au(1.), num(2.)]),
+            Err(EvalError::DimensionMismatch {
+                expected: Dim::LENGTH,
+                found: Dim::NONE,
+                at: None
+            })
+        );
+        assert_eq!(
+            call("vec3", &[au(1.), au(0.), au(-1.)]),
+            Ok(Value::Vec3([1., 0., -1.], Dim::LENGTH))
+        );
+        assert_eq!(
+            call("rgb", &[num(255.), num(136.), num(-3.)]),
+            Ok(Value::Color(Color::rgba(255, 136, 0, 255)))
+        );
+        assert_eq!(
+            call("rgba", &[num(0.), num(255.), num(0.), num(0.5)]),
+            Ok(Value::Color(Color::rgba(0, 255, 0, 128)))
+        );
+        assert_eq!(
+            call("sin", &[Value::Bool(true)]),
+            Err(EvalError::TypeMismatch {
+                expected: "number",
+                found: "boolean",
+                at: None
+            })
+        );
+    }
+
+    #[test]
+    fn test_register() {
+        let mut registry = FunctionRegistry::with_builtins();
+        registry.register("double", Arity::exact(1), |args| {
+            Ok(Value::Num(args[0].as_num()? * 2.))
+        });
+        let function = registry.get("double").unwrap();
+        assert_eq!((function.f)(&[Value::Num(2.)]), Ok(Value::Num(4.)));
+        assert!(registry.contains("sqrt"));
+        assert!(!FunctionRegistry::builtins().contains("double"));
+    }
+}
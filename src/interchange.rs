@@ -0,0 +1,369 @@
+use crate::{
+    compile::literal,
+    format::format_commands,
+    parser::{escape, expr, Arg, Command, Expression, Property, Value},
+    scene::{load_scene, BodySpec, Scene},
+    sources::{read_file, Sources},
+    units::Dim,
+};
+
+/// The file formats a scene can be read from and written to, chosen by the
+/// extension of the file name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum SceneFormat {
+    /// The scene language, as in `assets/sol.txt`.
+    Dsl,
+    Json,
+    Toml,
+    Ron,
+}
+
+impl SceneFormat {
+    /// Files with an extension other than `.json`, `.toml` or `.ron` are
+    /// taken to be in the scene language.
+    pub fn of(path: &str) -> Self {
+        let extension = path
+            .rsplit_once('.')
+            .map_or("", |(_, extension)| extension)
+            .to_ascii_lowercase();
+        match extension.as_str() {
+            "json" => Self::Json,
+            "toml" => Self::Toml,
+            "ron" => Self::Ron,
+            _ => Self::Dsl,
+        }
+    }
+}
+
+/// Reads a scene from a file in any of the supported formats. Problems in
+/// a scene language file are printed as diagnostics, and only a file that
+/// cannot be read or deserialized is an error.
+pub(crate) async fn load_scene_file(path: &str) -> Result<Scene, String> {
+    let format = SceneFormat::of(path);
+    if format == SceneFormat::Dsl {
+        let sources = Sources::load(path).await?;
+        let mut diagnostics = sources.diagnostics();
+        let scene = {
+            let commands = sources.parse(&mut diagnostics);
+            load_scene(&commands, &mut diagnostics)
+        };
+        diagnostics.emit();
+        return Ok(scene);
+    }
+    let text = read_file(path)
+        .await
+        .map_err(|e| format!("cannot read `{path}`: {e}"))?;
+    deserialize(&text, format).map_err(|e| format!("cannot load `{path}`: {e}"))
+}
+
+/// Reads a scene from JSON, TOML or RON, in which the bodies are listed
+/// with the same properties as in an `astro` block, in internal units.
+pub(crate) fn deserialize(
+    text: &str,
+    format: SceneFormat,
+) -> Result<Scene, String> {
+    let mut scene: Scene = match format {
+        SceneFormat::Json => {
+            serde_json::from_str(text).map_err(|e| e.to_string())
+        }
+        SceneFormat::Toml => toml::from_str(text).map_err(|e| e.to_string()),
+        SceneFormat::Ron => ron::from_str(text).map_err(|e| e.to_string()),
+        SceneFormat::Dsl => {
+            Err("the scene language is loaded from files".to_owned())
+        }
+    }?;
+    scene.validate()?;
+    scene.derive_periods();
+    Ok(scene)
+}
+
+/// Writes a scene in any of the supported formats.
+pub(crate) fn serialize(
+    scene: &Scene,
+    format: SceneFormat,
+) -> Result<String, String> {
+    match format {
+        SceneFormat::Dsl => {
+            let mut names = vec![];
+            written_names(&scene.bodies, &mut names);
+            Ok(format_commands(&scene_commands(scene, &names)))
+        }
+        SceneFormat::Json => {
+            serde_json::to_string_pretty(scene).map_err(|e| e.to_string())
+        }
+        SceneFormat::Toml => {
+            toml::to_string_pretty(scene).map_err(|e| e.to_string())
+        }
+        SceneFormat::Ron => {
+            ron::ser::to_string_pretty(scene, ron::ser::PrettyConfig::new())
+                .map_err(|e| e.to_string())
+        }
+    }
+}
+
+/// Returns the `astro` commands that load as `scene`, with the names of
+/// the bodies as written in `names`, from [`written_names`].
+fn scene_commands<'a>(
+    scene: &'a Scene,
+    names: &'a [String],
+) -> Vec<Command<'a>> {
+    let mut names = names.iter();
+    let bodies = scene.bodies.iter();
+    bodies.map(|body| body_command(body, &mut names)).collect()
+}
+
+/// Appends the names of `bodies` and their descendants in the order
+/// [`body_command`] writes them, with the escapes needed between quotes
+/// for names that are not identifiers.
+fn written_names(bodies: &[BodySpec], names: &mut Vec<String>) {
+    for body in bodies {
+        names.push(escape(&body.name));
+        written_names(&body.children, names);
+    }
+}
+
+fn body_command<'a>(
+    body: &'a BodySpec,
+    names: &mut std::slice::Iter<'a, String>,
+) -> Command<'a> {
+    let written = names.next().expect("a name for every body");
+    let num = |name, value| {
+        // Properties that change over time keep their expressions.
+        let ex = match body.animated.get(name).map(|source| expr(source)) {
+            Some(Ok((_, ex))) => ex,
+            _ => Expression::NumLiteral(value),
+        };
+        Command::Prop(name, Property::Expr(ex))
+    };
+    let mut block = vec![
+        num("radius", body.radius),
+        num("semimajor_axis", body.semimajor_axis),
+    ];
+    for (name, value) in [("mass", body.mass), ("gm", body.gm)] {
+        if let Some(value) = value {
+            block.push(num(name, value));
+        }
+    }
+    // The elements of circular orbits in the reference plane are left out.
+    for (name, value) in [
+        ("eccentricity", body.eccentricity),
+        ("inclination", body.inclination),
+        (
+            "longitude_of_ascending_node",
+            body.longitude_of_ascending_node,
+        ),
+        ("argument_of_periapsis", body.argument_of_periapsis),
+        ("mean_anomaly_at_epoch", body.mean_anomaly_at_epoch),
+    ] {
+        if value != 0. || body.animated.contains_key(name) {
+            block.push(num(name, value));
+        }
+    }
+    if let Some(period) = body.orbit_period {
+        block.push(num("orbit_period", period));
+    }
+    if let Some(period) = body.rotation_period {
+        block.push(num("rotation_period", period));
+    }
+    for (name, value) in [
+        ("obliquity", Some(body.obliquity)),
+        ("spin_axis_right_ascension", body.spin_axis_right_ascension),
+        ("spin_axis_declination", body.spin_axis_declination),
+        ("prime_meridian", Some(body.prime_meridian)),
+        ("precession_period", body.precession_period),
+        ("laplace_radius", body.laplace_radius),
+    ] {
+        match value {
+            Some(value) if value != 0. || body.animated.contains_key(name) => {
+                block.push(num(name, value))
+            }
+            _ => (),
+        }
+    }
+    if let Some(plane) = body.reference_plane {
+        let word = Expression::Ident(plane.name());
+        block.push(Command::Prop("reference_plane", Property::Expr(word)));
+    }
+    if let Some(texture) = &body.texture {
+        block.push(Command::Prop("texture", Property::Str(texture.clone())));
+    }
+    for (name, set) in
+        [("star", body.star), ("tilt_children", body.tilt_children)]
+    {
+        if set {
+            block.push(Command::Prop(
+                name,
+                Property::Expr(Expression::BoolLiteral(true)),
+            ));
+        }
+    }
+    // Colors and vectors are written with `rgb`, `rgba` and `vec3`.
+    let mut value = |name, value| {
+        let ex = literal(value, None).expect("not a string");
+        block.push(Command::Prop(name, Property::Expr(ex)));
+    };
+    if let Some(color) = body.orbit_color {
+        value("orbit_color", Value::Color(color));
+    }
+    if let Some(axis) = body.spin_axis {
+        value("spin_axis", Value::Vec3(axis, Dim::NONE));
+    }
+    if !body.ring_colors.is_empty() {
+        let colors = body.ring_colors.iter().copied().map(Value::Color);
+        value("ring_colors", Value::List(colors.collect()));
+    }
+    let children = body.children.iter();
+    block.extend(children.map(|child| body_command(child, names)));
+    let is_identifier = body.name.starts_with(|c: char| c.is_alphabetic())
+        && body.name.chars().all(|c| c.is_alphanumeric() || c == '_');
+    let name = if is_identifier {
+        Arg::Str(&body.name)
+    } else {
+        Arg::Quoted(written)
+    };
+    Command::Com(vec![Arg::Str("astro"), name, Arg::Block(block)])
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{diagnostics::Diagnostics, parser::parse_scene};
+
+    /// Loads a scene from the scene language, which must have no errors.
+    fn load(source: &str) -> Scene {
+        let mut diagnostics = Diagnostics::new("test.txt", source);
+        let commands = parse_scene(source, &mut diagnostics);
+        let scene = load_scene(&commands, &mut diagnostics);
+        assert_eq!(diagnostics.error_count(), 0);
+        scene
+    }
+
+    fn sol() -> Scene {
+        load(include_str!("../assets/sol.txt"))
+    }
+
+    /// Checks that `scene` comes back the same from every format.
+    fn assert_round_trip(scene: &Scene) {
+        for format in [SceneFormat::Json, SceneFormat::Toml, SceneFormat::Ron] {
+            let text = serialize(scene, format).unwrap();
+            assert_eq!(deserialize(&text, format).as_ref(), Ok(scene));
+        }
+        let source = serialize(scene, SceneFormat::Dsl).unwrap();
+        let mut diagnostics = Diagnostics::new("export.txt", &source);
+        let commands = parse_scene(&source, &mut diagnostics);
+        assert_eq!(&load_scene(&commands, &mut diagnostics), scene);
+        assert_eq!(diagnostics.error_count(), 0);
+    }
+
+    #[test]
+    fn test_format_of() {
+        assert_eq!(SceneFormat::of("assets/sol.txt"), SceneFormat::Dsl);
+        assert_eq!(SceneFormat::of("catalog.JSON"), SceneFormat::Json);
+        assert_eq!(SceneFormat::of("a.b/scene.toml"), SceneFormat::Toml);
+        assert_eq!(SceneFormat::of("scene.ron"), SceneFormat::Ron);
+        assert_eq!(SceneFormat::of("scene"), SceneFormat::Dsl);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        assert_round_trip(&sol());
+    }
+
+    #[test]
+    fn test_animated_round_trip() {
+        let scene = load(
+            "fn pulse(p) = sin(2 * pi * t / p)
+            astro Mira {
+                radius: 0.2 AU * (1 + 0.3 * pulse(332 d))
+                semimajor_axis: 2
+            }",
+        );
+        assert!(scene.bodies[0].animated.contains_key("radius"));
+        assert_round_trip(&scene);
+    }
+
+    #[test]
+    fn test_quoted_names_round_trip() {
+        let scene = load(
+            r#"astro "Say \"hi\"" {
+                texture: "C:\\moons\\\"io\".png"
+                astro "Cost \${x}" {}
+                astro "Rock 1" {}
+            }"#,
+        );
+        let names: Vec<_> = scene.bodies[0]
+            .children
+            .iter()
+            .map(|body| body.name.as_str())
+            .collect();
+        assert_eq!(scene.bodies[0].name, r#"Say "hi""#);
+        assert_eq!(names, ["Cost ${x}", "Rock 1"]);
+        assert_eq!(
+            scene.bodies[0].texture.as_deref(),
+            Some(r#"C:\moons\"io".png"#)
+        );
+        assert_round_trip(&scene);
+    }
+
+    #[test]
+    fn test_value_types_round_trip() {
+        let scene = load(
+            "astro Saturn {
+                orbit_color: #e8d8a0
+                spin_axis: vec3(0.5, 1, 0)
+                ring_colors: [#6b5e4a80, #c8b89a]
+            }",
+        );
+        let json = serialize(&scene, SceneFormat::Json).unwrap();
+        assert!(json.contains(r##""orbit_color": "#e8d8a0""##), "{json}");
+        assert_round_trip(&scene);
+    }
+
+    #[test]
+    fn test_deserialize() {
+        let scene = deserialize(
+            r#"
+[[bodies]]
+name = "Sun"
+radius = 0.2
+mass = 1
+star = true
+
+[[bodies.children]]
+name = "Comet 1P"
+semimajor_axis = 17.8
+orbit_period = 27510
+
+[[bodies.children]]
+name = "Earth"
+"#,
+            SceneFormat::Toml,
+        )
+        .unwrap();
+        let sun = &scene.bodies[0];
+        assert_eq!(sun.radius, 0.2);
+        assert!(sun.star);
+        let comet = &sun.children[0];
+        assert_eq!(comet.name, "Comet 1P");
+        // Missing properties have the same defaults as in `astro` blocks.
+        assert_eq!(comet.radius, BodySpec::default().radius);
+        assert_eq!(comet.orbit_period, Some(27510.));
+        assert_eq!(comet.rotation_period, None);
+        // Periods that are unset follow from the masses.
+        let earth = &sun.children[1];
+        assert!((earth.orbit_period.unwrap() - 365.2569).abs() < 1e-3);
+
+        let error = deserialize(
+            r#"{"bodies": [{"name": "Sun", "radius": "big"}]}"#,
+            SceneFormat::Json,
+        )
+        .unwrap_err();
+        assert!(error.contains("invalid type"), "{error}");
+        let error = deserialize(
+            r#"(bodies: [(name: "Sun", raidus: 0.2)])"#,
+            SceneFormat::Ron,
+        )
+        .unwrap_err();
+        assert!(error.contains("raidus"), "{error}");
+    }
+}
@@ -0,0 +1,439 @@
+use std::f64::consts::{PI, TAU};
+
+/// The shape, orientation and phase of an orbit around the parent body, in
+/// AU and radians. The reference plane is the xy plane of the parent, with
+/// x the reference direction and z its north.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Elements {
+    /// For open trajectories, with an eccentricity of at least 1, the
+    /// distance of the periapsis instead, as their semimajor axis is
+    /// infinite or negative.
+    pub semimajor_axis: f64,
+    pub eccentricity: f64,
+    pub inclination: f64,
+    pub longitude_of_ascending_node: f64,
+    pub argument_of_periapsis: f64,
+    /// The mean anomaly at `t = 0`.
+    pub mean_anomaly_at_epoch: f64,
+}
+
+impl Elements {
+    /// Returns the position at mean anomaly `mean_anomaly`, relative to
+    /// the parent.
+    pub fn position(&self, mean_anomaly: f64) -> [f64; 3] {
+        self.rotate(self.in_plane(self.anomaly(mean_anomaly)))
+    }
+
+    /// Returns the eccentric anomaly of an ellipse, the hyperbolic anomaly
+    /// of a hyperbola or `tan(ν / 2)` of a parabola, with `ν` the true
+    /// anomaly, at mean anomaly `mean_anomaly`.
+    pub fn anomaly(&self, mean_anomaly: f64) -> f64 {
+        let e = self.eccentricity;
+        if e < 1. {
+            eccentric_anomaly(mean_anomaly, e)
+        } else if e == 1. {
+            parabolic_anomaly(mean_anomaly)
+        } else {
+            hyperbolic_anomaly(mean_anomaly, e)
+        }
+    }
+
+    /// Returns the point at `anomaly`, as returned by [`Self::anomaly`], in
+    /// the plane of the orbit with x towards the periapsis.
+    pub fn in_plane(&self, anomaly: f64) -> [f64; 2] {
+        let e = self.eccentricity;
+        if e < 1. {
+            let a = self.semimajor_axis;
+            let b = a * (1. - e * e).sqrt();
+            [a * (anomaly.cos() - e), b * anomaly.sin()]
+        } else if e == 1. {
+            let q = self.semimajor_axis;
+            [q * (1. - anomaly * anomaly), 2. * q * anomaly]
+        } else {
+            let a = self.semimajor_axis / (e - 1.);
+            let b = a * (e * e - 1.).sqrt();
+            [a * (e - anomaly.cosh()), b * anomaly.sinh()]
+        }
+    }
+
+    /// Returns the anomaly, as returned by [`Self::anomaly`], at which an
+    /// open trajectory leaves `distance` from the parent, or 0 if it never
+    /// comes that close.
+    pub fn anomaly_at_distance(&self, distance: f64) -> f64 {
+        let e = self.eccentricity;
+        // Open trajectories keep their periapsis distance in
+        // `semimajor_axis`.
+        let q = self.semimajor_axis;
+        if e == 1. {
+            (distance / q - 1.).max(0.).sqrt()
+        } else {
+            let a = q / (e - 1.);
+            ((distance / a + 1.) / e).max(1.).acosh()
+        }
+    }
+
+    /// Turns a point of the plane of the orbit, with x towards the
+    /// periapsis, into the reference frame.
+    fn rotate(&self, [x, y]: [f64; 2]) -> [f64; 3] {
+        let (sin_node, cos_node) = self.longitude_of_ascending_node.sin_cos();
+        let (sin_i, cos_i) = self.inclination.sin_cos();
+        let (sin_w, cos_w) = self.argument_of_periapsis.sin_cos();
+        // Towards the periapsis, then tilted about the line of nodes, then
+        // turned to the ascending node.
+        let (x, y) = (cos_w * x - sin_w * y, sin_w * x + cos_w * y);
+        let (y, z) = (cos_i * y, sin_i * y);
+        [cos_node * x - sin_node * y, sin_node * x + cos_node * y, z]
+    }
+}
+
+/// Returns the period of an orbit around a gravitational parameter `gm` by
+/// Kepler's third law, with `semimajor_axis` as in [`Elements`]. For open
+/// trajectories, the time in which the mean anomaly grows by 2π.
+pub(crate) fn orbit_period(
+    gm: f64,
+    semimajor_axis: f64,
+    eccentricity: f64,
+) -> f64 {
+    let e = eccentricity;
+    // The mean motion is `sqrt(gm / cube)`.
+    let cube = if e < 1. {
+        semimajor_axis.powi(3)
+    } else if e == 1. {
+        2. * semimajor_axis.powi(3)
+    } else {
+        (semimajor_axis / (e - 1.)).powi(3)
+    };
+    TAU * (cube / gm).sqrt()
+}
+
+/// The angle between the equator of the Earth and the ecliptic at J2000, in
+/// radians, from the IAU 2006 precession model.
+const OBLIQUITY_J2000: f64 = 84_381.406 / 3600. * PI / 180.;
+
+/// Returns the direction at right ascension `right_ascension` and
+/// declination `declination` in the frame of the ecliptic of J2000, with x
+/// towards the March equinox.
+pub(crate) fn equatorial_to_ecliptic(
+    right_ascension: f64,
+    declination: f64,
+) -> [f64; 3] {
+    let (sin_ra, cos_ra) = right_ascension.sin_cos();
+    let (sin_dec, cos_dec) = declination.sin_cos();
+    let [x, y, z] = [cos_dec * cos_ra, cos_dec * sin_ra, sin_dec];
+    // The equator is tilted about the equinox away from the ecliptic.
+    let (sin_e, cos_e) = OBLIQUITY_J2000.sin_cos();
+    [x, cos_e * y + sin_e * z, -sin_e * y + cos_e * z]
+}
+
+/// Newton's method converges in a handful of steps from the starting points
+/// below, so this is only reached by bisection near `e = 1`.
+const MAX_ITERATIONS: usize = 100;
+
+/// Solves Kepler's equation `E - e sin E = M` for the eccentric anomaly
+/// `E` of an elliptic orbit, with `e` from 0 up to but excluding 1.
+pub(crate) fn eccentric_anomaly(mean_anomaly: f64, eccentricity: f64) -> f64 {
+    let e = eccentricity;
+    // `E - M` has a period of 2π, so the equation is solved for `M` in
+    // [-π, π], where the root is in [-π, π] too.
+    let turns = (mean_anomaly / TAU).round() * TAU;
+    let m = mean_anomaly - turns;
+    // Danby's starting point, which is close even for almost parabolic
+    // orbits.
+    let guess = m + 0.85 * e * m.signum();
+    let anomaly = solve(
+        |anomaly| {
+            let (sin, cos) = anomaly.sin_cos();
+            (anomaly - e * sin - m, 1. - e * cos)
+        },
+        guess,
+        (-PI, PI),
+    );
+    anomaly + turns
+}
+
+/// Solves Kepler's equation `e sinh H - H = M` for the hyperbolic anomaly
+/// `H` of a hyperbolic trajectory, with `e` greater than 1.
+pub(crate) fn hyperbolic_anomaly(mean_anomaly: f64, eccentricity: f64) -> f64 {
+    let e = eccentricity;
+    // The equation is odd, so it is solved for `|M|`, where the root is
+    // positive and below where `(e - 1) sinh H` or `H³ / 6`, both less
+    // than `e sinh H - H`, reach `|M|`.
+    let m = mean_anomaly.abs();
+    let high = (m / (e - 1.)).asinh().min((6. * m).cbrt());
+    // Far from the periapsis `e sinh H` grows as `e exp(H) / 2`.
+    let guess = (2. * m / e + 1.8).ln();
+    let anomaly = solve(
+        |anomaly| {
+            let f = e * anomaly.sinh() - anomaly - m;
+            (f, e * anomaly.cosh() - 1.)
+        },
+        guess,
+        (0., high),
+    );
+    anomaly.copysign(mean_anomaly)
+}
+
+/// Solves Barker's equation `D + D³ / 3 = M` for `D = tan(ν / 2)` of a
+/// parabolic trajectory, with `ν` the true anomaly.
+pub(crate) fn parabolic_anomaly(mean_anomaly: f64) -> f64 {
+    // The cubic has a single real root, which `sinh 3x = 3 sinh x +
+    // 4 sinh³ x` gives in closed form.
+    2. * ((1.5 * mean_anomaly).asinh() / 3.).sinh()
+}
+
+/// Finds the root of an increasing function, which returns its value and
+/// derivative, between `low` and `high` by Newton's method from `guess`.
+fn solve(
+    f: impl Fn(f64) -> (f64, f64),
+    guess: f64,
+    (mut low, mut high): (f64, f64),
+) -> f64 {
+    let mut x = guess.clamp(low, high);
+    for _ in 0..MAX_ITERATIONS {
+        let (y, slope) = f(x);
+        if y == 0. {
+            break;
+        }
+        // The sign of `y` tells which side the root is.
+        if y < 0. {
+            low = x;
+        } else {
+            high = x;
+        }
+        let mut next = x - y / slope;
+        // Near the periapsis of an almost parabolic orbit the derivative
+        // nearly vanishes and a step can overshoot, so bisect instead.
+        if !(low < next && next < high) {
+            next = 0.5 * (low + high);
+        }
+        if (next - x).abs() <= f64::EPSILON * x.abs().max(1.) {
+            return next;
+        }
+        x = next;
+    }
+    x
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn distance([x, y, z]: [f64; 3]) -> f64 {
+        (x * x + y * y + z * z).sqrt()
+    }
+
+    fn assert_close(a: [f64; 3], b: [f64; 3]) {
+        let error = distance([a[0] - b[0], a[1] - b[1], a[2] - b[2]]);
+        assert!(error < 1e-9, "{a:?} != {b:?}");
+    }
+
+    #[test]
+    fn test_eccentric_anomaly() {
+        for e in [0., 0.0167, 0.2056, 0.5, 0.9, 0.967, 0.999, 0.999_999] {
+            for i in -40..=40 {
+                let m = i as f64 * 0.37;
+                let anomaly = eccentric_anomaly(m, e);
+                let residual = anomaly - e * anomaly.sin() - m;
+                assert!(residual.abs() < 1e-12, "e = {e}, M = {m}: {residual}");
+                // The anomaly turns along with the mean anomaly.
+                assert!((anomaly - m).abs() <= PI, "e = {e}, M = {m}");
+            }
+        }
+        // A value found independently.
+        assert!(
+            (eccentric_anomaly(1., 0.5) - 1.498_701_133_517_848).abs() < 1e-12
+        );
+        // The periapsis and apoapsis.
+        assert!(eccentric_anomaly(0., 0.999_999).abs() < 1e-12);
+        assert!((eccentric_anomaly(PI, 0.999_999) - PI).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_position() {
+        let orbit = Elements {
+            semimajor_axis: 2.,
+            eccentricity: 0.5,
+            inclination: 0.,
+            longitude_of_ascending_node: 0.,
+            argument_of_periapsis: 0.,
+            mean_anomaly_at_epoch: 0.,
+        };
+        // The periapsis is towards x, and the body moves towards y.
+        assert_close(orbit.position(0.), [1., 0., 0.]);
+        assert_close(orbit.position(PI), [-3., 0., 0.]);
+        assert!(orbit.position(0.1)[1] > 0.);
+
+        // A polar orbit with the periapsis at its northernmost point.
+        let polar = Elements {
+            inclination: PI / 2.,
+            argument_of_periapsis: PI / 2.,
+            longitude_of_ascending_node: PI / 2.,
+            ..orbit
+        };
+        assert_close(polar.position(0.), [0., 0., 1.]);
+        assert_close(polar.position(PI), [0., 0., -3.]);
+
+        // The orbit turns about its normal, which is tilted by the
+        // inclination away from the ascending node.
+        let tilted = Elements {
+            inclination: 0.3,
+            longitude_of_ascending_node: 1.2,
+            argument_of_periapsis: 2.5,
+            ..orbit
+        };
+        let [x0, y0, z0] = tilted.position(0.4);
+        let [x1, y1, z1] = tilted.position(0.5);
+        let normal = [y0 * z1 - z0 * y1, z0 * x1 - x0 * z1, x0 * y1 - y0 * x1];
+        let length = distance(normal);
+        let normal = normal.map(|c| c / length);
+        let (sin_i, cos_i) = 0.3f64.sin_cos();
+        let (sin_node, cos_node) = 1.2f64.sin_cos();
+        assert_close(normal, [sin_i * sin_node, -sin_i * cos_node, cos_i]);
+        for m in [0., 1., 2., 3.] {
+            let r = distance(tilted.position(m));
+            let anomaly = eccentric_anomaly(m, 0.5);
+            assert!((r - 2. * (1. - 0.5 * anomaly.cos())).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn test_orbit_period() {
+        // With the Gaussian gravitational constant, an orbit of 1 AU
+        // around one solar mass takes a sidereal year, whatever its shape.
+        let k = 0.017_202_098_95;
+        for e in [0., 0.5, 0.99] {
+            let year = orbit_period(k * k, 1., e);
+            assert!((year - 365.256_898_3).abs() < 1e-6, "e = {e}");
+        }
+        let period = orbit_period(k * k, 4., 0.);
+        assert!((period / (8. * 365.256_898_3) - 1.).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_open_anomalies() {
+        for m in (-40..=40).map(|i| i as f64 * 0.37).chain([1e6, -1e12]) {
+            let tolerance = 1e-12 * m.abs().max(1.);
+            for e in [1. + 1e-9, 1.000_001, 1.01, 1.2, 2.77, 10., 1000.] {
+                let anomaly = hyperbolic_anomaly(m, e);
+                let residual = e * anomaly.sinh() - anomaly - m;
+                assert!(residual.abs() < tolerance, "e = {e}, M = {m}");
+            }
+            let anomaly = parabolic_anomaly(m);
+            let residual = anomaly + anomaly.powi(3) / 3. - m;
+            assert!(residual.abs() < tolerance, "M = {m}: {residual}");
+        }
+        assert_eq!(hyperbolic_anomaly(0., 1.5), 0.);
+        assert_eq!(parabolic_anomaly(0.), 0.);
+    }
+
+    /// Returns the position and velocity at `t` from the periapsis, with
+    /// the velocity from the change of the position around it.
+    fn state(
+        orbit: &Elements,
+        mean_motion: f64,
+        t: f64,
+    ) -> ([f64; 3], [f64; 3]) {
+        let h = 1e-6 * (1. / mean_motion).max(t.abs());
+        let [x0, y0, z0] = orbit.position(mean_motion * (t - h));
+        let [x1, y1, z1] = orbit.position(mean_motion * (t + h));
+        let velocity = [x1 - x0, y1 - y0, z1 - z0].map(|d| d / (2. * h));
+        (orbit.position(mean_motion * t), velocity)
+    }
+
+    #[test]
+    fn test_open_trajectories() {
+        // Curtis, Orbital Mechanics for Engineering Students, example 3.5:
+        // a trajectory around the Earth with a periapsis 300 km above the
+        // ground at 15 km/s, in km and s.
+        let mu = 398_600.;
+        let (q, speed) = (6678., 15.);
+        let e = q * speed * speed / mu - 1.;
+        let hyperbola = Elements {
+            semimajor_axis: q,
+            eccentricity: e,
+            inclination: 0.,
+            longitude_of_ascending_node: 0.,
+            argument_of_periapsis: 0.,
+            mean_anomaly_at_epoch: 0.,
+        };
+        let a = q / (e - 1.);
+        let mean_motion = TAU / orbit_period(mu, q, e);
+        // At a true anomaly of 100°, the body is 48 497 km away.
+        let nu = 100f64.to_radians();
+        let anomaly =
+            2. * (((e - 1.) / (e + 1.)).sqrt() * (nu / 2.).tan()).atanh();
+        let t = (e * anomaly.sinh() - anomaly) / mean_motion;
+        let ([x, y, _], _) = state(&hyperbola, mean_motion, t);
+        assert!((x.hypot(y) - 48_497.).abs() < 1.);
+        assert!((y.atan2(x) - nu).abs() < 1e-12);
+        // Three hours later it is 163 180 km away at 107.78°, at
+        // 10.51 km/s.
+        let ([x, y, z], [vx, vy, vz]) =
+            state(&hyperbola, mean_motion, t + 3. * 3600.);
+        assert!((x.hypot(y) - 163_180.).abs() < 1.);
+        assert!((y.atan2(x).to_degrees() - 107.78).abs() < 0.005);
+        assert!((vx.hypot(vy) - 10.51).abs() < 0.005);
+        assert_eq!([z, vz], [0., 0.]);
+        // The energy and angular momentum are those of the periapsis.
+        let energy = (vx * vx + vy * vy) / 2. - mu / x.hypot(y);
+        assert!((energy - (speed * speed / 2. - mu / q)).abs() < 1e-6);
+        assert!(((x * vy - y * vx) / (q * speed) - 1.).abs() < 1e-6);
+
+        // Far away the body moves along an asymptote, and keeps the
+        // energy of the periapsis.
+        let asymptote = (-1. / e).acos();
+        for t in [1e7, 1e12] {
+            let ([x, y, _], [vx, vy, _]) = state(&hyperbola, mean_motion, t);
+            assert!((y.atan2(x) - asymptote).abs() < 1e-3, "t = {t}");
+            let vis_viva = mu * (2. / x.hypot(y) + 1. / a);
+            assert!((vx.hypot(vy) / vis_viva.sqrt() - 1.).abs() < 1e-6);
+            let ([x, y, _], _) = state(&hyperbola, mean_motion, -t);
+            assert!((y.atan2(x) + asymptote).abs() < 1e-3, "t = {t}");
+        }
+
+        // A parabola with the same periapsis escapes at exactly the escape
+        // speed, and is twice as far at a true anomaly of 90°.
+        let parabola = Elements {
+            eccentricity: 1.,
+            ..hyperbola
+        };
+        let mean_motion = TAU / orbit_period(mu, q, 1.);
+        let (position, [vx, vy, _]) =
+            state(&parabola, mean_motion, 4. / 3. / mean_motion);
+        assert_close(position, [0., 2. * q, 0.]);
+        assert!((vx.hypot(vy) - (mu / q).sqrt()).abs() < 1e-6);
+        let ([x, y, _], [vx, vy, _]) = state(&parabola, mean_motion, 0.);
+        assert_eq!([x, y], [q, 0.]);
+        assert!((vy - (2. * mu / q).sqrt()).abs() < 1e-6 && vx.abs() < 1e-6);
+        for t in [1e7, 1e12] {
+            let ([x, y, _], [vx, vy, _]) = state(&parabola, mean_motion, t);
+            let escape = (2. * mu / x.hypot(y)).sqrt();
+            assert!((vx.hypot(vy) / escape - 1.).abs() < 1e-6, "t = {t}");
+        }
+
+        // Both leave a given distance where they reach it.
+        for orbit in [hyperbola, parabola] {
+            let anomaly = orbit.anomaly_at_distance(10. * q);
+            let [x, y] = orbit.in_plane(anomaly);
+            assert!((x.hypot(y) / q - 10.).abs() < 1e-9);
+            assert_eq!(orbit.anomaly_at_distance(q / 2.), 0.);
+        }
+    }
+
+    #[test]
+    fn test_equatorial_to_ecliptic() {
+        let deg = f64::to_radians;
+        // The equinox, and the Sun at the June solstice.
+        assert_close(equatorial_to_ecliptic(0., 0.), [1., 0., 0.]);
+        let solstice = equatorial_to_ecliptic(PI / 2., OBLIQUITY_J2000);
+        assert_close(solstice, [0., 1., 0.]);
+        // The north pole of the Earth leans towards the solstice.
+        let (sin_e, cos_e) = OBLIQUITY_J2000.sin_cos();
+        assert_close(equatorial_to_ecliptic(0., PI / 2.), [0., sin_e, cos_e]);
+        // The north pole of Uranus lies almost in the ecliptic. The IAU
+        // takes it on the north side, while Uranus turns the other way with
+        // an obliquity of 97.77°.
+        let [_, _, z] = equatorial_to_ecliptic(deg(257.311), deg(-15.175));
+        assert!((z.acos().to_degrees() - (180. - 97.77)).abs() < 1.);
+    }
+}
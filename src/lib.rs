@@ -1,9 +1,36 @@
 mod astro_body;
+#[cfg(not(target_arch = "wasm32"))]
+mod cli;
+mod color;
+mod compile;
+mod diagnostics;
+mod format;
+mod functions;
+mod interchange;
+mod kepler;
+#[cfg(not(target_arch = "wasm32"))]
+mod lsp;
 mod orbit_control_ex;
 mod parser;
 mod run;
+mod scene;
+mod schema;
+mod sources;
+mod units;
 // mod web_main;
 
+#[cfg(not(target_arch = "wasm32"))]
+pub use cli::run_cli;
+pub use color::Color;
+#[cfg(feature = "bench")]
+pub use compile::bench;
+pub use diagnostics::Diagnostics;
+pub use functions::{Arity, FunctionRegistry};
+pub use parser::{parse_scene, EvalError, Value};
+pub use run::run;
+pub use scene::{load_scene_with_functions, Scene};
+pub use units::Dim;
+
 // Entry point for wasm
 #[cfg(target_arch = "wasm32")]
 use wasm_bindgen::prelude::*;
@@ -13,27 +40,17 @@ use wasm_bindgen::prelude::*;
 pub async fn start() -> Result<(), JsValue> {
     console_log::init_with_level(log::Level::Debug).unwrap();
 
-    use crate::parser::commands;
+    use crate::interchange::load_scene_file;
     use log::info;
     info!("Logging works!");
 
     std::panic::set_hook(Box::new(console_error_panic_hook::hook));
 
-    fn map_err(e: impl std::error::Error) -> JsValue {
-        JsValue::from_str(&e.to_string())
-    }
-
-    let assets = three_d_asset::io::load_async(&["assets/sol.txt"])
+    let scene = load_scene_file("assets/sol.txt")
+        .await
+        .map_err(|e| JsValue::from_str(&e))?;
+    run::run(scene, FunctionRegistry::builtins())
         .await
-        .map_err(map_err)?;
-    let commands = {
-        let s =
-            std::str::from_utf8(assets.get("").unwrap()).map_err(map_err)?;
-        println!("source: {s:?}");
-        let (_, commands) = commands(&s).map_err(map_err)?;
-        println!("commands: {commands:#?}");
-        commands
-    };
-    run::run(commands).await;
+        .map_err(|e| JsValue::from_str(&e))?;
     Ok(())
 }
@@ -0,0 +1,803 @@
+use std::{collections::HashMap, error::Error};
+
+use lsp_server::{
+    Connection, ErrorCode, Message, Notification, Request, Response,
+};
+use lsp_types::{
+    notification::{
+        DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument,
+        Notification as _, PublishDiagnostics,
+    },
+    request::{
+        Completion, DocumentSymbolRequest, GotoDefinition, HoverRequest,
+        Request as _,
+    },
+    CompletionItem, CompletionItemKind, CompletionOptions, CompletionResponse,
+    DiagnosticSeverity, DocumentSymbol, DocumentSymbolResponse, Documentation,
+    GotoDefinitionResponse, Hover, HoverContents, HoverProviderCapability,
+    Location, MarkupContent, MarkupKind, OneOf, Position,
+    PublishDiagnosticsParams, Range, ServerCapabilities, SymbolKind,
+    TextDocumentSyncCapability, TextDocumentSyncKind, Url,
+};
+
+use crate::{
+    diagnostics::{Diagnostics, Level, Span},
+    functions::FunctionRegistry,
+    parser::{parse_scene, Arg, Command, Value},
+    scene::{load_scene_traced, parse_astro_command, parse_template_command},
+    schema::{
+        internal_unit, lookup, PropType, PropertySchema, BODY_PROPERTIES,
+    },
+    sources::Sources,
+};
+
+/// `rusty-space lsp` serves the language server protocol on standard input
+/// and output, so that editors can check scene files as they are edited.
+pub(crate) fn serve() -> Result<(), Box<dyn Error + 'static>> {
+    let (connection, io_threads) = Connection::stdio();
+    connection.initialize(serde_json::to_value(capabilities())?)?;
+    Server::default().run(&connection)?;
+    // The writer thread stops once the connection is dropped.
+    drop(connection);
+    io_threads.join()?;
+    Ok(())
+}
+
+fn capabilities() -> ServerCapabilities {
+    ServerCapabilities {
+        text_document_sync: Some(TextDocumentSyncCapability::Kind(
+            TextDocumentSyncKind::FULL,
+        )),
+        hover_provider: Some(HoverProviderCapability::Simple(true)),
+        definition_provider: Some(OneOf::Left(true)),
+        completion_provider: Some(CompletionOptions::default()),
+        document_symbol_provider: Some(OneOf::Left(true)),
+        ..Default::default()
+    }
+}
+
+/// The files open in the editor, with their unsaved contents.
+#[derive(Default)]
+struct Server {
+    documents: HashMap<Url, String>,
+}
+
+impl Server {
+    /// Handles messages until the client shuts the server down.
+    fn run(&mut self, connection: &Connection) -> Result<(), Box<dyn Error>> {
+        for message in &connection.receiver {
+            match message {
+                Message::Request(request) => {
+                    if connection.handle_shutdown(&request)? {
+                        return Ok(());
+                    }
+                    let response = self.request(request);
+                    connection.sender.send(Message::Response(response))?;
+                }
+                Message::Notification(notification) => {
+                    if let Some(published) = self.notification(notification) {
+                        connection.sender.send(Message::Notification(
+                            Notification::new(
+                                PublishDiagnostics::METHOD.to_owned(),
+                                published,
+                            ),
+                        ))?;
+                    }
+                }
+                Message::Response(_) => (),
+            }
+        }
+        Ok(())
+    }
+
+    /// Keeps track of the open files. Returns the diagnostics of a file
+    /// that was opened, changed or closed.
+    fn notification(
+        &mut self,
+        notification: Notification,
+    ) -> Option<PublishDiagnosticsParams> {
+        let uri = match notification.method.as_str() {
+            DidOpenTextDocument::METHOD => {
+                let params = params::<DidOpenTextDocument>(notification)?;
+                let document = params.text_document;
+                self.documents.insert(document.uri.clone(), document.text);
+                document.uri
+            }
+            DidChangeTextDocument::METHOD => {
+                let params = params::<DidChangeTextDocument>(notification)?;
+                // The whole text is sent on every change.
+                let text = params.content_changes.into_iter().last()?.text;
+                let uri = params.text_document.uri;
+                self.documents.insert(uri.clone(), text);
+                uri
+            }
+            DidCloseTextDocument::METHOD => {
+                let params = params::<DidCloseTextDocument>(notification)?;
+                let uri = params.text_document.uri;
+                self.documents.remove(&uri);
+                return Some(PublishDiagnosticsParams::new(uri, vec![], None));
+            }
+            _ => return None,
+        };
+        let text = &self.documents[&uri];
+        let diagnostics = analyze(&path_of(&uri), text)
+            .diagnostics
+            .into_iter()
+            .map(|(level, message, span)| lsp_types::Diagnostic {
+                range: range(text, span),
+                severity: Some(match level {
+                    Level::Error => DiagnosticSeverity::ERROR,
+                    Level::Warning => DiagnosticSeverity::WARNING,
+                }),
+                source: Some("rusty-space".to_owned()),
+                message,
+                ..Default::default()
+            })
+            .collect();
+        Some(PublishDiagnosticsParams::new(uri, diagnostics, None))
+    }
+
+    fn request(&self, request: Request) -> Response {
+        match request.method.clone().as_str() {
+            HoverRequest::METHOD => {
+                respond::<HoverRequest>(request, |params| {
+                    let at = params.text_document_position_params;
+                    let text = self.documents.get(&at.text_document.uri)?;
+                    let path = path_of(&at.text_document.uri);
+                    hover(&path, text, offset(text, at.position))
+                })
+            }
+            GotoDefinition::METHOD => {
+                respond::<GotoDefinition>(request, |params| {
+                    let at = params.text_document_position_params;
+                    let text = self.documents.get(&at.text_document.uri)?;
+                    let span = definition(text, offset(text, at.position))?;
+                    Some(GotoDefinitionResponse::Scalar(Location::new(
+                        at.text_document.uri,
+                        range(text, Some(span)),
+                    )))
+                })
+            }
+            Completion::METHOD => respond::<Completion>(request, |_| {
+                Some(CompletionResponse::Array(completions()))
+            }),
+            DocumentSymbolRequest::METHOD => {
+                respond::<DocumentSymbolRequest>(request, |params| {
+                    let text = self.documents.get(&params.text_document.uri)?;
+                    Some(DocumentSymbolResponse::Nested(symbols(text)))
+                })
+            }
+            method => Response::new_err(
+                request.id,
+                ErrorCode::MethodNotFound as i32,
+                format!("unsupported request `{method}`"),
+            ),
+        }
+    }
+}
+
+fn params<N: lsp_types::notification::Notification>(
+    notification: Notification,
+) -> Option<N::Params> {
+    serde_json::from_value(notification.params).ok()
+}
+
+/// Answers a request of type `R` with the result of `handle`.
+fn respond<R: lsp_types::request::Request>(
+    request: Request,
+    handle: impl FnOnce(R::Params) -> R::Result,
+) -> Response {
+    match serde_json::from_value(request.params) {
+        Ok(params) => Response::new_ok(request.id, handle(params)),
+        Err(e) => Response::new_err(
+            request.id,
+            ErrorCode::InvalidParams as i32,
+            e.to_string(),
+        ),
+    }
+}
+
+/// Returns the path of a file, which includes are resolved against.
+fn path_of(uri: &Url) -> String {
+    uri.to_file_path()
+        .ok()
+        .and_then(|path| path.to_str().map(str::to_owned))
+        .unwrap_or_else(|| uri.path().to_owned())
+}
+
+/// Returns the line and UTF-16 column of a byte offset, as the protocol
+/// counts them.
+fn position(text: &str, offset: usize) -> Position {
+    let before = &text[..offset];
+    let line = before.matches('\n').count();
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    let character = before[line_start..].encode_utf16().count();
+    Position::new(line as u32, character as u32)
+}
+
+/// Returns the byte offset of a position, or the end of its line if the
+/// line is shorter.
+fn offset(text: &str, position: Position) -> usize {
+    let line_start: usize = text
+        .split_inclusive('\n')
+        .take(position.line as usize)
+        .map(str::len)
+        .sum();
+    let line = text[line_start..].split('\n').next().unwrap_or("");
+    let mut character = 0;
+    for (i, c) in line.char_indices() {
+        if position.character <= character {
+            return line_start + i;
+        }
+        character += c.len_utf16() as u32;
+    }
+    line_start + line.len()
+}
+
+/// Diagnostics without a location are shown at the start of the file.
+fn range(text: &str, span: Option<Span>) -> Range {
+    let span = span.unwrap_or(Span { start: 0, end: 0 });
+    Range::new(position(text, span.start), position(text, span.end))
+}
+
+/// What loading an open file found, located in its text.
+struct Analysis {
+    /// The problems in the file itself. Those in the files it includes are
+    /// shown when they are opened.
+    diagnostics: Vec<(Level, String, Option<Span>)>,
+    /// Every property set in the file, by the span of its name.
+    values: Vec<PropertyValue>,
+}
+
+struct PropertyValue {
+    span: Span,
+    body: String,
+    schema: &'static PropertySchema,
+    value: Value,
+    animated: bool,
+}
+
+/// Loads the scene in an open file, which is at `path` but has contents
+/// `text`. Its includes are read from disk.
+fn analyze(path: &str, text: &str) -> Analysis {
+    let sources = Sources::with_root(path, text.to_owned());
+    let mut diagnostics = sources.diagnostics();
+    let commands = sources.parse(&mut diagnostics);
+    let (_, evaluated) = load_scene_traced(&commands, &mut diagnostics);
+    let values = evaluated
+        .into_iter()
+        .filter_map(|evaluated| {
+            Some(PropertyValue {
+                span: Span::of(sources.root(), evaluated.name)?,
+                body: evaluated.body,
+                schema: evaluated.schema,
+                value: evaluated.value,
+                animated: evaluated.animated,
+            })
+        })
+        .collect();
+    let diagnostics = diagnostics
+        .items()
+        .iter()
+        .filter(|item| item.file == 0)
+        .map(|item| (item.level, item.message.clone(), item.span))
+        .collect();
+    Analysis {
+        diagnostics,
+        values,
+    }
+}
+
+/// Parses an open file alone, for the requests that only need its syntax.
+fn parse(text: &str) -> Vec<Command<'_>> {
+    parse_scene(text, &mut Diagnostics::new("", text))
+}
+
+/// The most values of a property shown, which a template or a loop can set
+/// on many bodies.
+const MAX_HOVER_VALUES: usize = 10;
+
+/// Describes the property whose name is at `offset`, with the values it
+/// was set to.
+fn hover(path: &str, text: &str, offset: usize) -> Option<Hover> {
+    let commands = parse(text);
+    let (name, span) = property_at(text, &commands, offset)?;
+    let (schema, _) = lookup(BODY_PROPERTIES, name)?;
+    let mut contents = format!("**{}**: {}", schema.name, schema.ty);
+    if let PropType::Number(dim) = schema.ty {
+        if let Some(unit) = internal_unit(dim) {
+            contents += &format!(", in {unit}");
+        }
+    }
+    contents += &format!("\n\n{}", schema.description);
+    let analysis = analyze(path, text);
+    let values: Vec<_> = analysis
+        .values
+        .iter()
+        .filter(|value| value.span == span)
+        .collect();
+    if !values.is_empty() {
+        contents += "\n\n---\n";
+    }
+    for value in values.iter().take(MAX_HOVER_VALUES) {
+        let at = if value.animated { " at `t = 0`" } else { "" };
+        contents += &format!(
+            "\n`{}` in `{}`{at}",
+            show(value.schema, &value.value),
+            value.body
+        );
+    }
+    if MAX_HOVER_VALUES < values.len() {
+        contents +=
+            &format!("\n\nand {} more", values.len() - MAX_HOVER_VALUES);
+    }
+    Some(Hover {
+        contents: HoverContents::Markup(MarkupContent {
+            kind: MarkupKind::Markdown,
+            value: contents,
+        }),
+        range: Some(range(text, Some(span))),
+    })
+}
+
+/// Writes a value, with numbers in the internal unit of the property.
+fn show(schema: &PropertySchema, value: &Value) -> String {
+    let PropType::Number(dim) = schema.ty else {
+        return value.to_string();
+    };
+    match (value.as_dim(dim), internal_unit(dim)) {
+        (Ok(n), Some(unit)) => format!("{n} {unit}"),
+        (Ok(n), None) => n.to_string(),
+        (Err(_), _) => value.to_string(),
+    }
+}
+
+/// Returns the name of the property set at `offset` and its span.
+fn property_at<'src>(
+    text: &'src str,
+    commands: &[Command<'src>],
+    offset: usize,
+) -> Option<(&'src str, Span)> {
+    commands.iter().find_map(|command| match command {
+        Command::Prop(name, _) => {
+            let span = Span::of(text, name)?;
+            (span.start <= offset && offset <= span.end)
+                .then_some((*name, span))
+        }
+        Command::Com(args) => args.iter().find_map(|arg| match arg {
+            Arg::Block(block) => property_at(text, block, offset),
+            _ => None,
+        }),
+        Command::For(_, _, _, body) => property_at(text, body, offset),
+        _ => None,
+    })
+}
+
+/// Returns the first name written in a command, which it starts at or
+/// just after its keyword.
+fn first_name<'src>(command: &Command<'src>) -> Option<&'src str> {
+    match command {
+        Command::Com(args) => match args.first()? {
+            Arg::Str(s) | Arg::Quoted(s) | Arg::Base(s) => Some(s),
+            Arg::Block(_) => None,
+        },
+        Command::Prop(name, _)
+        | Command::Def(name, _)
+        | Command::Let(name, _)
+        | Command::Const(name, _)
+        | Command::FnDef(name, _, _)
+        | Command::Include(name)
+        | Command::For(name, _, _, _)
+        | Command::Comment(name)
+        | Command::TrailingComment(name)
+        | Command::Error(name) => Some(name),
+    }
+}
+
+/// Returns the spans of the commands of a block that ends at `end`. Each
+/// command reaches up to the next one.
+fn extents(text: &str, commands: &[Command], end: usize) -> Vec<Span> {
+    let starts: Vec<_> = commands
+        .iter()
+        .map(|command| {
+            first_name(command)
+                .and_then(|name| Span::of(text, name))
+                .map_or(end, |span| span.start)
+        })
+        .collect();
+    (0..starts.len())
+        .map(|i| Span {
+            start: starts[i],
+            end: starts.get(i + 1).copied().unwrap_or(end),
+        })
+        .collect()
+}
+
+/// Returns the span of the identifier at `offset`, unless it is a field
+/// such as `radius` in `self.radius`.
+fn identifier_at(text: &str, offset: usize) -> Option<Span> {
+    let is_part = |c: char| c.is_alphanumeric() || c == '_';
+    let start = text[..offset]
+        .char_indices()
+        .rev()
+        .take_while(|&(_, c)| is_part(c))
+        .last()
+        .map_or(offset, |(i, _)| i);
+    let end = text[offset..]
+        .find(|c: char| !is_part(c))
+        .map_or(text.len(), |i| offset + i);
+    let identifier = &text[start..end];
+    if identifier.is_empty()
+        || identifier.starts_with(|c: char| c.is_ascii_digit())
+        || text[..start].ends_with('.')
+    {
+        return None;
+    }
+    Some(Span { start, end })
+}
+
+/// Returns the span of the definition of the variable or function named at
+/// `offset`.
+fn definition(text: &str, offset: usize) -> Option<Span> {
+    let span = identifier_at(text, offset)?;
+    let commands = parse(text);
+    let name = &text[span.start..span.end];
+    let found = find_definition(text, &commands, text.len(), span, name, None);
+    Span::of(text, found?)
+}
+
+/// Finds the definition of `name` visible at `at` in a block ending at
+/// `end`, given the one `visible` from the enclosing blocks.
+fn find_definition<'src>(
+    text: &'src str,
+    commands: &[Command<'src>],
+    end: usize,
+    at: Span,
+    name: &str,
+    mut visible: Option<&'src str>,
+) -> Option<&'src str> {
+    let is_at = |s: &str| Span::of(text, s) == Some(at);
+    for (command, extent) in commands.iter().zip(extents(text, commands, end)) {
+        if at.start < extent.start {
+            break;
+        }
+        let inside = at.start < extent.end;
+        match command {
+            // `name = value` assigns to a visible variable of that name, and
+            // only defines one if there is none.
+            Command::Def(def, _) if *def == name => {
+                if inside && is_at(def) {
+                    return visible.or(Some(def));
+                }
+                visible = visible.or(Some(def));
+            }
+            Command::Let(def, _) | Command::Const(def, _) if *def == name => {
+                if inside && is_at(def) {
+                    return Some(def);
+                }
+                // The value of `let x = x + 1` reads the previous `x`.
+                if !inside {
+                    visible = Some(def);
+                }
+            }
+            Command::FnDef(def, params, _) => {
+                if inside {
+                    if let Some(param) = params.iter().find(|p| **p == name) {
+                        return Some(param);
+                    }
+                }
+                if *def == name {
+                    visible = Some(def);
+                }
+            }
+            Command::For(var, _, _, body) if inside => {
+                if *var == name {
+                    visible = Some(var);
+                }
+                return find_definition(
+                    text, body, extent.end, at, name, visible,
+                );
+            }
+            Command::Com(args) if inside => {
+                for arg in args {
+                    if let Arg::Block(block) = arg {
+                        return find_definition(
+                            text, block, extent.end, at, name, visible,
+                        );
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+    visible
+}
+
+/// Offers the properties and the built-in functions.
+fn completions() -> Vec<CompletionItem> {
+    let properties = BODY_PROPERTIES.iter().map(|schema| CompletionItem {
+        label: schema.name.to_owned(),
+        kind: Some(CompletionItemKind::PROPERTY),
+        detail: Some(schema.ty.to_string()),
+        documentation: Some(Documentation::String(
+            schema.description.to_owned(),
+        )),
+        ..Default::default()
+    });
+    let registry = FunctionRegistry::builtins();
+    let functions = registry.names().into_iter().map(|name| CompletionItem {
+        label: name.to_owned(),
+        kind: Some(CompletionItemKind::FUNCTION),
+        detail: registry.get(name).map(|f| f.arity.to_string()),
+        ..Default::default()
+    });
+    properties.chain(functions).collect()
+}
+
+/// Returns the `astro` and `template` blocks as a tree.
+fn symbols(text: &str) -> Vec<DocumentSymbol> {
+    fn collect(
+        text: &str,
+        commands: &[Command],
+        end: usize,
+        symbols: &mut Vec<DocumentSymbol>,
+    ) {
+        for (command, extent) in
+            commands.iter().zip(extents(text, commands, end))
+        {
+            if let Command::For(_, _, _, body) = command {
+                collect(text, body, extent.end, symbols);
+                continue;
+            }
+            let (kind, (name, base, block)) = match parse_astro_command(command)
+            {
+                Some(parts) => (SymbolKind::OBJECT, parts),
+                None => match parse_template_command(command) {
+                    Some(parts) => (SymbolKind::CLASS, parts),
+                    None => continue,
+                },
+            };
+            let Some(selection) = Span::of(text, name) else {
+                continue;
+            };
+            // The block ends at its `}`, before the space up to the next
+            // command.
+            let end =
+                extent.start + text[extent.start..extent.end].trim_end().len();
+            let mut children = vec![];
+            collect(text, block, extent.end, &mut children);
+            #[allow(deprecated)]
+            symbols.push(DocumentSymbol {
+                name: name.to_owned(),
+                detail: base.map(|base| format!(": {base}")),
+                kind,
+                tags: None,
+                deprecated: None,
+                range: range(
+                    text,
+                    Some(Span {
+                        start: extent.start,
+                        end,
+                    }),
+                ),
+                selection_range: range(text, Some(selection)),
+                children: Some(children),
+            });
+        }
+    }
+    let mut symbols = vec![];
+    collect(text, &parse(text), text.len(), &mut symbols);
+    symbols
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use lsp_server::RequestId;
+    use serde_json::json;
+
+    const SCENE: &str = "let size = 0.1
+template Planet {
+    radius: size
+}
+astro Sun {
+    star: true
+    fn orbit(n) = n * 2 AU
+    for i in 1..3 {
+        astro \"Rock${i}\" : Planet {
+            semimajor_axis: orbit(i)
+        }
+    }
+}
+";
+
+    /// Returns the offset of the `n`th occurrence of `pattern`, counting
+    /// from 0, plus `delta`.
+    fn find(pattern: &str, n: usize, delta: usize) -> usize {
+        SCENE.match_indices(pattern).nth(n).unwrap().0 + delta
+    }
+
+    #[test]
+    fn test_positions() {
+        let text = "a\nπ = \"𝄞\" b\n";
+        let b = text.find('b').unwrap();
+        // `𝄞` is two UTF-16 code units.
+        assert_eq!(position(text, b), Position::new(1, 9));
+        assert_eq!(offset(text, Position::new(1, 9)), b);
+        assert_eq!(offset(text, Position::new(0, 5)), 1);
+        assert_eq!(offset(text, Position::new(5, 0)), text.len());
+    }
+
+    #[test]
+    fn test_diagnostics() {
+        let text = "astro Sun {\n    radius: 2 d\n    colour: 1\n}\n";
+        let analysis = analyze("/nonexistent/scene.txt", text);
+        let messages: Vec<_> = analysis
+            .diagnostics
+            .iter()
+            .map(|(level, message, span)| {
+                let span = span.unwrap();
+                (*level, message.as_str(), &text[span.start..span.end])
+            })
+            .collect();
+        assert_eq!(
+            messages,
+            [
+                (Level::Error, "expected a length, found a time", "d"),
+                (Level::Error, "unknown property `colour`", "colour"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_hover() {
+        let hover =
+            hover("/nonexistent/scene.txt", SCENE, find("radius", 0, 2))
+                .unwrap();
+        let HoverContents::Markup(contents) = hover.contents else {
+            panic!("expected markup, found {:?}", hover.contents);
+        };
+        assert_eq!(
+            contents.value,
+            "**radius**: length, in AU\n\nRadius of the body.\n\n---\n\n\
+            `0.1 AU` in `Rock1`\n`0.1 AU` in `Rock2`"
+        );
+        assert_eq!(
+            hover.range,
+            Some(Range::new(Position::new(2, 4), Position::new(2, 10)))
+        );
+        // Only property names have something to show.
+        let at_value = find("size", 1, 0);
+        assert_eq!(
+            super::hover("/nonexistent/scene.txt", SCENE, at_value),
+            None
+        );
+    }
+
+    #[test]
+    fn test_definition() {
+        let at = |pattern, n, delta| {
+            definition(SCENE, find(pattern, n, delta))
+                .map(|span| (span.start, &SCENE[span.start..span.end]))
+        };
+        assert_eq!(at("size", 1, 2), Some((find("size", 0, 0), "size")));
+        assert_eq!(at("orbit", 1, 0), Some((find("orbit", 0, 0), "orbit")));
+        assert_eq!(at("(i)", 0, 1), Some((find("i in", 0, 0), "i")));
+        assert_eq!(at("n * 2", 0, 0), Some((find("n)", 0, 0), "n")));
+        // Properties and units are not variables.
+        assert_eq!(at("radius", 0, 0), None);
+        assert_eq!(at("AU", 0, 0), None);
+    }
+
+    #[test]
+    fn test_symbols() {
+        let symbols = symbols(SCENE);
+        let names: Vec<_> = symbols
+            .iter()
+            .map(|symbol| (symbol.name.as_str(), symbol.kind))
+            .collect();
+        assert_eq!(
+            names,
+            [("Planet", SymbolKind::CLASS), ("Sun", SymbolKind::OBJECT)]
+        );
+        let sun = &symbols[1];
+        assert_eq!(
+            sun.range,
+            Range::new(Position::new(4, 0), Position::new(12, 1))
+        );
+        let rocks = sun.children.as_ref().unwrap();
+        assert_eq!(rocks.len(), 1);
+        assert_eq!(rocks[0].name, "Rock${i}");
+        assert_eq!(rocks[0].detail.as_deref(), Some(": Planet"));
+    }
+
+    #[test]
+    fn test_completions() {
+        let labels: Vec<_> =
+            completions().into_iter().map(|item| item.label).collect();
+        assert!(labels.contains(&"semimajor_axis".to_owned()));
+        assert!(labels.contains(&"sqrt".to_owned()));
+    }
+
+    /// Drives the server as an editor would, over an in-memory connection.
+    #[test]
+    fn test_session() {
+        let (server, client) = Connection::memory();
+        let thread = std::thread::spawn(move || {
+            Server::default().run(&server).map_err(|e| e.to_string())
+        });
+        let uri = "file:///nonexistent/scene.txt";
+        let send = |message: Message| client.sender.send(message).unwrap();
+        let notify = |method: &str, params| {
+            send(Notification::new(method.to_owned(), params).into())
+        };
+        let request = |id: i32, method: &str, params| {
+            send(Request::new(id.into(), method.to_owned(), params).into());
+            match client.receiver.recv().unwrap() {
+                Message::Response(response) => {
+                    assert_eq!(response.id, RequestId::from(id));
+                    response.result.unwrap()
+                }
+                message => panic!("expected a response, found {message:?}"),
+            }
+        };
+        let published = || match client.receiver.recv().unwrap() {
+            Message::Notification(notification) => {
+                assert_eq!(notification.method, PublishDiagnostics::METHOD);
+                notification.params
+            }
+            message => panic!("expected diagnostics, found {message:?}"),
+        };
+
+        notify(
+            DidOpenTextDocument::METHOD,
+            json!({"textDocument": {
+                "uri": uri, "languageId": "rusty-space", "version": 1,
+                "text": "astro Sun {\n    radius: 2 d\n}\n",
+            }}),
+        );
+        let params = published();
+        assert_eq!(
+            params["diagnostics"][0]["message"],
+            "expected a length, found a time"
+        );
+        assert_eq!(
+            params["diagnostics"][0]["range"],
+            json!({
+                "start": {"line": 1, "character": 14},
+                "end": {"line": 1, "character": 15},
+            })
+        );
+
+        notify(
+            DidChangeTextDocument::METHOD,
+            json!({
+                "textDocument": {"uri": uri, "version": 2},
+                "contentChanges": [{"text": "astro Sun {\n    radius: 2 AU\n}\n"}],
+            }),
+        );
+        assert_eq!(published()["diagnostics"], json!([]));
+
+        let hover = request(
+            1,
+            HoverRequest::METHOD,
+            json!({
+                "textDocument": {"uri": uri},
+                "position": {"line": 1, "character": 6},
+            }),
+        );
+        let contents = hover["contents"]["value"].as_str().unwrap();
+        assert!(contents.ends_with("`2 AU` in `Sun`"), "{contents}");
+
+        let outline = request(
+            2,
+            DocumentSymbolRequest::METHOD,
+            json!({"textDocument": {"uri": uri}}),
+        );
+        assert_eq!(outline[0]["name"], "Sun");
+
+        assert_eq!(request(3, "shutdown", json!(null)), json!(null));
+        notify("exit", json!(null));
+        assert_eq!(thread.join().unwrap(), Ok(()));
+    }
+}
@@ -1,15 +1,23 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    color::Color,
+    diagnostics::Diagnostics,
+    functions::{Arity, FunctionRegistry, NativeFunction},
+    units::{self, Dim},
+};
 
 use nom::{
     branch::alt,
-    bytes::complete::tag,
+    bytes::complete::{tag, take_until, take_while},
     character::complete::{
-        alpha1, alphanumeric1, char, multispace0, none_of, one_of, space0,
+        alpha1, alphanumeric1, anychar, char, hex_digit1, multispace1, none_of,
+        not_line_ending, one_of, space0, space1,
     },
-    combinator::{opt, recognize},
-    multi::{fold_many0, many0, many1},
+    combinator::{eof, map, not, opt, peek, recognize, verify},
+    multi::{fold_many0, many0, many1, separated_list0},
     number::complete::recognize_float,
-    sequence::{delimited, pair},
+    sequence::{delimited, pair, preceded, terminated},
     IResult,
 };
 
@@ -17,321 +25,1809 @@ use nom::{
 pub enum Expression<'src> {
     Ident(&'src str),
     NumLiteral(f64),
+    /// A number followed by a unit, e.g. `6371 km`. The unit is kept as
+    /// written and converted to internal units on evaluation.
+    Quantity(f64, &'src str),
+    BoolLiteral(bool),
+    /// The contents of a quoted string, without the quotes.
+    StrLiteral(&'src str),
+    /// A color such as `#ff8800`, including the `#`.
+    Color(&'src str),
+    /// `[a, b, c]`.
+    List(Vec<Expression<'src>>),
     FnInvoke(&'src str, Vec<Expression<'src>>),
+    /// A property of the current or enclosing body, e.g. `parent.radius`.
+    Field(&'src str, &'src str),
     Add(Box<Expression<'src>>, Box<Expression<'src>>),
     Sub(Box<Expression<'src>>, Box<Expression<'src>>),
     Mul(Box<Expression<'src>>, Box<Expression<'src>>),
     Div(Box<Expression<'src>>, Box<Expression<'src>>),
+    Mod(Box<Expression<'src>>, Box<Expression<'src>>),
+    Pow(Box<Expression<'src>>, Box<Expression<'src>>),
+    Neg(Box<Expression<'src>>),
+    Lt(Box<Expression<'src>>, Box<Expression<'src>>),
+    Le(Box<Expression<'src>>, Box<Expression<'src>>),
+    Gt(Box<Expression<'src>>, Box<Expression<'src>>),
+    Ge(Box<Expression<'src>>, Box<Expression<'src>>),
+    Eq(Box<Expression<'src>>, Box<Expression<'src>>),
+    Ne(Box<Expression<'src>>, Box<Expression<'src>>),
+    And(Box<Expression<'src>>, Box<Expression<'src>>),
+    Or(Box<Expression<'src>>, Box<Expression<'src>>),
+    Not(Box<Expression<'src>>),
+    /// `cond ? then : else`, also written `if(cond, then, else)`.
+    Cond(
+        Box<Expression<'src>>,
+        Box<Expression<'src>>,
+        Box<Expression<'src>>,
+    ),
 }
 
-fn unary_fn(
-    f: fn(f64) -> f64,
-) -> impl Fn(&Vec<Expression>, &HashMap<String, f64>) -> f64 {
-    move |args, vars| {
-        f(eval(
-            args.into_iter().next().expect("function missing argument"),
-            vars,
-        ))
+impl<'src> Expression<'src> {
+    /// Returns the first name or string in the expression, which is the
+    /// closest thing to a location that an expression node has.
+    pub fn span(&self) -> Option<&'src str> {
+        match self {
+            Self::Ident(s)
+            | Self::Field(s, _)
+            | Self::Quantity(_, s)
+            | Self::StrLiteral(s)
+            | Self::Color(s)
+            | Self::FnInvoke(s, _) => Some(s),
+            Self::NumLiteral(_) | Self::BoolLiteral(_) => None,
+            Self::List(items) => items.iter().find_map(Self::span),
+            Self::Neg(ex) | Self::Not(ex) => ex.span(),
+            Self::Add(lhs, rhs)
+            | Self::Sub(lhs, rhs)
+            | Self::Mul(lhs, rhs)
+            | Self::Div(lhs, rhs)
+            | Self::Mod(lhs, rhs)
+            | Self::Pow(lhs, rhs)
+            | Self::Lt(lhs, rhs)
+            | Self::Le(lhs, rhs)
+            | Self::Gt(lhs, rhs)
+            | Self::Ge(lhs, rhs)
+            | Self::Eq(lhs, rhs)
+            | Self::Ne(lhs, rhs)
+            | Self::And(lhs, rhs)
+            | Self::Or(lhs, rhs) => lhs.span().or_else(|| rhs.span()),
+            Self::Cond(cond, then, els) => {
+                cond.span().or_else(|| then.span()).or_else(|| els.span())
+            }
+        }
     }
 }
 
-fn binary_fn(
-    f: fn(f64, f64) -> f64,
-) -> impl Fn(&Vec<Expression>, &HashMap<String, f64>) -> f64 {
-    move |args, vars| {
-        let mut args = args.into_iter();
-        let lhs = eval(
-            args.next().expect("function missing the first argument"),
-            vars,
-        );
-        let rhs = eval(
-            args.next().expect("function missing the second argument"),
-            vars,
-        );
-        f(lhs, rhs)
+/// The result of evaluating an [`Expression`].
+#[derive(Debug, PartialEq, Clone)]
+pub enum Value {
+    Num(f64),
+    /// A number with a physical dimension, in internal units. Dimensionless
+    /// quantities are always represented as `Num`.
+    Quantity(f64, Dim),
+    Bool(bool),
+    Str(String),
+    /// A direction or position, whose components have the same dimension.
+    Vec3([f64; 3], Dim),
+    Color(Color),
+    List(Vec<Value>),
+}
+
+impl Value {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Self::Num(_) => "number",
+            Self::Quantity(..) => "quantity",
+            Self::Bool(_) => "boolean",
+            Self::Str(_) => "string",
+            Self::Vec3(..) => "vector",
+            Self::Color(_) => "color",
+            Self::List(_) => "list",
+        }
+    }
+
+    fn mismatch<'src>(&self, expected: &'static str) -> EvalError<'src> {
+        EvalError::TypeMismatch {
+            expected,
+            found: self.type_name(),
+            at: None,
+        }
+    }
+
+    pub fn quantity(value: f64, dim: Dim) -> Self {
+        if dim.is_none() {
+            Self::Num(value)
+        } else {
+            Self::Quantity(value, dim)
+        }
+    }
+
+    pub fn as_num<'src>(&self) -> Result<f64, EvalError<'src>> {
+        self.as_dim(Dim::NONE)
+    }
+
+    /// Returns the magnitude and dimension of a number or quantity.
+    pub fn as_quantity<'src>(&self) -> Result<(f64, Dim), EvalError<'src>> {
+        match self {
+            Self::Num(n) => Ok((*n, Dim::NONE)),
+            Self::Quantity(value, dim) => Ok((*value, *dim)),
+            _ => Err(self.mismatch("number")),
+        }
+    }
+
+    /// Returns the value in internal units, if it has dimension `dim`. Plain
+    /// numbers are assumed to be in internal units already.
+    pub fn as_dim<'src>(&self, dim: Dim) -> Result<f64, EvalError<'src>> {
+        match self.as_quantity()? {
+            (value, found) if found == dim || found.is_none() => Ok(value),
+            (_, found) => Err(EvalError::DimensionMismatch {
+                expected: dim,
+                found,
+                at: None,
+            }),
+        }
+    }
+
+    pub fn as_bool<'src>(&self) -> Result<bool, EvalError<'src>> {
+        match self {
+            Self::Bool(b) => Ok(*b),
+            _ => Err(self.mismatch("boolean")),
+        }
+    }
+
+    pub fn into_string<'src>(self) -> Result<String, EvalError<'src>> {
+        match self {
+            Self::Str(s) => Ok(s),
+            _ => Err(self.mismatch("string")),
+        }
+    }
+
+    /// Returns the components of a vector in internal units, if it has
+    /// dimension `dim`.
+    pub fn as_vec3<'src>(&self, dim: Dim) -> Result<[f64; 3], EvalError<'src>> {
+        match self {
+            Self::Vec3(v, found) if *found == dim => Ok(*v),
+            Self::Vec3(_, found) => Err(EvalError::DimensionMismatch {
+                expected: dim,
+                found: *found,
+                at: None,
+            }),
+            _ => Err(self.mismatch("vector")),
+        }
+    }
+
+    pub fn as_color<'src>(&self) -> Result<Color, EvalError<'src>> {
+        match self {
+            Self::Color(color) => Ok(*color),
+            _ => Err(self.mismatch("color")),
+        }
+    }
+
+    pub fn into_list<'src>(self) -> Result<Vec<Value>, EvalError<'src>> {
+        match self {
+            Self::List(items) => Ok(items),
+            _ => Err(self.mismatch("list")),
+        }
     }
 }
 
-pub(crate) fn eval(expr: &Expression, vars: &HashMap<String, f64>) -> f64 {
-    match expr {
-        Expression::Ident("pi") => std::f64::consts::PI,
-        Expression::Ident(id) => {
-            if let Some(val) = vars.get(*id) {
-                *val
-            } else {
-                panic!("Unknown name {:?}", id)
+impl std::fmt::Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Num(n) => write!(f, "{n}"),
+            Self::Quantity(value, dim) => write!(f, "{value} ({dim})"),
+            Self::Bool(b) => write!(f, "{b}"),
+            Self::Str(s) => write!(f, "{s:?}"),
+            Self::Vec3([x, y, z], dim) => {
+                write!(f, "vec3({x}, {y}, {z})")?;
+                if !dim.is_none() {
+                    write!(f, " ({dim})")?;
+                }
+                Ok(())
             }
+            Self::Color(color) => write!(f, "{color}"),
+            Self::List(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{item}")?;
+                }
+                write!(f, "]")
+            }
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum EvalError<'src> {
+    UnknownName(&'src str),
+    UnknownFunction(&'src str),
+    WrongArity {
+        name: &'src str,
+        expected: Arity,
+        found: usize,
+    },
+    TypeMismatch {
+        expected: &'static str,
+        found: &'static str,
+        at: Option<&'src str>,
+    },
+    DimensionMismatch {
+        expected: Dim,
+        found: Dim,
+        at: Option<&'src str>,
+    },
+    /// Raising a quantity to a power that is not an integer, which would
+    /// give it a fractional dimension.
+    FractionalPower {
+        base: Dim,
+        at: Option<&'src str>,
+    },
+    /// A product, quotient or power whose dimension has an exponent too
+    /// large to represent.
+    DimensionOverflow {
+        at: Option<&'src str>,
+    },
+    RecursionLimit(&'src str),
+    /// `self` or `parent` outside of the blocks of bodies that have them.
+    NoBody(&'src str),
+    /// A property that has not been set on `self` or `parent`.
+    UnsetProperty(&'src str, &'src str),
+    /// The contents of a `${...}` in a string that are not an expression.
+    InvalidInterpolation(&'src str),
+    /// A string in an expression that changes over time.
+    VaryingString {
+        at: Option<&'src str>,
+    },
+}
+
+impl<'src> EvalError<'src> {
+    /// The part of the source the error should be reported at, if known.
+    pub fn span(&self) -> Option<&'src str> {
+        match self {
+            Self::UnknownName(name)
+            | Self::UnknownFunction(name)
+            | Self::WrongArity { name, .. }
+            | Self::RecursionLimit(name)
+            | Self::NoBody(name)
+            | Self::UnsetProperty(_, name)
+            | Self::InvalidInterpolation(name) => Some(name),
+            Self::TypeMismatch { at, .. }
+            | Self::DimensionMismatch { at, .. }
+            | Self::FractionalPower { at, .. }
+            | Self::DimensionOverflow { at }
+            | Self::VaryingString { at } => *at,
         }
-        Expression::NumLiteral(n) => *n,
-        Expression::FnInvoke("sqrt", args) => unary_fn(f64::sqrt)(args, vars),
-        Expression::FnInvoke("sin", args) => unary_fn(f64::sin)(args, vars),
-        Expression::FnInvoke("cos", args) => unary_fn(f64::cos)(args, vars),
-        Expression::FnInvoke("tan", args) => unary_fn(f64::tan)(args, vars),
-        Expression::FnInvoke("asin", args) => unary_fn(f64::asin)(args, vars),
-        Expression::FnInvoke("acos", args) => unary_fn(f64::acos)(args, vars),
-        Expression::FnInvoke("atan", args) => unary_fn(f64::atan)(args, vars),
-        Expression::FnInvoke("atan2", args) => {
-            binary_fn(f64::atan2)(args, vars)
+    }
+
+    /// Fills in the location of a type or dimension mismatch with the span
+    /// of the expression that produced the value, unless it is already
+    /// known.
+    pub fn at(mut self, span: Option<&'src str>) -> Self {
+        match &mut self {
+            Self::TypeMismatch { at: at @ None, .. }
+            | Self::DimensionMismatch { at: at @ None, .. }
+            | Self::FractionalPower { at: at @ None, .. }
+            | Self::DimensionOverflow { at: at @ None } => *at = span,
+            _ => (),
         }
-        Expression::FnInvoke("pow", args) => binary_fn(f64::powf)(args, vars),
-        Expression::FnInvoke("exp", args) => unary_fn(f64::exp)(args, vars),
-        Expression::FnInvoke("log", args) => binary_fn(f64::log)(args, vars),
-        Expression::FnInvoke("log10", args) => unary_fn(f64::log10)(args, vars),
-        Expression::FnInvoke(name, _) => panic!("Unknown function {name:?}"),
-        Expression::Add(lhs, rhs) => eval(lhs, vars) + eval(rhs, vars),
-        Expression::Sub(lhs, rhs) => eval(lhs, vars) - eval(rhs, vars),
-        Expression::Mul(lhs, rhs) => eval(lhs, vars) * eval(rhs, vars),
-        Expression::Div(lhs, rhs) => eval(lhs, vars) / eval(rhs, vars),
+        self
     }
 }
 
-fn factor(i: &str) -> IResult<&str, Expression> {
-    alt((number, func_call, ident, parens))(i)
+impl std::fmt::Display for EvalError<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnknownName(name) => write!(f, "unknown name `{name}`"),
+            Self::UnknownFunction(name) => {
+                write!(f, "unknown function `{name}`")
+            }
+            Self::WrongArity {
+                name,
+                expected,
+                found,
+            } => write!(
+                f,
+                "function `{name}` takes {expected} but {found} {} supplied",
+                if *found == 1 { "was" } else { "were" }
+            ),
+            Self::TypeMismatch {
+                expected, found, ..
+            } => write!(f, "expected a {expected}, found a {found}"),
+            Self::DimensionMismatch {
+                expected, found, ..
+            } => write!(
+                f,
+                "expected {}, found {}",
+                with_article(expected),
+                with_article(found)
+            ),
+            Self::FractionalPower { base, .. } => write!(
+                f,
+                "cannot raise {} to a fractional power",
+                with_article(base)
+            ),
+            Self::DimensionOverflow { .. } => {
+                write!(f, "the dimension of the result is too large")
+            }
+            Self::NoBody(body) => write!(f, "there is no `{body}` body here"),
+            Self::UnsetProperty(body, name) => {
+                write!(f, "`{body}.{name}` has not been set")
+            }
+            Self::InvalidInterpolation(_) => {
+                write!(f, "expected an expression inside `${{...}}`")
+            }
+            Self::VaryingString { .. } => {
+                write!(f, "a string cannot change over time")
+            }
+            Self::RecursionLimit(name) => write!(
+                f,
+                "calls to `{name}` nested more than {MAX_CALL_DEPTH} deep"
+            ),
+        }
+    }
 }
 
-fn func_call(i: &str) -> IResult<&str, Expression> {
-    let (r, ident) = delimited(multispace0, identifier, multispace0)(i)?;
-    // println!("func_invoke ident: {}", ident);
-    let (r, args) = delimited(
-        multispace0,
-        delimited(
-            tag("("),
-            many0(delimited(
-                multispace0,
-                expr,
-                delimited(multispace0, opt(tag(",")), multispace0),
-            )),
-            tag(")"),
-        ),
-        multispace0,
-    )(r)?;
-    Ok((r, Expression::FnInvoke(ident, args)))
+fn with_article(dim: &Dim) -> String {
+    let noun = dim.to_string();
+    if noun.starts_with(['a', 'e', 'i', 'o', 'u']) {
+        format!("an {noun}")
+    } else {
+        format!("a {noun}")
+    }
 }
 
-fn ident(input: &str) -> IResult<&str, Expression> {
-    let (r, res) = delimited(multispace0, identifier, multispace0)(input)?;
-    Ok((r, Expression::Ident(res)))
+pub(crate) type EvalResult<'src> = Result<Value, EvalError<'src>>;
+
+/// Calls beyond this depth are assumed to be runaway recursion.
+pub(crate) const MAX_CALL_DEPTH: usize = 64;
+
+/// A function defined in a scene file with `fn name(params) = body`.
+#[derive(Debug, PartialEq, Clone)]
+pub(crate) struct Function<'src> {
+    pub params: Vec<&'src str>,
+    pub body: Expression<'src>,
 }
 
-fn identifier(input: &str) -> IResult<&str, &str> {
-    recognize(pair(
-        alt((alpha1, tag("_"))),
-        many0(alt((alphanumeric1, tag("_")))),
-    ))(input)
+/// The names defined in a block of a scene file.
+#[derive(Debug, Default)]
+pub(crate) struct Scope<'src> {
+    pub vars: HashMap<String, Value>,
+    /// The variables in `vars` declared with `const`.
+    pub constants: HashSet<String>,
+    pub functions: HashMap<String, Function<'src>>,
+    /// The properties set so far on the body whose block this is, which
+    /// expressions can read as `self.name`, or `parent.name` in the blocks
+    /// of child bodies.
+    pub properties: Option<HashMap<String, Value>>,
 }
 
-fn number(input: &str) -> IResult<&str, Expression> {
-    let (r, v) = recognize_float(input)?;
-    Ok((
-        r,
-        Expression::NumLiteral(v.parse().map_err(|_| {
-            nom::Err::Error(nom::error::Error {
-                input,
-                code: nom::error::ErrorKind::Digit,
-            })
-        })?),
-    ))
+/// The variables and functions visible to an expression.
+#[derive(Clone, Copy)]
+pub(crate) struct Env<'a, 'src> {
+    /// The enclosing scopes, innermost last.
+    scopes: &'a [Scope<'src>],
+    /// Arguments of the user-defined function being evaluated, which shadow
+    /// the variables.
+    args: Option<&'a HashMap<&'src str, Value>>,
+    depth: usize,
+    /// The functions implemented in Rust.
+    natives: &'a FunctionRegistry,
 }
 
-fn parens(i: &str) -> IResult<&str, Expression> {
-    delimited(
-        multispace0,
-        delimited(tag("("), expr, tag(")")),
-        multispace0,
-    )(i)
+impl<'a, 'src> Env<'a, 'src> {
+    /// Returns an environment with the built-in functions.
+    #[cfg(test)]
+    pub fn new(scopes: &'a [Scope<'src>]) -> Self {
+        Self::with_functions(scopes, FunctionRegistry::builtins())
+    }
+
+    pub fn with_functions(
+        scopes: &'a [Scope<'src>],
+        natives: &'a FunctionRegistry,
+    ) -> Self {
+        Self {
+            scopes,
+            args: None,
+            depth: 0,
+            natives,
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&'a Value> {
+        self.args.and_then(|args| args.get(name)).or_else(|| {
+            self.scopes
+                .iter()
+                .rev()
+                .find_map(|scope| scope.vars.get(name))
+        })
+    }
+
+    pub fn function(&self, name: &str) -> Option<&'a Function<'src>> {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.functions.get(name))
+    }
+
+    pub fn natives(&self) -> &'a FunctionRegistry {
+        self.natives
+    }
+
+    /// Returns how many user-defined function calls deep the environment
+    /// is.
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// Returns the environment of the body of a user-defined function
+    /// called with `args`.
+    pub fn with_args(&self, args: &'a HashMap<&'src str, Value>) -> Self {
+        Self {
+            args: Some(args),
+            depth: self.depth + 1,
+            ..*self
+        }
+    }
+
+    /// Returns the properties of `self` or `parent`.
+    fn body(&self, name: &str) -> Option<&'a HashMap<String, Value>> {
+        let level = match name {
+            "self" => 0,
+            "parent" => 1,
+            _ => return None,
+        };
+        self.scopes
+            .iter()
+            .rev()
+            .filter_map(|scope| scope.properties.as_ref())
+            .nth(level)
+    }
 }
 
-fn term(i: &str) -> IResult<&str, Expression> {
-    let (i, init) = factor(i)?;
+/// Calls a user-defined function. Its body sees the arguments and the
+/// variables, but not the arguments of the function calling it.
+fn call<'src>(
+    name: &'src str,
+    args: &[Expression<'src>],
+    env: &Env<'_, 'src>,
+) -> EvalResult<'src> {
+    let Some(function) = env.function(name) else {
+        return Err(EvalError::UnknownFunction(name));
+    };
+    if args.len() != function.params.len() {
+        return Err(EvalError::WrongArity {
+            name,
+            expected: Arity::exact(function.params.len()),
+            found: args.len(),
+        });
+    }
+    if MAX_CALL_DEPTH <= env.depth {
+        return Err(EvalError::RecursionLimit(name));
+    }
+    let values = function
+        .params
+        .iter()
+        .zip(args)
+        .map(|(param, arg)| Ok((*param, eval(arg, env)?)))
+        .collect::<Result<HashMap<_, _>, _>>()?;
+    eval(&function.body, &env.with_args(&values))
+}
 
-    fold_many0(
-        pair(
-            delimited(multispace0, alt((char('*'), char('/'))), multispace0),
-            factor,
-        ),
-        move || init.clone(),
-        |acc, (op, val): (char, Expression)| match op {
-            '*' => Expression::Mul(Box::new(acc), Box::new(val)),
-            '/' => Expression::Div(Box::new(acc), Box::new(val)),
-            _ => panic!(
-                "Multiplicative expression should have '*' or '/' operator"
-            ),
-        },
-    )(i)
+/// Something an operator is applied to, which can be evaluated and has a
+/// location for errors: an expression with its [`Env`], or a compiled
+/// expression with its inputs.
+pub(crate) trait Operand<'src> {
+    fn value(&self) -> EvalResult<'src>;
+    fn span(&self) -> Option<&'src str>;
 }
 
-pub fn expr(i: &str) -> IResult<&str, Expression> {
-    let (i, init) = term(i)?;
+impl<'src, O: Operand<'src>> Operand<'src> for &O {
+    fn value(&self) -> EvalResult<'src> {
+        (*self).value()
+    }
 
-    fold_many0(
-        pair(
-            delimited(multispace0, alt((char('+'), char('-'))), multispace0),
-            term,
-        ),
-        move || init.clone(),
-        |acc, (op, val): (char, Expression)| match op {
-            '+' => Expression::Add(Box::new(acc), Box::new(val)),
-            '-' => Expression::Sub(Box::new(acc), Box::new(val)),
-            _ => panic!("Additive expression should have '+' or '-' operator"),
-        },
-    )(i)
+    fn span(&self) -> Option<&'src str> {
+        (*self).span()
+    }
 }
 
-pub fn block(i: &str) -> IResult<&str, Arg> {
-    let (rest, ex) = delimited(char('{'), many0(command), char('}'))(i)?;
-    Ok((rest, Arg::Block(ex)))
+impl<'src> Operand<'src> for (&Expression<'src>, &Env<'_, 'src>) {
+    fn value(&self) -> EvalResult<'src> {
+        eval(self.0, self.1)
+    }
+
+    fn span(&self) -> Option<&'src str> {
+        self.0.span()
+    }
 }
 
-fn identifier_string(i: &str) -> IResult<&str, Arg> {
-    let (rest, id) = identifier(i).map(|(r, s)| (r, s))?;
-    Ok((rest, Arg::Str(id)))
+pub(crate) fn num<'src>(
+    operand: impl Operand<'src>,
+) -> Result<f64, EvalError<'src>> {
+    operand.value()?.as_num().map_err(|e| e.at(operand.span()))
 }
 
-fn arg(i: &str) -> IResult<&str, Arg> {
-    delimited(space0, alt((block, identifier_string)), space0)(i)
+fn quantity<'src>(
+    operand: impl Operand<'src>,
+) -> Result<(f64, Dim), EvalError<'src>> {
+    quantity_of(&operand.value()?, operand.span())
 }
 
-#[derive(Debug, PartialEq)]
-pub enum Arg<'src> {
-    Str(&'src str),
-    Block(Vec<Command<'src>>),
+/// Returns the magnitude and dimension of the value of an operand at `at`.
+fn quantity_of<'src>(
+    value: &Value,
+    at: Option<&'src str>,
+) -> Result<(f64, Dim), EvalError<'src>> {
+    value.as_quantity().map_err(|e| e.at(at))
 }
 
-#[derive(Debug, PartialEq)]
-pub enum Property<'src> {
-    Str(String),
-    Expr(Expression<'src>),
+fn vector<'src>(
+    operand: impl Operand<'src>,
+) -> Result<([f64; 3], Dim), EvalError<'src>> {
+    match operand.value()? {
+        Value::Vec3(v, dim) => Ok((v, dim)),
+        value => Err(value.mismatch("vector").at(operand.span())),
+    }
 }
 
-#[derive(Debug, PartialEq)]
-pub enum Command<'src> {
-    Com(Vec<Arg<'src>>),
-    Prop(&'src str, Property<'src>),
-    Def(&'src str, Expression<'src>),
+pub(crate) fn boolean<'src>(
+    operand: impl Operand<'src>,
+) -> Result<bool, EvalError<'src>> {
+    operand.value()?.as_bool().map_err(|e| e.at(operand.span()))
 }
 
-fn newlines(i: &str) -> IResult<&str, ()> {
-    delimited(space0, many1(one_of("\r\n")), space0)(i)
-        .map(|(rest, _)| (rest, ()))
+/// Evaluates both operands of an operator that requires them to have the
+/// same dimension, such as `+` or `<`.
+fn same_dim<'src>(
+    lhs: impl Operand<'src>,
+    rhs: impl Operand<'src>,
+) -> Result<(f64, f64, Dim), EvalError<'src>> {
+    let (a, lhs_dim) = quantity(&lhs)?;
+    let (b, rhs_dim) = quantity(&rhs)?;
+    let dim = match_dims(lhs_dim, rhs_dim, &lhs, &rhs)?;
+    Ok((a, b, dim))
 }
 
-fn string(i: &str) -> IResult<&str, String> {
-    let (i, s) = delimited(char('"'), many0(none_of("\"")), char('"'))(i)?;
-    Ok((
-        i,
-        String::from_utf8(s.into_iter().map(|c| c as u8).collect()).unwrap(),
-    ))
+fn match_dims<'src>(
+    lhs_dim: Dim,
+    rhs_dim: Dim,
+    lhs: impl Operand<'src>,
+    rhs: impl Operand<'src>,
+) -> Result<Dim, EvalError<'src>> {
+    if lhs_dim != rhs_dim {
+        return Err(EvalError::DimensionMismatch {
+            expected: lhs_dim,
+            found: rhs_dim,
+            at: rhs.span().or_else(|| lhs.span()),
+        });
+    }
+    Ok(lhs_dim)
 }
 
-pub fn command(i: &str) -> IResult<&str, Command> {
-    fn com(i: &str) -> IResult<&str, Command> {
-        let (i, res) = many1(arg)(i)?;
-        Ok((i, Command::Com(res)))
+/// Evaluates `+`, `-` or `%`, of numbers or of the components of vectors.
+pub(crate) fn additive<'src>(
+    lhs: impl Operand<'src>,
+    rhs: impl Operand<'src>,
+    f: fn(f64, f64) -> f64,
+) -> EvalResult<'src> {
+    let a = lhs.value()?;
+    if let Value::Vec3(u, lhs_dim) = a {
+        let (v, rhs_dim) = vector(&rhs)?;
+        let dim = match_dims(lhs_dim, rhs_dim, &lhs, &rhs)?;
+        return Ok(Value::Vec3(std::array::from_fn(|i| f(u[i], v[i])), dim));
     }
+    let (a, lhs_dim) = quantity_of(&a, lhs.span())?;
+    let (b, rhs_dim) = quantity(&rhs)?;
+    let dim = match_dims(lhs_dim, rhs_dim, &lhs, &rhs)?;
+    Ok(Value::quantity(f(a, b), dim))
+}
 
-    fn def(i: &str) -> IResult<&str, Command> {
-        let (i, name) = identifier(i)?;
-        let (i, _) = delimited(space0, char('='), space0)(i)?;
-        let (i, ex) = expr(i)?;
-        Ok((i, Command::Def(name, ex)))
+/// Evaluates `*` or `/`, whose result has the product or quotient of the
+/// dimensions of the operands. A vector is multiplied or divided component
+/// by component by a number.
+pub(crate) fn multiplicative<'src>(
+    lhs: impl Operand<'src>,
+    rhs: impl Operand<'src>,
+    f: fn(f64, f64) -> f64,
+    dim: fn(Dim, Dim) -> Option<Dim>,
+) -> EvalResult<'src> {
+    let overflow = || EvalError::DimensionOverflow { at: lhs.span() };
+    let a = lhs.value()?;
+    if let Value::Vec3(u, lhs_dim) = a {
+        let (b, rhs_dim) = quantity(&rhs)?;
+        let dim = dim(lhs_dim, rhs_dim).ok_or_else(overflow)?;
+        return Ok(Value::Vec3(u.map(|x| f(x, b)), dim));
+    }
+    let (a, lhs_dim) = quantity_of(&a, lhs.span())?;
+    let b = rhs.value()?;
+    if let Value::Vec3(v, rhs_dim) = b {
+        let dim = dim(lhs_dim, rhs_dim).ok_or_else(overflow)?;
+        return Ok(Value::Vec3(v.map(|x| f(a, x)), dim));
+    }
+    let (b, rhs_dim) = quantity_of(&b, rhs.span())?;
+    let dim = dim(lhs_dim, rhs_dim).ok_or_else(overflow)?;
+    Ok(Value::quantity(f(a, b), dim))
+}
+
+/// Evaluates `^`. Quantities with a dimension can only be raised to integer
+/// powers.
+pub(crate) fn power_of<'src>(
+    lhs: impl Operand<'src>,
+    rhs: impl Operand<'src>,
+) -> EvalResult<'src> {
+    let (base, dim) = quantity(&lhs)?;
+    let exponent = num(rhs)?;
+    if dim.is_none() {
+        return Ok(Value::Num(base.powf(exponent)));
     }
+    if exponent.fract() != 0. {
+        return Err(EvalError::FractionalPower {
+            base: dim,
+            at: lhs.span(),
+        });
+    }
+    // Exponents outside the range of `i8` overflow any dimension.
+    let power = i8::try_from(exponent as i64).ok();
+    let Some(dim) = power.and_then(|n| dim.checked_powi(n)) else {
+        return Err(EvalError::DimensionOverflow { at: lhs.span() });
+    };
+    Ok(Value::quantity(base.powf(exponent), dim))
+}
 
-    fn prop(i: &str) -> IResult<&str, Command> {
-        fn string_prop(i: &str) -> IResult<&str, Property> {
-            let (i, s) = string(i)?;
-            Ok((i, Property::Str(s.to_string())))
+pub(crate) fn negate<'src>(operand: impl Operand<'src>) -> EvalResult<'src> {
+    match operand.value()? {
+        Value::Vec3(v, dim) => Ok(Value::Vec3(v.map(|x| -x), dim)),
+        value => {
+            let (value, dim) = quantity_of(&value, operand.span())?;
+            Ok(Value::quantity(-value, dim))
         }
-        fn expr_prop(i: &str) -> IResult<&str, Property> {
-            let (i, ex) = expr(i)?;
-            Ok((i, Property::Expr(ex)))
-        }
-        let (i, res) = identifier(i)?;
-        let (i, _) = delimited(space0, char(':'), space0)(i)?;
-        let (i, ex) = alt((string_prop, expr_prop))(i)?;
-        Ok((i, Command::Prop(res, ex)))
     }
+}
 
-    delimited(opt(newlines), alt((prop, def, com)), opt(newlines))(i)
+/// Evaluates an ordering comparison, which is only defined for numbers and
+/// quantities of the same dimension.
+pub(crate) fn compare<'src>(
+    lhs: impl Operand<'src>,
+    rhs: impl Operand<'src>,
+    f: fn(&f64, &f64) -> bool,
+) -> EvalResult<'src> {
+    let (a, b, _) = same_dim(lhs, rhs)?;
+    Ok(Value::Bool(f(&a, &b)))
 }
 
-pub fn commands(i: &str) -> IResult<&str, Vec<Command>> {
-    many0(command)(i)
+/// Evaluates `==`. Comparing values of different types is an error rather
+/// than `false`, since it is almost certainly a mistake.
+pub(crate) fn equals<'src>(
+    lhs: impl Operand<'src>,
+    rhs: impl Operand<'src>,
+) -> Result<bool, EvalError<'src>> {
+    let lhs_value = lhs.value()?;
+    let rhs_value = rhs.value()?;
+    if let (Ok((a, lhs_dim)), Ok((b, rhs_dim))) =
+        (lhs_value.as_quantity(), rhs_value.as_quantity())
+    {
+        if lhs_dim != rhs_dim {
+            return Err(EvalError::DimensionMismatch {
+                expected: lhs_dim,
+                found: rhs_dim,
+                at: rhs.span().or_else(|| lhs.span()),
+            });
+        }
+        return Ok(a == b);
+    }
+    if lhs_value.type_name() != rhs_value.type_name() {
+        return Err(rhs_value.mismatch(lhs_value.type_name()).at(rhs.span()));
+    }
+    Ok(lhs_value == rhs_value)
 }
 
-#[cfg(test)]
-mod test {
-    use super::*;
+/// Calls a function implemented in Rust, whose arity has been checked.
+pub(crate) fn call_native<'src, O: Operand<'src>>(
+    native: &NativeFunction,
+    args: impl ExactSizeIterator<Item = O>,
+    call: Option<&'src str>,
+) -> EvalResult<'src> {
+    // A function of one argument can only fail because of it.
+    let mut at = call;
+    let single = args.len() == 1;
+    let values = args
+        .map(|arg| {
+            if single {
+                at = arg.span();
+            }
+            arg.value()
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    (native.f)(&values).map_err(|e| e.at(at))
+}
 
-    #[test]
-    fn test_number() {
-        assert_eq!(
-            number("123.45 "),
-            Ok((" ", Expression::NumLiteral(123.45)))
-        );
-    }
+/// A part of a string with `${...}` interpolations.
+#[derive(Debug, PartialEq)]
+pub(crate) enum Piece<'src> {
+    /// Text with its escapes replaced by the characters they stand for.
+    Text(String),
+    Expr(Expression<'src>),
+    /// A `${...}` that does not hold an expression, after which the rest
+    /// of the string is ignored.
+    Invalid(EvalError<'src>),
+}
 
-    #[test]
-    fn test_block() {
-        assert_eq!(
-            block("{p}"),
-            Ok(("", Arg::Block(vec![Command::Com(vec![Arg::Str("p")])])))
-        );
+/// Splits `s` into text and the expressions inside `${...}`. In the text,
+/// `\"`, `\\` and `\$` stand for `"`, `\` and `$`, so `\${` is not an
+/// interpolation, and any other backslash stands for itself.
+pub(crate) fn pieces(s: &str) -> Vec<Piece<'_>> {
+    let mut pieces = vec![];
+    let mut text = String::new();
+    let mut rest = s;
+    while let Some(start) = rest.find(['\\', '$']) {
+        text += &rest[..start];
+        rest = &rest[start..];
+        if let Some(escaped) = rest.strip_prefix('\\') {
+            match escaped.chars().next() {
+                Some(c @ ('"' | '\\' | '$')) => {
+                    text.push(c);
+                    rest = &escaped[1..];
+                }
+                _ => {
+                    text.push('\\');
+                    rest = escaped;
+                }
+            }
+            continue;
+        }
+        let Some(inner) = rest.strip_prefix("${") else {
+            text.push('$');
+            rest = &rest[1..];
+            continue;
+        };
+        pieces.push(Piece::Text(std::mem::take(&mut text)));
+        let Some(end) = inner.find('}') else {
+            let error = EvalError::InvalidInterpolation(rest);
+            pieces.push(Piece::Invalid(error));
+            return pieces;
+        };
+        let Ok((_, ex)) = terminated(expr, pair(ws0, eof))(&inner[..end])
+        else {
+            let error = EvalError::InvalidInterpolation(&inner[..end]);
+            pieces.push(Piece::Invalid(error));
+            return pieces;
+        };
+        pieces.push(Piece::Expr(ex));
+        rest = &inner[end + 1..];
     }
+    text += rest;
+    pieces.push(Piece::Text(text));
+    pieces
+}
 
-    #[test]
-    fn test_multiline() {
-        assert_eq!(
-            block(
-                "{
-            p
-}"
-            ),
-            Ok(("", Arg::Block(vec![Command::Com(vec![Arg::Str("p")])])))
-        );
-    }
+/// Returns `s` as the text of a string between quotes, which [`pieces`]
+/// reads back as `s`.
+pub(crate) fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace("${", "\\${")
+}
 
-    #[test]
-    fn test_command() {
-        assert_eq!(
-            command(
-                r#"a  {
-            p
-        }"#
-            ),
-            Ok((
-                "",
-                Command::Com(vec![
-                    Arg::Str("a"),
-                    Arg::Block(vec![Command::Com(vec![Arg::Str("p")])])
-                ])
-            ))
-        );
+/// Appends an interpolated value to a string.
+pub(crate) fn push_value(out: &mut String, value: Value) {
+    match value {
+        Value::Str(s) => *out += &s,
+        value => *out += &value.to_string(),
     }
+}
 
-    #[test]
-    fn test_prop() {
-        assert_eq!(
-            command("hello: world"),
-            Ok((
-                "",
-                Command::Prop(
-                    "hello",
-                    Property::Expr(Expression::Ident("world"))
-                )
-            ))
-        );
+/// Replaces each `${expr}` in `s` with the value of `expr`.
+pub(crate) fn interpolate<'src>(
+    s: &'src str,
+    env: &Env<'_, 'src>,
+) -> Result<String, EvalError<'src>> {
+    let mut out = String::new();
+    for piece in pieces(s) {
+        match piece {
+            Piece::Text(text) => out += &text,
+            Piece::Expr(ex) => push_value(&mut out, eval(&ex, env)?),
+            Piece::Invalid(error) => return Err(error),
+        }
     }
+    Ok(out)
+}
 
-    #[test]
-    fn test_string_prop() {
-        assert_eq!(
-            command(r#"hello: "world""#),
-            Ok((
-                "",
-                Command::Prop("hello", Property::Str("world".to_owned()))
-            ))
-        );
-    }
+pub(crate) fn eval<'src>(
+    expr: &Expression<'src>,
+    env: &Env<'_, 'src>,
+) -> EvalResult<'src> {
+    Ok(match expr {
+        Expression::Ident("pi") => Value::Num(std::f64::consts::PI),
+        Expression::Ident(id) => {
+            if let Some(val) = env.get(id) {
+                val.clone()
+            } else {
+                return Err(EvalError::UnknownName(id));
+            }
+        }
+        Expression::Field(body, name) => {
+            let Some(properties) = env.body(body) else {
+                return Err(match *body {
+                    "self" | "parent" => EvalError::NoBody(body),
+                    _ => EvalError::UnknownName(body),
+                });
+            };
+            match properties.get(*name) {
+                Some(value) => value.clone(),
+                None => return Err(EvalError::UnsetProperty(body, name)),
+            }
+        }
+        Expression::NumLiteral(n) => Value::Num(*n),
+        Expression::Quantity(n, unit) => {
+            // The parser only accepts known units.
+            let (factor, dim) = units::lookup(unit).unwrap();
+            Value::quantity(n * factor, dim)
+        }
+        Expression::BoolLiteral(b) => Value::Bool(*b),
+        Expression::StrLiteral(s) => Value::Str(interpolate(s, env)?),
+        // The parser only accepts valid colors.
+        Expression::Color(s) => Value::Color(Color::from_hex(&s[1..]).unwrap()),
+        Expression::List(items) => Value::List(
+            items
+                .iter()
+                .map(|item| eval(item, env))
+                .collect::<Result<_, _>>()?,
+        ),
+        Expression::FnInvoke(name @ "if", args) => {
+            // The parser turns well-formed calls into `Cond`.
+            return Err(EvalError::WrongArity {
+                name,
+                expected: Arity::exact(3),
+                found: args.len(),
+            });
+        }
+        Expression::FnInvoke(name, args) => match env.natives.get(name) {
+            Some(native) => {
+                if !native.arity.accepts(args.len()) {
+                    return Err(EvalError::WrongArity {
+                        name,
+                        expected: native.arity,
+                        found: args.len(),
+                    });
+                }
+                let args = args.iter().map(|arg| (arg, env));
+                call_native(native, args, expr.span())?
+            }
+            None => call(name, args, env)?,
+        },
+        Expression::Add(lhs, rhs) => {
+            additive((&**lhs, env), (&**rhs, env), |a, b| a + b)?
+        }
+        Expression::Sub(lhs, rhs) => {
+            additive((&**lhs, env), (&**rhs, env), |a, b| a - b)?
+        }
+        Expression::Mul(lhs, rhs) => multiplicative(
+            (&**lhs, env),
+            (&**rhs, env),
+            |a, b| a * b,
+            Dim::checked_mul,
+        )?,
+        Expression::Div(lhs, rhs) => multiplicative(
+            (&**lhs, env),
+            (&**rhs, env),
+            |a, b| a / b,
+            Dim::checked_div,
+        )?,
+        Expression::Mod(lhs, rhs) => {
+            additive((&**lhs, env), (&**rhs, env), |a, b| a % b)?
+        }
+        Expression::Pow(lhs, rhs) => power_of((&**lhs, env), (&**rhs, env))?,
+        Expression::Neg(ex) => negate((&**ex, env))?,
+        Expression::Lt(lhs, rhs) => {
+            compare((&**lhs, env), (&**rhs, env), f64::lt)?
+        }
+        Expression::Le(lhs, rhs) => {
+            compare((&**lhs, env), (&**rhs, env), f64::le)?
+        }
+        Expression::Gt(lhs, rhs) => {
+            compare((&**lhs, env), (&**rhs, env), f64::gt)?
+        }
+        Expression::Ge(lhs, rhs) => {
+            compare((&**lhs, env), (&**rhs, env), f64::ge)?
+        }
+        Expression::Eq(lhs, rhs) => {
+            Value::Bool(equals((&**lhs, env), (&**rhs, env))?)
+        }
+        Expression::Ne(lhs, rhs) => {
+            Value::Bool(!equals((&**lhs, env), (&**rhs, env))?)
+        }
+        Expression::And(lhs, rhs) => {
+            Value::Bool(boolean((&**lhs, env))? && boolean((&**rhs, env))?)
+        }
+        Expression::Or(lhs, rhs) => {
+            Value::Bool(boolean((&**lhs, env))? || boolean((&**rhs, env))?)
+        }
+        Expression::Not(ex) => Value::Bool(!boolean((&**ex, env))?),
+        Expression::Cond(cond, then, els) => {
+            if boolean((&**cond, env))? {
+                eval(then, env)?
+            } else {
+                eval(els, env)?
+            }
+        }
+    })
+}
+
+fn factor(i: &str) -> IResult<&str, Expression<'_>> {
+    alt((
+        number,
+        str_literal,
+        color,
+        list,
+        func_call,
+        field,
+        ident,
+        parens,
+    ))(i)
+}
+
+/// A `#` followed by 3, 4, 6 or 8 hex digits and no other letter or digit,
+/// such as `#ff8800`, is a color rather than a comment.
+fn color_literal(i: &str) -> IResult<&str, &str> {
+    recognize(terminated(
+        pair(
+            char('#'),
+            verify(hex_digit1, |hex: &str| Color::from_hex(hex).is_some()),
+        ),
+        not(alt((alphanumeric1, tag("_")))),
+    ))(i)
+}
+
+fn color(i: &str) -> IResult<&str, Expression<'_>> {
+    let (r, s) = preceded(ws0, color_literal)(i)?;
+    Ok((r, Expression::Color(s)))
+}
+
+fn list(i: &str) -> IResult<&str, Expression<'_>> {
+    let (r, items) = preceded(
+        ws0,
+        delimited(
+            char('['),
+            many0(delimited(ws0, expr, delimited(ws0, opt(tag(",")), ws0))),
+            pair(ws0, char(']')),
+        ),
+    )(i)?;
+    Ok((r, Expression::List(items)))
+}
+
+fn field(i: &str) -> IResult<&str, Expression<'_>> {
+    let (r, (body, name)) =
+        preceded(ws0, pair(identifier, preceded(char('.'), identifier)))(i)?;
+    Ok((r, Expression::Field(body, name)))
+}
+
+fn func_call(i: &str) -> IResult<&str, Expression<'_>> {
+    let (r, ident) = preceded(ws0, identifier)(i)?;
+    // println!("func_invoke ident: {}", ident);
+    let (r, args) = preceded(
+        ws0,
+        delimited(
+            tag("("),
+            many0(delimited(ws0, expr, delimited(ws0, opt(tag(",")), ws0))),
+            tag(")"),
+        ),
+    )(r)?;
+    if ident == "if" && args.len() == 3 {
+        let mut args = args.into_iter().map(Box::new);
+        let (cond, then, els) = (
+            args.next().unwrap(),
+            args.next().unwrap(),
+            args.next().unwrap(),
+        );
+        return Ok((r, Expression::Cond(cond, then, els)));
+    }
+    Ok((r, Expression::FnInvoke(ident, args)))
+}
+
+fn ident(input: &str) -> IResult<&str, Expression<'_>> {
+    let (r, res) = preceded(ws0, identifier)(input)?;
+    Ok((
+        r,
+        match res {
+            "true" => Expression::BoolLiteral(true),
+            "false" => Expression::BoolLiteral(false),
+            _ => Expression::Ident(res),
+        },
+    ))
+}
+
+fn identifier(input: &str) -> IResult<&str, &str> {
+    recognize(pair(
+        alt((alpha1, tag("_"))),
+        many0(alt((alphanumeric1, tag("_")))),
+    ))(input)
+}
+
+/// A number literal, optionally followed by a unit such as `km` or `d`.
+fn number(input: &str) -> IResult<&str, Expression<'_>> {
+    let (mut r, mut v) = preceded(ws0, recognize_float)(input)?;
+    // Leave the dot in `0..N` to the range.
+    if v.ends_with('.') && r.starts_with('.') {
+        v = &v[..v.len() - 1];
+        r = &input[input.len() - r.len() - 1..];
+    }
+    let v = v.parse().map_err(|_| {
+        nom::Err::Error(nom::error::Error {
+            input,
+            code: nom::error::ErrorKind::Digit,
+        })
+    })?;
+    let (r, unit) = opt(preceded(
+        space0,
+        verify(identifier, |unit: &str| units::lookup(unit).is_some()),
+    ))(r)?;
+    Ok((
+        r,
+        match unit {
+            Some(unit) => Expression::Quantity(v, unit),
+            None => Expression::NumLiteral(v),
+        },
+    ))
+}
+
+/// The text between the quotes of a string, in which a backslash keeps
+/// the character after it from ending the string.
+fn string_text<'src>(
+    stop: &'static str,
+) -> impl FnMut(&'src str) -> IResult<&'src str, &'src str> {
+    recognize(fold_many0(
+        alt((preceded(char('\\'), anychar), none_of(stop))),
+        || (),
+        |_, _| (),
+    ))
+}
+
+fn str_literal(i: &str) -> IResult<&str, Expression<'_>> {
+    let (i, s) =
+        preceded(ws0, delimited(char('"'), string_text("\"\\"), char('"')))(i)?;
+    Ok((i, Expression::StrLiteral(s)))
+}
+
+fn parens(i: &str) -> IResult<&str, Expression<'_>> {
+    preceded(ws0, delimited(tag("("), expr, pair(ws0, tag(")"))))(i)
+}
+
+/// Exponentiation binds tighter than unary minus, so `-2^2` is `-(2^2)`,
+/// and it is right associative, so `2^3^2` is `2^(3^2)`.
+fn power(i: &str) -> IResult<&str, Expression<'_>> {
+    let (i, base) = factor(i)?;
+    let (i, exponent) = opt(preceded(
+        delimited(ws0, alt((tag("**"), tag("^"))), ws0),
+        unary,
+    ))(i)?;
+    Ok(match exponent {
+        Some(exponent) => {
+            (i, Expression::Pow(Box::new(base), Box::new(exponent)))
+        }
+        None => (i, base),
+    })
+}
+
+fn unary(i: &str) -> IResult<&str, Expression<'_>> {
+    let (r, op) = opt(delimited(ws0, one_of("+-!"), ws0))(i)?;
+    match op {
+        Some('-') => {
+            let (r, ex) = unary(r)?;
+            Ok((r, Expression::Neg(Box::new(ex))))
+        }
+        Some('!') => {
+            let (r, ex) = unary(r)?;
+            Ok((r, Expression::Not(Box::new(ex))))
+        }
+        Some(_) => unary(r),
+        None => power(i),
+    }
+}
+
+fn term(i: &str) -> IResult<&str, Expression<'_>> {
+    let (i, init) = unary(i)?;
+
+    fold_many0(
+        pair(delimited(ws0, one_of("*/%"), ws0), unary),
+        move || init.clone(),
+        |acc, (op, val): (char, Expression)| match op {
+            '*' => Expression::Mul(Box::new(acc), Box::new(val)),
+            '/' => Expression::Div(Box::new(acc), Box::new(val)),
+            '%' => Expression::Mod(Box::new(acc), Box::new(val)),
+            _ => {
+                panic!("Multiplicative expression should have '*', '/' or '%'")
+            }
+        },
+    )(i)
+}
+
+fn sum(i: &str) -> IResult<&str, Expression<'_>> {
+    let (i, init) = term(i)?;
+
+    fold_many0(
+        pair(delimited(ws0, alt((char('+'), char('-'))), ws0), term),
+        move || init.clone(),
+        |acc, (op, val): (char, Expression)| match op {
+            '+' => Expression::Add(Box::new(acc), Box::new(val)),
+            '-' => Expression::Sub(Box::new(acc), Box::new(val)),
+            _ => panic!("Additive expression should have '+' or '-' operator"),
+        },
+    )(i)
+}
+
+/// Comparisons do not chain, so `a < b < c` is a syntax error.
+fn comparison(i: &str) -> IResult<&str, Expression<'_>> {
+    let (i, lhs) = sum(i)?;
+    let (i, rhs) = opt(pair(
+        delimited(
+            ws0,
+            alt((
+                tag("<="),
+                tag(">="),
+                tag("=="),
+                tag("!="),
+                tag("<"),
+                tag(">"),
+            )),
+            ws0,
+        ),
+        sum,
+    ))(i)?;
+    let Some((op, rhs)) = rhs else {
+        return Ok((i, lhs));
+    };
+    let (lhs, rhs) = (Box::new(lhs), Box::new(rhs));
+    Ok((
+        i,
+        match op {
+            "<=" => Expression::Le(lhs, rhs),
+            ">=" => Expression::Ge(lhs, rhs),
+            "==" => Expression::Eq(lhs, rhs),
+            "!=" => Expression::Ne(lhs, rhs),
+            "<" => Expression::Lt(lhs, rhs),
+            ">" => Expression::Gt(lhs, rhs),
+            _ => panic!("Comparison should have a comparison operator"),
+        },
+    ))
+}
+
+fn and(i: &str) -> IResult<&str, Expression<'_>> {
+    let (i, init) = comparison(i)?;
+
+    fold_many0(
+        preceded(delimited(ws0, tag("&&"), ws0), comparison),
+        move || init.clone(),
+        |acc, val| Expression::And(Box::new(acc), Box::new(val)),
+    )(i)
+}
+
+fn or(i: &str) -> IResult<&str, Expression<'_>> {
+    let (i, init) = and(i)?;
+
+    fold_many0(
+        preceded(delimited(ws0, tag("||"), ws0), and),
+        move || init.clone(),
+        |acc, val| Expression::Or(Box::new(acc), Box::new(val)),
+    )(i)
+}
+
+/// The conditional operator `cond ? then : else` has the lowest precedence
+/// and is right associative.
+pub fn expr(i: &str) -> IResult<&str, Expression<'_>> {
+    let (i, cond) = or(i)?;
+    let (i, branches) = opt(pair(
+        preceded(delimited(ws0, char('?'), ws0), expr),
+        preceded(delimited(ws0, char(':'), ws0), expr),
+    ))(i)?;
+    Ok(match branches {
+        Some((then, els)) => (
+            i,
+            Expression::Cond(Box::new(cond), Box::new(then), Box::new(els)),
+        ),
+        None => (i, cond),
+    })
+}
+
+pub fn block(i: &str) -> IResult<&str, Arg<'_>> {
+    let (i, first) = preceded(char('{'), opt(trailing_comment))(i)?;
+    let (rest, mut ex) = terminated(
+        statements(alt((command, unparsed))),
+        pair(ws0, char('}')),
+    )(i)?;
+    if let Some(comment) = first {
+        ex.insert(0, comment);
+    }
+    Ok((rest, Arg::Block(ex)))
+}
+
+fn identifier_string(i: &str) -> IResult<&str, Arg<'_>> {
+    let (rest, id) = identifier(i)?;
+    Ok((rest, Arg::Str(id)))
+}
+
+/// A quoted name, which may contain `${...}` interpolations.
+fn quoted_string(i: &str) -> IResult<&str, Arg<'_>> {
+    let (rest, s) = delimited(char('"'), string_text("\"\\\n"), char('"'))(i)?;
+    Ok((rest, Arg::Quoted(s)))
+}
+
+/// `: Name`, naming the template an `astro` or `template` block inherits
+/// from.
+fn base(i: &str) -> IResult<&str, Arg<'_>> {
+    let (rest, name) = preceded(pair(char(':'), sp0), identifier)(i)?;
+    Ok((rest, Arg::Base(name)))
+}
+
+/// `astro` or `template` and its name, with the template it inherits from
+/// if any, which cannot follow anything else.
+fn named_with_base(i: &str) -> IResult<&str, Vec<Arg<'_>>> {
+    let keyword =
+        verify(identifier, |id: &str| matches!(id, "astro" | "template"));
+    let (i, keyword) = preceded(sp0, keyword)(i)?;
+    let (i, name) = preceded(sp0, alt((identifier_string, quoted_string)))(i)?;
+    let (i, base) = opt(preceded(sp0, base))(i)?;
+    let mut args = vec![Arg::Str(keyword), name];
+    args.extend(base);
+    Ok((i, args))
+}
+
+fn arg(i: &str) -> IResult<&str, Arg<'_>> {
+    preceded(sp0, alt((block, identifier_string, quoted_string)))(i)
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Arg<'src> {
+    Str(&'src str),
+    /// The contents of a quoted string, without the quotes.
+    Quoted(&'src str),
+    Base(&'src str),
+    Block(Vec<Command<'src>>),
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Property<'src> {
+    Str(String),
+    Expr(Expression<'src>),
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Command<'src> {
+    Com(Vec<Arg<'src>>),
+    Prop(&'src str, Property<'src>),
+    /// `name = expr`, which assigns to an existing variable or else defines
+    /// one in the current block.
+    Def(&'src str, Expression<'src>),
+    /// `let name = expr`, defining a variable in the current block.
+    Let(&'src str, Expression<'src>),
+    /// `const name = expr`, defining a variable that cannot be reassigned.
+    Const(&'src str, Expression<'src>),
+    /// `fn name(params) = body`. The `fn` keyword is optional.
+    FnDef(&'src str, Vec<&'src str>, Expression<'src>),
+    /// `include "path"` or `import "path"`, replaced by the commands of the
+    /// named file when the scene is loaded.
+    Include(&'src str),
+    /// `for var in start..end { ... }`, repeating the block with `var` set
+    /// to each integer from `start` up to but excluding `end`.
+    For(
+        &'src str,
+        Expression<'src>,
+        Expression<'src>,
+        Vec<Command<'src>>,
+    ),
+    /// A comment on a line of its own, including its `#`, `//` or `/*`.
+    Comment(&'src str),
+    /// A comment after a statement or a `{` on the same line.
+    TrailingComment(&'src str),
+    /// A statement that failed to parse, kept so that it can be reported
+    /// after parsing has recovered.
+    Error(&'src str),
+}
+
+fn line_comment(i: &str) -> IResult<&str, &str> {
+    recognize(pair(alt((tag("#"), tag("//"))), not_line_ending))(i)
+}
+
+fn block_comment(i: &str) -> IResult<&str, &str> {
+    recognize(delimited(tag("/*"), take_until("*/"), tag("*/")))(i)
+}
+
+fn comment(i: &str) -> IResult<&str, &str> {
+    alt((line_comment, block_comment))(i)
+}
+
+/// A line comment inside a statement, which cannot start with a color.
+/// Comments on a line of their own or after a statement can.
+fn inline_comment(i: &str) -> IResult<&str, &str> {
+    preceded(not(color_literal), line_comment)(i)
+}
+
+/// Skips spaces, tabs and comments, but not line breaks, which separate
+/// statements.
+fn sp0(i: &str) -> IResult<&str, ()> {
+    fold_many0(
+        alt((space1, inline_comment, block_comment)),
+        || (),
+        |_, _| (),
+    )(i)
+}
+
+/// Skips any whitespace including line breaks, and comments.
+fn ws0(i: &str) -> IResult<&str, ()> {
+    fold_many0(
+        alt((multispace1, inline_comment, block_comment)),
+        || (),
+        |_, _| (),
+    )(i)
+}
+
+/// Skips line breaks and the spaces around them. Comments are left to be
+/// parsed as commands.
+fn newlines(i: &str) -> IResult<&str, ()> {
+    terminated(many1(preceded(space0, one_of("\r\n"))), space0)(i)
+        .map(|(rest, _)| (rest, ()))
+}
+
+/// Checks that a statement ends here, at a line break, a `}`, a comment or
+/// the end of the input.
+fn end_of_statement(i: &str) -> IResult<&str, ()> {
+    let (i, _) = peek(preceded(
+        space0,
+        alt((eof, recognize(one_of("\r\n}")), comment)),
+    ))(i)?;
+    Ok((i, ()))
+}
+
+fn trailing_comment(i: &str) -> IResult<&str, Command<'_>> {
+    let (i, comment) = preceded(space0, comment)(i)?;
+    Ok((i, Command::TrailingComment(comment)))
+}
+
+/// Parses a sequence of statements, each followed by an optional comment on
+/// the same line.
+fn statements<'src>(
+    statement: impl FnMut(&'src str) -> IResult<&'src str, Command<'src>>,
+) -> impl FnMut(&'src str) -> IResult<&'src str, Vec<Command<'src>>> {
+    fold_many0(
+        pair(statement, opt(trailing_comment)),
+        Vec::new,
+        |mut acc, (command, comment)| {
+            acc.push(command);
+            acc.extend(comment);
+            acc
+        },
+    )
+}
+
+pub fn command(i: &str) -> IResult<&str, Command<'_>> {
+    fn com(i: &str) -> IResult<&str, Command<'_>> {
+        let (i, head) = opt(named_with_base)(i)?;
+        let (i, mut res) = match head {
+            Some(head) => (i, head),
+            None => map(arg, |arg| vec![arg])(i)?,
+        };
+        let (i, rest) = terminated(many0(arg), end_of_statement)(i)?;
+        res.extend(rest);
+        Ok((i, Command::Com(res)))
+    }
+
+    fn def(i: &str) -> IResult<&str, Command<'_>> {
+        let (i, keyword) = opt(terminated(
+            alt((tag("fn"), tag("let"), tag("const"))),
+            space1,
+        ))(i)?;
+        let (i, name) = identifier(i)?;
+        let params = delimited(
+            pair(sp0, char('(')),
+            separated_list0(char(','), delimited(ws0, identifier, ws0)),
+            char(')'),
+        );
+        let (i, params) = match keyword {
+            Some("fn") => map(params, Some)(i)?,
+            Some(_) => (i, None),
+            None => opt(params)(i)?,
+        };
+        let (i, _) = delimited(sp0, char('='), sp0)(i)?;
+        let (i, ex) = terminated(expr, end_of_statement)(i)?;
+        Ok((
+            i,
+            match (keyword, params) {
+                (_, Some(params)) => Command::FnDef(name, params, ex),
+                (Some("let"), None) => Command::Let(name, ex),
+                (Some("const"), None) => Command::Const(name, ex),
+                _ => Command::Def(name, ex),
+            },
+        ))
+    }
+
+    fn prop(i: &str) -> IResult<&str, Command<'_>> {
+        let (i, res) = identifier(i)?;
+        let (i, _) = delimited(sp0, char(':'), sp0)(i)?;
+        let (i, ex) = terminated(expr, end_of_statement)(i)?;
+        // A plain string is kept as is, so that textures can be found
+        // without evaluating anything.
+        let ex = match ex {
+            Expression::StrLiteral(s) => match &pieces(s)[..] {
+                [Piece::Text(text)] => Property::Str(text.clone()),
+                _ => Property::Expr(ex),
+            },
+            ex => Property::Expr(ex),
+        };
+        Ok((i, Command::Prop(res, ex)))
+    }
+
+    fn include(i: &str) -> IResult<&str, Command<'_>> {
+        let (i, _) = terminated(alt((tag("include"), tag("import"))), sp0)(i)?;
+        let (i, path) = terminated(
+            delimited(char('"'), take_while(|c| c != '"'), char('"')),
+            end_of_statement,
+        )(i)?;
+        Ok((i, Command::Include(path)))
+    }
+
+    fn for_loop(i: &str) -> IResult<&str, Command<'_>> {
+        let (i, var) =
+            delimited(pair(tag("for"), space1), identifier, space1)(i)?;
+        let (i, start) = preceded(pair(tag("in"), space1), expr)(i)?;
+        let (i, end) = preceded(pair(sp0, tag("..")), expr)(i)?;
+        let (i, body) = preceded(sp0, block)(i)?;
+        let Arg::Block(body) = body else {
+            unreachable!("block always returns Arg::Block")
+        };
+        Ok((i, Command::For(var, start, end, body)))
+    }
+
+    // Comments before a statement on the same line are kept as commands of
+    // their own, so only spaces are skipped.
+    preceded(
+        pair(opt(newlines), space0),
+        alt((
+            map(comment, Command::Comment),
+            include,
+            for_loop,
+            prop,
+            def,
+            com,
+        )),
+    )(i)
+}
+
+/// Skips a statement that failed to parse, up to the end of the line or the
+/// `}` closing the enclosing block, so that parsing can resume after it.
+/// Braces opened inside the statement are skipped as a whole, and braces in
+/// comments are ignored.
+fn unparsed(i: &str) -> IResult<&str, Command<'_>> {
+    let (i, _) = ws0(i)?;
+    let mut depth = 0;
+    let mut rest = i;
+    loop {
+        if let Ok((r, _)) = alt((line_comment, block_comment))(rest) {
+            rest = r;
+            continue;
+        }
+        let mut chars = rest.chars();
+        match chars.next() {
+            None => break,
+            Some('{') => depth += 1,
+            Some('}') if depth == 0 => break,
+            Some('}') => depth -= 1,
+            Some('\r' | '\n') if depth == 0 => break,
+            _ => (),
+        }
+        rest = chars.as_str();
+    }
+    let end = i.len() - rest.len();
+    let skipped = i[..end].trim_end();
+    if skipped.is_empty() {
+        return Err(nom::Err::Error(nom::error::Error::new(
+            i,
+            nom::error::ErrorKind::Verify,
+        )));
+    }
+    Ok((&i[end..], Command::Error(skipped)))
+}
+
+/// A `}` without a matching `{` at the top level.
+fn unmatched_brace(i: &str) -> IResult<&str, Command<'_>> {
+    let (i, brace) = preceded(ws0, recognize(char('}')))(i)?;
+    Ok((i, Command::Error(brace)))
+}
+
+pub fn commands(i: &str) -> IResult<&str, Vec<Command<'_>>> {
+    terminated(
+        statements(alt((command, unparsed, unmatched_brace))),
+        pair(ws0, eof),
+    )(i)
+}
+
+fn report_unparsed<'src>(
+    commands: &[Command<'src>],
+    diagnostics: &mut Diagnostics<'src>,
+) {
+    for command in commands {
+        match command {
+            Command::Error(s) if *s == "}" => {
+                diagnostics.error(s, "unexpected closing brace");
+            }
+            Command::Error(s)
+                if s.matches('{').count() > s.matches('}').count() =>
+            {
+                diagnostics.error(s, "unclosed `{` in this statement");
+            }
+            Command::Error(s) => {
+                diagnostics.error(s, "could not parse this statement");
+            }
+            Command::Com(args) => {
+                for arg in args {
+                    if let Arg::Block(block) = arg {
+                        report_unparsed(block, diagnostics);
+                    }
+                }
+            }
+            Command::For(_, _, _, body) => report_unparsed(body, diagnostics),
+            _ => (),
+        }
+    }
+}
+
+/// Parses a whole scene file. Statements that could not be parsed are
+/// reported to `diagnostics` and kept as [`Command::Error`] so that the rest
+/// of the file still loads.
+pub fn parse_scene<'src>(
+    source: &'src str,
+    diagnostics: &mut Diagnostics<'src>,
+) -> Vec<Command<'src>> {
+    match commands(source) {
+        Ok((_, commands)) => {
+            report_unparsed(&commands, diagnostics);
+            commands
+        }
+        Err(nom::Err::Error(e) | nom::Err::Failure(e)) => {
+            diagnostics.error(
+                e.input,
+                format!("syntax error ({})", e.code.description()),
+            );
+            vec![]
+        }
+        Err(nom::Err::Incomplete(_)) => {
+            diagnostics.error(source, "unexpected end of input");
+            vec![]
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_number() {
+        assert_eq!(
+            number("123.45 "),
+            Ok((" ", Expression::NumLiteral(123.45)))
+        );
+    }
+
+    #[test]
+    fn test_block() {
+        assert_eq!(
+            block("{p}"),
+            Ok(("", Arg::Block(vec![Command::Com(vec![Arg::Str("p")])])))
+        );
+    }
+
+    #[test]
+    fn test_single_line_block() {
+        let radius =
+            Command::Prop("radius", Property::Expr(Expression::NumLiteral(1.)));
+        assert_eq!(
+            command("astro A { radius: 1 }"),
+            Ok((
+                "",
+                Command::Com(vec![
+                    Arg::Str("astro"),
+                    Arg::Str("A"),
+                    Arg::Block(vec![radius.clone()])
+                ])
+            ))
+        );
+        assert_eq!(
+            block("{ /* c */ radius: 1 }"),
+            Ok((
+                "",
+                Arg::Block(vec![
+                    Command::TrailingComment("/* c */"),
+                    radius.clone()
+                ])
+            ))
+        );
+        assert_eq!(
+            block("{\n    /* c */ radius: 1\n}"),
+            Ok(("", Arg::Block(vec![Command::Comment("/* c */"), radius])))
+        );
+    }
+
+    #[test]
+    fn test_multiline() {
+        assert_eq!(
+            block(
+                "{
+            p
+}"
+            ),
+            Ok(("", Arg::Block(vec![Command::Com(vec![Arg::Str("p")])])))
+        );
+    }
+
+    #[test]
+    fn test_command() {
+        assert_eq!(
+            command(
+                r#"a  {
+            p
+        }"#
+            ),
+            Ok((
+                "",
+                Command::Com(vec![
+                    Arg::Str("a"),
+                    Arg::Block(vec![Command::Com(vec![Arg::Str("p")])])
+                ])
+            ))
+        );
+    }
+
+    #[test]
+    fn test_include() {
+        assert_eq!(
+            command("include \"jovian_moons.txt\"\n"),
+            Ok(("\n", Command::Include("jovian_moons.txt")))
+        );
+        assert_eq!(
+            command("import \"a.txt\" # comment"),
+            Ok((" # comment", Command::Include("a.txt")))
+        );
+        // Without a string it is an ordinary command.
+        assert_eq!(
+            command("include moons"),
+            Ok((
+                "",
+                Command::Com(vec![Arg::Str("include"), Arg::Str("moons")])
+            ))
+        );
+    }
+
+    #[test]
+    fn test_base() {
+        assert_eq!(
+            command("astro Jupiter : GasGiant {\n  radius: 1\n}"),
+            Ok((
+                "",
+                Command::Com(vec![
+                    Arg::Str("astro"),
+                    Arg::Str("Jupiter"),
+                    Arg::Base("GasGiant"),
+                    Arg::Block(vec![Command::Prop(
+                        "radius",
+                        Property::Expr(Expression::NumLiteral(1.))
+                    )])
+                ])
+            ))
+        );
+        assert_eq!(
+            command("template Moon:Rock {}"),
+            Ok((
+                "",
+                Command::Com(vec![
+                    Arg::Str("template"),
+                    Arg::Str("Moon"),
+                    Arg::Base("Rock"),
+                    Arg::Block(vec![])
+                ])
+            ))
+        );
+        // Only `astro` and `template` names can be followed by a template.
+        assert!(command("orbit_period: w d").is_err());
+        assert!(command("ring Inner : Rock {}").is_err());
+    }
+
+    #[test]
+    fn test_for() {
+        let source = "for i in 0..N {\n  astro \"Rock${i}\" {\n  }\n}";
+        assert_eq!(
+            command(source),
+            Ok((
+                "",
+                Command::For(
+                    "i",
+                    Expression::NumLiteral(0.),
+                    Expression::Ident("N"),
+                    vec![Command::Com(vec![
+                        Arg::Str("astro"),
+                        Arg::Quoted("Rock${i}"),
+                        Arg::Block(vec![])
+                    ])]
+                )
+            ))
+        );
+        assert_eq!(
+            command("for k in 1 + 1 .. 2 * n {}"),
+            Ok((
+                "",
+                Command::For(
+                    "k",
+                    Expression::Add(
+                        Box::new(Expression::NumLiteral(1.)),
+                        Box::new(Expression::NumLiteral(1.))
+                    ),
+                    Expression::Mul(
+                        Box::new(Expression::NumLiteral(2.)),
+                        Box::new(Expression::Ident("n"))
+                    ),
+                    vec![]
+                )
+            ))
+        );
+        assert_eq!(number("1.5.."), Ok(("..", Expression::NumLiteral(1.5))));
+    }
+
+    #[test]
+    fn test_interpolation() {
+        assert_eq!(
+            eval_value("\"Rock${n}\""),
+            Ok(Value::Str("Rock3".to_owned()))
+        );
+        assert_eq!(
+            eval_value("\"${n * 2}-${HIGH_RES}\""),
+            Ok(Value::Str("6-true".to_owned()))
+        );
+        assert_eq!(
+            eval_value("\"a${1 +}\""),
+            Err(EvalError::InvalidInterpolation("1 +"))
+        );
+        assert_eq!(eval_value("\"a${m}\""), Err(EvalError::UnknownName("m")));
+        assert_eq!(
+            eval_value(r#""\"${n}\" \${n} \\ \q $""#),
+            Ok(Value::Str(r#""3" ${n} \ \q $"#.to_owned()))
+        );
+        assert_eq!(
+            command(r#"astro "Say \"hi\"" {}"#),
+            Ok((
+                "",
+                Command::Com(vec![
+                    Arg::Str("astro"),
+                    Arg::Quoted(r#"Say \"hi\""#),
+                    Arg::Block(vec![])
+                ])
+            ))
+        );
+    }
+
+    #[test]
+    fn test_prop() {
+        assert_eq!(
+            command("hello: world"),
+            Ok((
+                "",
+                Command::Prop(
+                    "hello",
+                    Property::Expr(Expression::Ident("world"))
+                )
+            ))
+        );
+    }
+
+    #[test]
+    fn test_string_prop() {
+        assert_eq!(
+            command(r#"hello: "world""#),
+            Ok((
+                "",
+                Command::Prop("hello", Property::Str("world".to_owned()))
+            ))
+        );
+    }
 
     #[test]
     fn test_command_prop() {
@@ -356,6 +1852,233 @@ astro Moon {
         );
     }
 
+    #[test]
+    fn test_command_prop_comments() {
+        assert_eq!(
+            commands(
+                r#"
+# The Moon
+astro Moon { // Earth's satellite
+    /* radius: 0.3
+       is too big */
+    radius: 0.5 # in Earth radii
+    // texture: "moon.png"
+}"#
+            ),
+            Ok((
+                "",
+                vec![
+                    Command::Comment("# The Moon"),
+                    Command::Com(vec![
+                        Arg::Str("astro"),
+                        Arg::Str("Moon"),
+                        Arg::Block(vec![
+                            Command::TrailingComment("// Earth's satellite"),
+                            Command::Comment(
+                                "/* radius: 0.3\n       is too big */"
+                            ),
+                            Command::Prop(
+                                "radius",
+                                Property::Expr(Expression::NumLiteral(0.5))
+                            ),
+                            Command::TrailingComment("# in Earth radii"),
+                            Command::Comment("// texture: \"moon.png\""),
+                        ])
+                    ])
+                ]
+            ))
+        );
+    }
+
+    #[test]
+    fn test_expr_comments() {
+        assert_eq!(
+            command("a = 2 * /* AU */ b # trailing"),
+            Ok((
+                " # trailing",
+                Command::Def(
+                    "a",
+                    Expression::Mul(
+                        Box::new(Expression::NumLiteral(2.)),
+                        Box::new(Expression::Ident("b"))
+                    )
+                )
+            ))
+        );
+        assert_eq!(
+            command("a = ( // first\n  1 /* second */ + pow(2, /* exp */ 3))"),
+            Ok((
+                "",
+                Command::Def(
+                    "a",
+                    Expression::Add(
+                        Box::new(Expression::NumLiteral(1.)),
+                        Box::new(Expression::FnInvoke(
+                            "pow",
+                            vec![
+                                Expression::NumLiteral(2.),
+                                Expression::NumLiteral(3.)
+                            ]
+                        ))
+                    )
+                )
+            ))
+        );
+        // Division is not mistaken for a comment.
+        assert_eq!(
+            command("a = b / c"),
+            Ok((
+                "",
+                Command::Def(
+                    "a",
+                    Expression::Div(
+                        Box::new(Expression::Ident("b")),
+                        Box::new(Expression::Ident("c"))
+                    )
+                )
+            ))
+        );
+    }
+
+    #[test]
+    fn test_commented_out_body() {
+        let (commands, errors) = parse_errors(
+            "/*\nastro Sun {\n  radius: 1\n}\n*/\n# astro Moon {\nAU = 1 // }\n",
+        );
+        assert_eq!(errors, Vec::<&str>::new());
+        assert_eq!(
+            commands,
+            vec![
+                Command::Comment("/*\nastro Sun {\n  radius: 1\n}\n*/"),
+                Command::Comment("# astro Moon {"),
+                Command::Def("AU", Expression::NumLiteral(1.)),
+                Command::TrailingComment("// }"),
+            ]
+        );
+    }
+
+    fn eval_value(s: &str) -> Result<Value, EvalError<'_>> {
+        let (rest, ex) = expr(s).unwrap();
+        assert_eq!(rest, "");
+        let scope = Scope {
+            vars: HashMap::from([
+                ("HIGH_RES".to_owned(), Value::Bool(true)),
+                ("n".to_owned(), Value::Num(3.)),
+            ]),
+            ..Scope::default()
+        };
+        eval(&ex, &Env::new(&[scope]))
+    }
+
+    fn eval_str(s: &str) -> f64 {
+        eval_value(s).unwrap().as_num().unwrap()
+    }
+
+    #[test]
+    fn test_unary() {
+        assert_eq!(
+            expr("-a"),
+            Ok(("", Expression::Neg(Box::new(Expression::Ident("a")))))
+        );
+        assert_eq!(eval_str("-2"), -2.);
+        assert_eq!(eval_str("+2"), 2.);
+        assert_eq!(eval_str("--2"), 2.);
+        assert_eq!(eval_str("3 - -2"), 5.);
+        assert_eq!(eval_str("3 * -(1 + 1)"), -6.);
+    }
+
+    #[test]
+    fn test_power() {
+        assert_eq!(eval_str("2^10"), 1024.);
+        assert_eq!(eval_str("2 ** 10"), 1024.);
+        assert_eq!(eval_str("2^3^2"), 512.);
+        assert_eq!(eval_str("-2^2"), -4.);
+        assert_eq!(eval_str("2^-1"), 0.5);
+        assert_eq!(eval_str("3 * 10^2"), 300.);
+        assert_eq!(eval_str("2 * 3 ** 2"), 18.);
+    }
+
+    #[test]
+    fn test_mod() {
+        assert_eq!(eval_str("7 % 4"), 3.);
+        assert_eq!(eval_str("1 + 7 % 4 * 2"), 7.);
+        assert_eq!(eval_str("-7 % 4"), -3.);
+    }
+
+    #[test]
+    fn test_comparison() {
+        use Value::Bool;
+        assert_eq!(eval_value("1 < 2"), Ok(Bool(true)));
+        assert_eq!(eval_value("2 <= 2"), Ok(Bool(true)));
+        assert_eq!(eval_value("1 + 1 > 2"), Ok(Bool(false)));
+        assert_eq!(eval_value("n >= 3"), Ok(Bool(true)));
+        assert_eq!(eval_value("n == 3"), Ok(Bool(true)));
+        assert_eq!(eval_value("n != 3"), Ok(Bool(false)));
+        assert_eq!(eval_value(r#""a" == "a""#), Ok(Bool(true)));
+        assert_eq!(
+            eval_value(r#"n == "3""#),
+            Err(EvalError::TypeMismatch {
+                expected: "number",
+                found: "string",
+                at: Some("3")
+            })
+        );
+        // Comparisons do not chain.
+        assert_eq!(expr("1 < 2 < 3").map(|(rest, _)| rest), Ok(" < 3"));
+    }
+
+    #[test]
+    fn test_logical() {
+        use Value::Bool;
+        assert_eq!(eval_value("true && !false"), Ok(Bool(true)));
+        assert_eq!(eval_value("false || n < 2"), Ok(Bool(false)));
+        assert_eq!(eval_value("true || false && false"), Ok(Bool(true)));
+        assert_eq!(eval_value("!(n > 1) || HIGH_RES"), Ok(Bool(true)));
+        // The right hand side is not evaluated if the result is known.
+        assert_eq!(eval_value("false && unknown"), Ok(Bool(false)));
+        assert_eq!(
+            eval_value("!n"),
+            Err(EvalError::TypeMismatch {
+                expected: "boolean",
+                found: "number",
+                at: Some("n")
+            })
+        );
+    }
+
+    #[test]
+    fn test_conditional() {
+        assert_eq!(
+            eval_value(r#"HIGH_RES ? "earth_8k.jpg" : "earth_2k.jpg""#),
+            Ok(Value::Str("earth_8k.jpg".to_owned()))
+        );
+        assert_eq!(eval_str("n > 5 ? 1 : n > 2 ? 2 : 3"), 2.);
+        assert_eq!(eval_str("if(n < 5, n * 2, 0)"), 6.);
+        assert_eq!(eval_str("1 + (false ? 1 : 2)"), 3.);
+        assert_eq!(
+            eval_value("if(true, 1)"),
+            Err(EvalError::WrongArity {
+                name: "if",
+                expected: Arity::exact(3),
+                found: 2
+            })
+        );
+        assert_eq!(
+            command(r#"texture: HIGH_RES ? "a.jpg" : "b.jpg""#),
+            Ok((
+                "",
+                Command::Prop(
+                    "texture",
+                    Property::Expr(Expression::Cond(
+                        Box::new(Expression::Ident("HIGH_RES")),
+                        Box::new(Expression::StrLiteral("a.jpg")),
+                        Box::new(Expression::StrLiteral("b.jpg"))
+                    ))
+                )
+            ))
+        );
+    }
+
     #[test]
     fn test_def() {
         assert_eq!(
@@ -363,4 +2086,481 @@ astro Moon {
             Ok(("", Command::Def("a", Expression::NumLiteral(123.))))
         );
     }
+
+    #[test]
+    fn test_let_const() {
+        assert_eq!(
+            command("let x = 1"),
+            Ok(("", Command::Let("x", Expression::NumLiteral(1.))))
+        );
+        assert_eq!(
+            command("const X = 1"),
+            Ok(("", Command::Const("X", Expression::NumLiteral(1.))))
+        );
+        // Only `fn` takes parameters.
+        assert!(command("let f(x) = x").is_err());
+        assert_eq!(
+            command("letter = 1"),
+            Ok(("", Command::Def("letter", Expression::NumLiteral(1.))))
+        );
+    }
+
+    #[test]
+    fn test_field() {
+        assert_eq!(
+            expr("parent.radius * 2"),
+            Ok((
+                "",
+                Expression::Mul(
+                    Box::new(Expression::Field("parent", "radius")),
+                    Box::new(Expression::NumLiteral(2.))
+                )
+            ))
+        );
+        let properties =
+            |radius| Some(HashMap::from([("radius".to_owned(), radius)]));
+        let scopes = [
+            Scope {
+                properties: properties(Value::Num(1.)),
+                ..Scope::default()
+            },
+            // A loop inside the parent's block.
+            Scope::default(),
+            Scope {
+                properties: properties(Value::Num(0.5)),
+                ..Scope::default()
+            },
+        ];
+        let env = Env::new(&scopes);
+        let eval_field = |s| eval(&expr(s).unwrap().1, &env);
+        assert_eq!(eval_field("parent.radius"), Ok(Value::Num(1.)));
+        assert_eq!(eval_field("self.radius"), Ok(Value::Num(0.5)));
+        assert_eq!(
+            eval_field("self.mass"),
+            Err(EvalError::UnsetProperty("self", "mass"))
+        );
+        assert_eq!(
+            eval_field("earth.radius"),
+            Err(EvalError::UnknownName("earth"))
+        );
+        let env = Env::new(&scopes[..1]);
+        assert_eq!(
+            eval(&expr("parent.radius").unwrap().1, &env),
+            Err(EvalError::NoBody("parent"))
+        );
+    }
+
+    #[test]
+    fn test_fn_def() {
+        let body = Expression::Mul(
+            Box::new(Expression::NumLiteral(365.25)),
+            Box::new(Expression::FnInvoke(
+                "pow",
+                vec![Expression::Ident("a"), Expression::NumLiteral(1.5)],
+            )),
+        );
+        assert_eq!(
+            command("fn period(a) = 365.25 * pow(a, 1.5)"),
+            Ok(("", Command::FnDef("period", vec!["a"], body.clone())))
+        );
+        assert_eq!(
+            command("period( a ) = 365.25 * pow(a, 1.5)"),
+            Ok(("", Command::FnDef("period", vec!["a"], body)))
+        );
+        assert_eq!(
+            command("fn zero() = 0"),
+            Ok((
+                "",
+                Command::FnDef("zero", vec![], Expression::NumLiteral(0.))
+            ))
+        );
+    }
+
+    fn eval_with_functions<'src>(
+        defs: &[&'src str],
+        s: &'src str,
+    ) -> Result<Value, EvalError<'src>> {
+        let mut scope = Scope::default();
+        for def in defs {
+            let Ok((_, Command::FnDef(name, params, body))) = command(def)
+            else {
+                panic!("not a function definition: {def}");
+            };
+            scope
+                .functions
+                .insert(name.to_owned(), Function { params, body });
+        }
+        scope.vars.insert("a".to_owned(), Value::Num(100.));
+        let (_, ex) = expr(s).unwrap();
+        eval(&ex, &Env::new(&[scope]))
+    }
+
+    #[test]
+    fn test_fn_call() {
+        let defs = [
+            "fn sq(x) = x * x",
+            "fn hyp(x, y) = sqrt(sq(x) + sq(y))",
+            "fn fact(n) = n <= 1 ? 1 : n * fact(n - 1)",
+            "fn get_a() = a",
+            "fn outer(a) = get_a()",
+            "fn forever(n) = forever(n + 1)",
+        ];
+        assert_eq!(eval_with_functions(&defs, "hyp(3, 4)"), Ok(Value::Num(5.)));
+        assert_eq!(eval_with_functions(&defs, "fact(5)"), Ok(Value::Num(120.)));
+        // Arguments shadow variables only inside the function's own body.
+        assert_eq!(
+            eval_with_functions(&defs, "outer(1)"),
+            Ok(Value::Num(100.))
+        );
+        assert_eq!(
+            eval_with_functions(&defs, "sq(1, 2)"),
+            Err(EvalError::WrongArity {
+                name: "sq",
+                expected: Arity::exact(1),
+                found: 2
+            })
+        );
+        assert_eq!(
+            eval_with_functions(&defs, "forever(0)"),
+            Err(EvalError::RecursionLimit("forever"))
+        );
+        assert_eq!(
+            eval_with_functions(&defs, "cube(2)"),
+            Err(EvalError::UnknownFunction("cube"))
+        );
+    }
+
+    #[test]
+    fn test_eval_errors() {
+        let scopes = [Scope::default()];
+        let vars = &Env::new(&scopes);
+        let (_, ex) = expr("AU * 2").unwrap();
+        assert_eq!(eval(&ex, vars), Err(EvalError::UnknownName("AU")));
+        let (_, ex) = expr("sqr(2)").unwrap();
+        assert_eq!(eval(&ex, vars), Err(EvalError::UnknownFunction("sqr")));
+        let (_, ex) = expr("atan2(1)").unwrap();
+        assert_eq!(
+            eval(&ex, vars),
+            Err(EvalError::WrongArity {
+                name: "atan2",
+                expected: Arity::exact(2),
+                found: 1
+            })
+        );
+    }
+
+    #[test]
+    fn test_native_functions() {
+        assert_eq!(eval_value("log(exp(2))"), Ok(Value::Num(2.)));
+        assert_eq!(eval_value("max(1, 3, 2)"), Ok(Value::Num(3.)));
+        assert_eq!(
+            eval_value("min(2 AU, 1 AU)"),
+            Ok(Value::Quantity(1., Dim::LENGTH))
+        );
+        assert_eq!(eval_value("clamp(-2, 0, 1)"), Ok(Value::Num(0.)));
+        assert_eq!(
+            eval_value("hypot(3 AU, 4 AU)").unwrap().as_dim(Dim::LENGTH),
+            Ok(5.)
+        );
+        let (_, ex) = expr("max()").unwrap();
+        assert_eq!(
+            eval(&ex, &Env::new(&[])),
+            Err(EvalError::WrongArity {
+                name: "max",
+                expected: Arity::at_least(1),
+                found: 0
+            })
+        );
+        assert_eq!(
+            eval_value("clamp(1, 2)").unwrap_err().to_string(),
+            "function `clamp` takes 3 arguments but 2 were supplied"
+        );
+        assert_eq!(
+            eval_value("log(1, 2, 3)").unwrap_err().to_string(),
+            "function `log` takes 1 or 2 arguments but 3 were supplied"
+        );
+
+        let mut functions = FunctionRegistry::with_builtins();
+        functions.register("double", Arity::exact(1), |args| {
+            Ok(Value::Num(args[0].as_num()? * 2.))
+        });
+        let (_, ex) = expr("double(sqrt(4))").unwrap();
+        let env = Env::with_functions(&[], &functions);
+        assert_eq!(eval(&ex, &env), Ok(Value::Num(4.)));
+    }
+
+    #[test]
+    fn test_units() {
+        assert_eq!(
+            expr("6371 km"),
+            Ok(("", Expression::Quantity(6371., "km")))
+        );
+        assert_eq!(expr("24.6h"), Ok(("", Expression::Quantity(24.6, "h"))));
+        // Only known units are taken as a suffix.
+        assert_eq!(expr("2 x"), Ok((" x", Expression::NumLiteral(2.))));
+        let value = eval_value("1.523 AU").unwrap();
+        assert_eq!(value, Value::Quantity(1.523, Dim::LENGTH));
+        assert_eq!(value.as_dim(Dim::LENGTH), Ok(1.523));
+        assert_eq!(eval_value("36 h"), Ok(Value::Quantity(1.5, Dim::TIME)));
+        assert_eq!(eval_value("2 yr / 1 d"), Ok(Value::Num(730.5)));
+        assert_eq!(
+            eval_value("1 AU + 1 AU"),
+            Ok(Value::Quantity(2., Dim::LENGTH))
+        );
+        assert_eq!(
+            eval_value("(2 AU)^2"),
+            Ok(Value::Quantity(4., Dim::LENGTH.checked_powi(2).unwrap()))
+        );
+        assert!((eval_str("sin(30 deg)") - 0.5).abs() < 1e-12);
+        assert_eq!(eval_value("1 d < 2 d"), Ok(Value::Bool(true)));
+        // Plain numbers are taken to be in internal units.
+        assert_eq!(Value::Num(3.).as_dim(Dim::TIME), Ok(3.));
+    }
+
+    #[test]
+    fn test_unit_errors() {
+        let mismatch = |expected, found, at| {
+            Err(EvalError::DimensionMismatch {
+                expected,
+                found,
+                at: Some(at),
+            })
+        };
+        assert_eq!(
+            eval_value("3 days").unwrap().as_dim(Dim::LENGTH),
+            Err(EvalError::DimensionMismatch {
+                expected: Dim::LENGTH,
+                found: Dim::TIME,
+                at: None,
+            })
+        );
+        assert_eq!(
+            eval_value("1 AU + 2 d"),
+            mismatch(Dim::LENGTH, Dim::TIME, "d")
+        );
+        assert_eq!(
+            eval_value("1 AU > 2"),
+            mismatch(Dim::LENGTH, Dim::NONE, "AU")
+        );
+        assert_eq!(
+            eval_value("sqrt(1 km)"),
+            mismatch(Dim::NONE, Dim::LENGTH, "km")
+        );
+        assert_eq!(
+            eval_value("(1 AU)^1.5"),
+            Err(EvalError::FractionalPower {
+                base: Dim::LENGTH,
+                at: Some("AU"),
+            })
+        );
+        assert_eq!(
+            eval_value("sqrt(1 km)").unwrap_err().to_string(),
+            "expected a number, found a length"
+        );
+        assert_eq!(
+            eval_value("(1 AU)^2 + 1 d").unwrap_err().to_string(),
+            "expected an area, found a time"
+        );
+        assert_eq!(
+            eval_value("(2 AU)^9 / (1 AU)^8"),
+            Ok(Value::Quantity(512., Dim::LENGTH))
+        );
+        // Exponents of dimensions are small integers.
+        let overflow = "the dimension of the result is too large";
+        for source in [
+            "((1 AU ^ 8) ^ 8) ^ 8 / 1 AU",
+            "(1 AU ^ 8) ^ 8 * (1 AU ^ 8) ^ 8",
+            "1 / (1 s ^ 8) ^ 8 / (1 s ^ 8) ^ 8 / 1 s",
+            "(1 AU)^128",
+            "(1 d)^1e10",
+        ] {
+            let error = eval_value(source).unwrap_err().to_string();
+            assert_eq!(error, overflow, "{source}");
+        }
+    }
+
+    fn parse_errors(source: &str) -> (Vec<Command<'_>>, Vec<&str>) {
+        let mut diagnostics = Diagnostics::new("test.txt", source);
+        let commands = parse_scene(source, &mut diagnostics);
+        let errors = diagnostics
+            .items()
+            .iter()
+            .map(|item| {
+                let span = item.span.unwrap();
+                &source[span.start..span.end]
+            })
+            .collect();
+        (commands, errors)
+    }
+
+    #[test]
+    fn test_colors() {
+        assert_eq!(expr("#ff8800"), Ok(("", Expression::Color("#ff8800"))));
+        assert_eq!(
+            eval_value("#f80"),
+            Ok(Value::Color(Color::rgba(255, 136, 0, 255)))
+        );
+        assert_eq!(
+            eval_value("rgb(255, 136, 0) == #ff8800"),
+            Ok(Value::Bool(true))
+        );
+        // A `#` followed by anything else than a color starts a comment.
+        assert_eq!(
+            command("c = 1 + #adds two\n 2"),
+            Ok((
+                "",
+                Command::Def(
+                    "c",
+                    Expression::Add(
+                        Box::new(Expression::NumLiteral(1.)),
+                        Box::new(Expression::NumLiteral(2.))
+                    )
+                )
+            ))
+        );
+        assert_eq!(
+            command("orbit_color: #00ff00 # green"),
+            Ok((
+                " # green",
+                Command::Prop(
+                    "orbit_color",
+                    Property::Expr(Expression::Color("#00ff00"))
+                )
+            ))
+        );
+        // On a line of its own, a color-like comment is still a comment.
+        assert_eq!(command("#fade"), Ok(("", Command::Comment("#fade"))));
+    }
+
+    #[test]
+    fn test_vectors_and_lists() {
+        assert_eq!(
+            eval_value("vec3(1, 2, 3) * 2 - vec3(0, 0, 1)"),
+            Ok(Value::Vec3([2., 4., 5.], Dim::NONE))
+        );
+        assert_eq!(
+            eval_value("-vec3(1 AU, 0 AU, 2 km) / 1 d"),
+            Ok(Value::Vec3(
+                [-1., -0., -2e3 / 149_597_870_700.],
+                Dim::LENGTH.checked_div(Dim::TIME).unwrap()
+            ))
+        );
+        assert_eq!(
+            eval_value("vec3(1, 0, 0) + 1"),
+            Err(EvalError::TypeMismatch {
+                expected: "vector",
+                found: "number",
+                at: None
+            })
+        );
+        assert_eq!(
+            eval_value("vec3(1 AU, 0, 0)"),
+            Err(EvalError::DimensionMismatch {
+                expected: Dim::LENGTH,
+                found: Dim::NONE,
+                at: Some("vec3")
+            })
+        );
+        assert_eq!(
+            expr("[1, #fff]"),
+            Ok((
+                "",
+                Expression::List(vec![
+                    Expression::NumLiteral(1.),
+                    Expression::Color("#fff")
+                ])
+            ))
+        );
+        assert_eq!(
+            eval_value("[n, n > 2, \"a\", []]"),
+            Ok(Value::List(vec![
+                Value::Num(3.),
+                Value::Bool(true),
+                Value::Str("a".to_owned()),
+                Value::List(vec![])
+            ]))
+        );
+        assert_eq!(
+            eval_value("\"${[vec3(1, 2, 3), #ff880080]}\""),
+            Ok(Value::Str("[vec3(1, 2, 3), #ff880080]".to_owned()))
+        );
+    }
+
+    #[test]
+    fn test_recover_top_level() {
+        let source = "a = 1\n$b = 2\nc = 3\nfn f(x) = x *\n";
+        let (commands, errors) = parse_errors(source);
+        assert_eq!(
+            commands,
+            vec![
+                Command::Def("a", Expression::NumLiteral(1.)),
+                Command::Error("$b = 2"),
+                Command::Def("c", Expression::NumLiteral(3.)),
+                Command::Error("fn f(x) = x *"),
+            ]
+        );
+        assert_eq!(errors, vec!["$b = 2", "fn f(x) = x *"]);
+    }
+
+    #[test]
+    fn test_recover_in_block() {
+        let source = r#"
+astro Earth {
+    radius: )
+    texture: "earth.jpg"
+    astro Moon {
+        radius: 0.5 +
+    }
+}
+astro Mars {
+}
+"#;
+        let (commands, errors) = parse_errors(source);
+        assert_eq!(errors, vec!["radius: )", "radius: 0.5 +"]);
+        assert_eq!(
+            commands,
+            vec![
+                Command::Com(vec![
+                    Arg::Str("astro"),
+                    Arg::Str("Earth"),
+                    Arg::Block(vec![
+                        Command::Error("radius: )"),
+                        Command::Prop(
+                            "texture",
+                            Property::Str("earth.jpg".to_owned())
+                        ),
+                        Command::Com(vec![
+                            Arg::Str("astro"),
+                            Arg::Str("Moon"),
+                            Arg::Block(vec![Command::Error("radius: 0.5 +")])
+                        ])
+                    ])
+                ]),
+                Command::Com(vec![
+                    Arg::Str("astro"),
+                    Arg::Str("Mars"),
+                    Arg::Block(vec![])
+                ]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_recover_unbalanced_braces() {
+        let (commands, errors) = parse_errors("a = 1\n}\nb = 2");
+        assert_eq!(commands.len(), 3);
+        assert_eq!(errors, vec!["}"]);
+
+        let (commands, errors) = parse_errors("astro Sun {\n  radius: 1\n");
+        assert_eq!(commands, vec![Command::Error("astro Sun {\n  radius: 1")]);
+        assert_eq!(errors, vec!["astro Sun {\n  radius: 1"]);
+    }
+
+    #[test]
+    fn test_sample_scene() {
+        let (commands, errors) =
+            parse_errors(include_str!("../assets/sol.txt"));
+        assert_eq!(errors, Vec::<&str>::new());
+        assert_eq!(commands.len(), 10);
+    }
 }
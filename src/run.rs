@@ -2,14 +2,20 @@ use three_d::*;
 
 use crate::{
     astro_body::{
-        apply_transform, load_astro_bodies, scan_textures, uv_sphere,
-        AstroBody, BodyContext,
+        advance, apply_transform, load_astro_bodies, uv_sphere, AstroBody,
+        BodyContext, Frame,
     },
+    functions::FunctionRegistry,
     orbit_control_ex::OrbitControlEx,
-    parser::Command,
+    scene::Scene,
 };
 
-pub async fn run<'src>(commands: Vec<Command<'src>>) {
+/// Opens a window showing `scene`, whose expressions can call the
+/// functions in `functions`, as when it was loaded.
+pub async fn run(
+    scene: Scene,
+    functions: &FunctionRegistry,
+) -> Result<(), String> {
     let window = Window::new(WindowSettings {
         title: "Rusty-space".to_string(),
         min_size: (512, 512),
@@ -37,9 +43,7 @@ pub async fn run<'src>(commands: Vec<Command<'src>>) {
         .build();
 
     let mut textures = vec!["hipparcossq.jpg".to_owned()];
-    for command in &commands {
-        scan_textures(command, &mut textures);
-    }
+    textures.extend(scene.textures());
 
     for texture in &mut textures {
         *texture = format!("assets/{}", texture);
@@ -52,11 +56,11 @@ pub async fn run<'src>(commands: Vec<Command<'src>>) {
         &context, &top_tex, &top_tex, &top_tex, &top_tex, &top_tex, &top_tex,
     );
 
-    let light = AmbientLight::new(&context, 0.1, Color::WHITE);
+    let light = AmbientLight::new(&context, 0.1, Srgba::WHITE);
     let point = PointLight::new(
         &context,
         10.,
-        Color::WHITE,
+        Srgba::WHITE,
         &Vec3::zero(),
         Attenuation {
             constant: 0.,
@@ -66,8 +70,13 @@ pub async fn run<'src>(commands: Vec<Command<'src>>) {
     );
 
     let mesh = uv_sphere(32);
-    let mut body_context = BodyContext::new(&context, &mut loaded, &mesh);
-    let mut bodies = load_astro_bodies(&commands, &mut body_context);
+    let mut body_context = BodyContext {
+        context: &context,
+        loaded: &mut loaded,
+        mesh: &mesh,
+    };
+    let mut bodies =
+        load_astro_bodies(&scene.bodies, &mut body_context, functions)?;
 
     // main loop
     window.render_loop(move |mut frame_input| {
@@ -80,12 +89,12 @@ pub async fn run<'src>(commands: Vec<Command<'src>>) {
         camera.set_viewport(viewport);
         control.handle_events(&mut camera, &mut frame_input.events);
 
+        // One second is one day.
+        let t = frame_input.accumulated_time * 1e-3;
+        let dt = frame_input.elapsed_time * 1e-3;
         for body in &mut bodies {
-            apply_transform(
-                body,
-                &Matrix4::identity(),
-                frame_input.accumulated_time * 1e-3,
-            );
+            advance(body, t, dt);
+            apply_transform(body, &Frame::scene());
         }
 
         fn get_render_models<'a, 'b>(
@@ -98,8 +107,11 @@ pub async fn run<'src>(commands: Vec<Command<'src>>) {
             if let Some(ref cylinder) = body.orbit_model {
                 models.push(cylinder as &dyn three_d::Object);
             }
+            for band in &body.ring_models {
+                models.push(band as &dyn three_d::Object);
+            }
             for body in body.children.iter() {
-                models.extend(get_render_models(&body));
+                models.extend(get_render_models(body));
             }
             models
         }
@@ -112,9 +124,10 @@ pub async fn run<'src>(commands: Vec<Command<'src>>) {
         frame_input
             .screen()
             .clear(ClearState::default())
-            .render(&camera, &[&skybox], &[])
+            .render(&camera, [&skybox], &[])
             .render(&camera, &render_models[..], &[&light, &point]);
 
         FrameOutput::default()
     });
+    Ok(())
 }
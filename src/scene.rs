@@ -0,0 +1,1654 @@
+use std::collections::{BTreeMap, HashMap};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    color::Color,
+    compile::{compile, resolve},
+    diagnostics::Diagnostics,
+    format::format_expression,
+    functions::FunctionRegistry,
+    kepler::orbit_period,
+    parser::{
+        eval, expr, interpolate, Arg, Command, Env, EvalError, Expression,
+        Function, Property, Scope, Value,
+    },
+    schema::{
+        in_range, internal_unit, lookup, PropType, PropertyError,
+        PropertySchema, BODY_PROPERTIES,
+    },
+    units::{Dim, GRAVITATIONAL_CONSTANT},
+};
+
+/// The simulation time in days, which the expressions of properties that
+/// change over time depend on.
+pub(crate) const TIME: &str = "t";
+
+/// A scene with every expression evaluated, in internal units, except for
+/// the properties that change over time. Unlike the parsed commands it
+/// does not borrow the source, so it can be kept around, compared and sent
+/// to other threads.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Scene {
+    pub(crate) bodies: Vec<BodySpec>,
+}
+
+/// The properties of a body in a [`Scene`], described by
+/// [`BODY_PROPERTIES`]. Those missing from a JSON, TOML or RON scene have
+/// the same defaults as in an `astro` block.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct BodySpec {
+    pub name: String,
+    /// Texture file name, relative to the `assets` directory.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub texture: Option<String>,
+    pub radius: f64,
+    /// In solar masses.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mass: Option<f64>,
+    /// In AU³/d².
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gm: Option<f64>,
+    pub semimajor_axis: f64,
+    pub eccentricity: f64,
+    /// Angles of the orientation and phase of the orbit, in radians.
+    pub inclination: f64,
+    pub longitude_of_ascending_node: f64,
+    pub argument_of_periapsis: f64,
+    pub mean_anomaly_at_epoch: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub orbit_period: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rotation_period: Option<f64>,
+    pub star: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub orbit_color: Option<Color>,
+    /// A direction, not necessarily of length 1.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub spin_axis: Option<[f64; 3]>,
+    /// Angles of the axis, in radians.
+    pub obliquity: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub spin_axis_right_ascension: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub spin_axis_declination: Option<f64>,
+    pub prime_meridian: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub precession_period: Option<f64>,
+    pub tilt_children: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reference_plane: Option<ReferencePlane>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub laplace_radius: Option<f64>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub ring_colors: Vec<Color>,
+    /// The expressions of [`TIME`] of the numeric properties that change
+    /// over time, in the scene language. The fields of these properties
+    /// hold their values at `t = 0`.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub animated: BTreeMap<String, String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub children: Vec<BodySpec>,
+}
+
+impl Default for BodySpec {
+    /// Returns a body with the defaults of [`BODY_PROPERTIES`].
+    fn default() -> Self {
+        let mut spec = Self {
+            name: String::new(),
+            texture: None,
+            radius: 0.,
+            mass: None,
+            gm: None,
+            semimajor_axis: 0.,
+            eccentricity: 0.,
+            inclination: 0.,
+            longitude_of_ascending_node: 0.,
+            argument_of_periapsis: 0.,
+            mean_anomaly_at_epoch: 0.,
+            orbit_period: None,
+            rotation_period: None,
+            star: false,
+            orbit_color: None,
+            spin_axis: None,
+            obliquity: 0.,
+            spin_axis_right_ascension: None,
+            spin_axis_declination: None,
+            prime_meridian: 0.,
+            precession_period: None,
+            tilt_children: false,
+            reference_plane: None,
+            laplace_radius: None,
+            ring_colors: vec![],
+            animated: BTreeMap::new(),
+            children: vec![],
+        };
+        for prop in BODY_PROPERTIES {
+            if let Some(default) = prop.default {
+                spec.set(prop.name, default.into());
+            }
+        }
+        spec
+    }
+}
+
+impl BodySpec {
+    /// Sets a property of [`BODY_PROPERTIES`] to a value of its type, with
+    /// numbers in internal units.
+    fn set(&mut self, name: &str, value: Value) {
+        match (name, value) {
+            ("radius", Value::Num(n)) => self.radius = n,
+            ("mass", Value::Num(n)) => self.mass = Some(n),
+            ("gm", Value::Num(n)) => self.gm = Some(n),
+            ("semimajor_axis", Value::Num(n)) => self.semimajor_axis = n,
+            ("eccentricity", Value::Num(n)) => self.eccentricity = n,
+            ("inclination", Value::Num(n)) => self.inclination = n,
+            ("longitude_of_ascending_node", Value::Num(n)) => {
+                self.longitude_of_ascending_node = n
+            }
+            ("argument_of_periapsis", Value::Num(n)) => {
+                self.argument_of_periapsis = n
+            }
+            ("mean_anomaly_at_epoch", Value::Num(n)) => {
+                self.mean_anomaly_at_epoch = n
+            }
+            ("orbit_period", Value::Num(n)) => self.orbit_period = Some(n),
+            ("rotation_period", Value::Num(n)) => {
+                self.rotation_period = Some(n)
+            }
+            ("texture", Value::Str(s)) => self.texture = Some(s),
+            ("star", Value::Bool(b)) => self.star = b,
+            ("orbit_color", Value::Color(c)) => self.orbit_color = Some(c),
+            ("spin_axis", Value::Vec3(v, _)) => self.spin_axis = Some(v),
+            ("obliquity", Value::Num(n)) => self.obliquity = n,
+            ("spin_axis_right_ascension", Value::Num(n)) => {
+                self.spin_axis_right_ascension = Some(n)
+            }
+            ("spin_axis_declination", Value::Num(n)) => {
+                self.spin_axis_declination = Some(n)
+            }
+            ("prime_meridian", Value::Num(n)) => self.prime_meridian = n,
+            ("precession_period", Value::Num(n)) => {
+                self.precession_period = Some(n)
+            }
+            ("tilt_children", Value::Bool(b)) => self.tilt_children = b,
+            ("reference_plane", Value::Str(s)) => {
+                self.reference_plane = Some(
+                    ReferencePlane::from_name(&s)
+                        .unwrap_or_else(|| unreachable!("`{s}` as a plane")),
+                )
+            }
+            ("laplace_radius", Value::Num(n)) => self.laplace_radius = Some(n),
+            ("ring_colors", Value::List(items)) => {
+                self.ring_colors = items
+                    .into_iter()
+                    .map(|item| match item {
+                        Value::Color(c) => c,
+                        item => unreachable!("{} in `ring_colors`", item),
+                    })
+                    .collect()
+            }
+            (name, value) => unreachable!(
+                "no property `{name}` taking a {}",
+                value.type_name()
+            ),
+        }
+    }
+
+    /// Returns a property of [`BODY_PROPERTIES`], if it is set.
+    fn get(&self, name: &str) -> Option<Value> {
+        match name {
+            "radius" => Some(Value::Num(self.radius)),
+            "mass" => self.mass.map(Value::Num),
+            "gm" => self.gm.map(Value::Num),
+            "semimajor_axis" => Some(Value::Num(self.semimajor_axis)),
+            "eccentricity" => Some(Value::Num(self.eccentricity)),
+            "inclination" => Some(Value::Num(self.inclination)),
+            "longitude_of_ascending_node" => {
+                Some(Value::Num(self.longitude_of_ascending_node))
+            }
+            "argument_of_periapsis" => {
+                Some(Value::Num(self.argument_of_periapsis))
+            }
+            "mean_anomaly_at_epoch" => {
+                Some(Value::Num(self.mean_anomaly_at_epoch))
+            }
+            "orbit_period" => self.orbit_period.map(Value::Num),
+            "rotation_period" => self.rotation_period.map(Value::Num),
+            "texture" => self.texture.clone().map(Value::Str),
+            "star" => Some(Value::Bool(self.star)),
+            "orbit_color" => self.orbit_color.map(Value::Color),
+            "spin_axis" => self.spin_axis.map(|v| Value::Vec3(v, Dim::NONE)),
+            "obliquity" => Some(Value::Num(self.obliquity)),
+            "spin_axis_right_ascension" => {
+                self.spin_axis_right_ascension.map(Value::Num)
+            }
+            "spin_axis_declination" => {
+                self.spin_axis_declination.map(Value::Num)
+            }
+            "prime_meridian" => Some(Value::Num(self.prime_meridian)),
+            "precession_period" => self.precession_period.map(Value::Num),
+            "tilt_children" => Some(Value::Bool(self.tilt_children)),
+            "reference_plane" => self
+                .reference_plane
+                .map(|plane| Value::Str(plane.name().to_owned())),
+            "laplace_radius" => self.laplace_radius.map(Value::Num),
+            "ring_colors" => (!self.ring_colors.is_empty()).then(|| {
+                Value::List(
+                    self.ring_colors
+                        .iter()
+                        .copied()
+                        .map(Value::Color)
+                        .collect(),
+                )
+            }),
+            _ => unreachable!("no property `{name}`"),
+        }
+    }
+
+    /// Returns the gravitational parameter from `gm` or else `mass`, in
+    /// AU³/d².
+    pub fn gravitational_parameter(&self) -> Option<f64> {
+        self.gm
+            .or(self.mass.map(|mass| GRAVITATIONAL_CONSTANT * mass))
+    }
+
+    /// Returns the period of the orbit by Kepler's third law, around bodies
+    /// of gravitational parameter `central`, if they and the body have
+    /// masses enough to tell.
+    fn kepler_period(&self, central: Option<f64>) -> Option<f64> {
+        let gm = central? + self.gravitational_parameter().unwrap_or(0.);
+        (self.semimajor_axis > 0.)
+            .then(|| orbit_period(gm, self.semimajor_axis, self.eccentricity))
+    }
+
+    /// Compiles the properties that change over time, whose expressions
+    /// can call the functions in `functions`, and checks that they can be
+    /// evaluated at `t = 0`.
+    pub fn animations(
+        &self,
+        functions: &FunctionRegistry,
+    ) -> Result<Vec<Animation>, String> {
+        self.animated
+            .iter()
+            .map(|(name, source)| {
+                let Some((schema, false)) = lookup(BODY_PROPERTIES, name)
+                else {
+                    return Err(format!("`{name}` cannot change over time"));
+                };
+                let animation = Animation::new(schema, source, functions)?;
+                animation.eval(0.).map_err(|e| format!("at t = 0: {e}"))?;
+                Ok(animation)
+            })
+            .collect()
+    }
+}
+
+/// The plane the orbit of a body is measured from, with the parent at its
+/// center.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum ReferencePlane {
+    /// The reference plane of the scene.
+    Ecliptic,
+    ParentEquator,
+    /// The plane the orbit precesses about, between the equator and the
+    /// orbit of the parent.
+    Laplace,
+}
+
+impl ReferencePlane {
+    const ALL: [Self; 3] = [Self::Ecliptic, Self::ParentEquator, Self::Laplace];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Ecliptic => "ecliptic",
+            Self::ParentEquator => "parent_equator",
+            Self::Laplace => "laplace",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|plane| plane.name() == name)
+    }
+}
+
+/// A numeric property that changes over time. It owns its expression and
+/// the functions it can call, so it can outlive the scene, and compiles the
+/// expression again whenever it is evaluated.
+pub(crate) struct Animation {
+    pub schema: &'static PropertySchema,
+    source: String,
+    functions: FunctionRegistry,
+    /// Whether an error evaluating it has been reported, so that it is
+    /// reported only once.
+    pub failed: bool,
+}
+
+impl Animation {
+    /// Checks that the expression of [`TIME`] of a property can be parsed,
+    /// and keeps it with a copy of `functions`.
+    fn new(
+        schema: &'static PropertySchema,
+        source: &str,
+        functions: &FunctionRegistry,
+    ) -> Result<Self, String> {
+        if !matches!(schema.ty, PropType::Number(_)) {
+            return Err(format!("`{}` cannot change over time", schema.name));
+        }
+        let animation = Self {
+            schema,
+            source: source.to_owned(),
+            functions: functions.clone(),
+            failed: false,
+        };
+        animation.parse()?;
+        Ok(animation)
+    }
+
+    fn parse(&self) -> Result<Expression<'_>, String> {
+        match expr(&self.source) {
+            Ok((rest, ex)) if rest.trim().is_empty() => Ok(ex),
+            _ => Err(format!("invalid expression `{}`", self.source)),
+        }
+    }
+
+    /// Returns the value of the property at time `t` in days, in internal
+    /// units.
+    pub fn eval(&self, t: f64) -> Result<f64, String> {
+        let PropType::Number(dim) = self.schema.ty else {
+            unreachable!("`{}` is not a number", self.schema.name);
+        };
+        let ex = self.parse()?;
+        let env = Env::with_functions(&[], &self.functions);
+        let value = compile(&ex, &env, &[TIME])
+            .run(&[Value::Quantity(t, Dim::TIME)])
+            .and_then(|value| value.as_dim(dim))
+            .map_err(|e| e.to_string())?;
+        if !in_range(value, &self.schema.range) {
+            let error = PropertyError::OutOfRange {
+                name: self.schema.name,
+                value,
+                range: self.schema.range,
+                unit: unit_of(self.schema),
+            };
+            return Err(error.to_string());
+        }
+        Ok(value)
+    }
+}
+
+impl Scene {
+    /// Returns the textures used by any body, without duplicates.
+    pub(crate) fn textures(&self) -> Vec<String> {
+        fn collect(bodies: &[BodySpec], textures: &mut Vec<String>) {
+            for body in bodies {
+                if let Some(texture) = &body.texture {
+                    if !textures.contains(texture) {
+                        textures.push(texture.clone());
+                    }
+                }
+                collect(&body.children, textures);
+            }
+        }
+        let mut textures = vec![];
+        collect(&self.bodies, &mut textures);
+        textures
+    }
+
+    /// Sets the periods of the orbits that are unset by Kepler's third law,
+    /// for scenes that were not loaded from the scene language.
+    pub(crate) fn derive_periods(&mut self) {
+        fn derive(bodies: &mut [BodySpec], central: Option<f64>) {
+            for body in bodies {
+                if body.orbit_period.is_none() {
+                    body.orbit_period = body.kepler_period(central);
+                }
+                let gm = body.gravitational_parameter();
+                derive(&mut body.children, gm);
+            }
+        }
+        let central = central_gm(&self.bodies);
+        derive(&mut self.bodies, central);
+    }
+
+    /// Checks every body against [`BODY_PROPERTIES`], for scenes that were
+    /// not loaded from the scene language.
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        fn check(bodies: &[BodySpec]) -> Result<(), String> {
+            for body in bodies {
+                for prop in BODY_PROPERTIES {
+                    let error = match body.get(prop.name) {
+                        None if prop.required => PropertyError::Missing {
+                            body: &body.name,
+                            name: prop.name,
+                        },
+                        Some(Value::Num(value))
+                            if !in_range(value, &prop.range) =>
+                        {
+                            PropertyError::OutOfRange {
+                                name: prop.name,
+                                value,
+                                range: prop.range,
+                                unit: unit_of(prop),
+                            }
+                        }
+                        _ => continue,
+                    };
+                    return Err(format!("body `{}`: {error}", body.name));
+                }
+                body.animations(FunctionRegistry::builtins())
+                    .map_err(|e| format!("body `{}`: {e}", body.name))?;
+                check(&body.children)?;
+            }
+            Ok(())
+        }
+        check(&self.bodies)
+    }
+}
+
+/// Returns the gravitational parameter of the bodies at the center of the
+/// scene, which the other bodies at the top level orbit.
+fn central_gm(bodies: &[BodySpec]) -> Option<f64> {
+    bodies
+        .iter()
+        .filter(|body| body.semimajor_axis == 0.)
+        .filter_map(BodySpec::gravitational_parameter)
+        .reduce(|a, b| a + b)
+}
+
+/// How far, relative to the period that follows from the masses, a given
+/// `orbit_period` can be before it is reported.
+const PERIOD_TOLERANCE: f64 = 0.01;
+
+fn unit_of(prop: &PropertySchema) -> Option<&'static str> {
+    match prop.ty {
+        PropType::Number(dim) => internal_unit(dim),
+        _ => None,
+    }
+}
+
+/// The parts of `keyword Name { ... }` or `keyword Name : Base { ... }`. The
+/// name may also be a quoted string with `${...}` interpolations.
+pub(crate) type BodyCommand<'a, 'src> =
+    (&'src str, Option<&'src str>, &'a [Command<'src>]);
+
+fn parse_body_command<'a, 'src>(
+    command: &'a Command<'src>,
+    keyword: &str,
+) -> Option<BodyCommand<'a, 'src>> {
+    let Command::Com(v) = command else {
+        return None;
+    };
+    let [Arg::Str(k), Arg::Str(name) | Arg::Quoted(name), rest @ ..] = &v[..]
+    else {
+        return None;
+    };
+    if *k != keyword {
+        return None;
+    }
+    match rest {
+        [Arg::Block(block)] => Some((name, None, block)),
+        [Arg::Base(base), Arg::Block(block)] => Some((name, Some(base), block)),
+        _ => None,
+    }
+}
+
+pub(crate) fn parse_astro_command<'a, 'src>(
+    command: &'a Command<'src>,
+) -> Option<BodyCommand<'a, 'src>> {
+    parse_body_command(command, "astro")
+}
+
+pub(crate) fn parse_template_command<'a, 'src>(
+    command: &'a Command<'src>,
+) -> Option<BodyCommand<'a, 'src>> {
+    parse_body_command(command, "template")
+}
+
+/// Evaluates the commands of a scene file into a [`Scene`].
+pub(crate) struct SceneBuilder<'a, 'src> {
+    pub diagnostics: &'a mut Diagnostics<'src>,
+    /// The blocks being loaded, innermost last. The first one holds the
+    /// top-level definitions.
+    scopes: Vec<Scope<'src>>,
+    /// The commands of each template, including the ones it inherits.
+    templates: HashMap<String, Vec<Command<'src>>>,
+    /// The properties `astro` blocks can set.
+    schema: &'static [PropertySchema],
+    /// The functions implemented in Rust, which expressions can call.
+    functions: &'a FunctionRegistry,
+    /// Every property set so far, in order.
+    pub evaluated: Vec<Evaluated<'src>>,
+    /// Where each body loaded in the current block set `orbit_period`, in
+    /// order, until the periods are checked at the end of the block.
+    period_spans: Vec<Option<&'src str>>,
+}
+
+/// A property as it was set on a body, for the language server to show.
+/// There is no language server on the web.
+#[derive(Debug, Clone)]
+#[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+pub(crate) struct Evaluated<'src> {
+    /// The name as written, which locates the property in the source.
+    pub name: &'src str,
+    pub body: String,
+    pub schema: &'static PropertySchema,
+    /// The value with its dimension, at `t = 0` if it changes over time.
+    pub value: Value,
+    pub animated: bool,
+}
+
+impl<'a, 'src> SceneBuilder<'a, 'src> {
+    pub fn new(
+        diagnostics: &'a mut Diagnostics<'src>,
+        functions: &'a FunctionRegistry,
+    ) -> Self {
+        Self {
+            diagnostics,
+            scopes: vec![Scope::default()],
+            templates: HashMap::new(),
+            schema: BODY_PROPERTIES,
+            functions,
+            evaluated: vec![],
+            period_spans: vec![],
+        }
+    }
+
+    fn env(&self) -> Env<'_, 'src> {
+        Env::with_functions(&self.scopes, self.functions)
+    }
+
+    /// Starts a block, which is the block of a body if `properties` is set.
+    fn push_scope(&mut self, properties: Option<HashMap<String, Value>>) {
+        self.scopes.push(Scope {
+            properties,
+            ..Scope::default()
+        });
+    }
+
+    fn pop_scope(&mut self) {
+        self.scopes.pop();
+    }
+
+    fn scope(&mut self) -> &mut Scope<'src> {
+        self.scopes.last_mut().unwrap()
+    }
+
+    /// Records a property of the body being loaded, for `self.name`.
+    fn set_property(&mut self, name: &str, value: Value) {
+        if let Some(properties) = &mut self.scope().properties {
+            properties.insert(name.to_owned(), value);
+        }
+    }
+
+    /// Reports an evaluation error as a diagnostic, at the location of the
+    /// error if it has one or at `at` otherwise.
+    fn report<T>(
+        &mut self,
+        at: &'src str,
+        result: Result<T, EvalError<'src>>,
+    ) -> Option<T> {
+        match result {
+            Ok(value) => Some(value),
+            Err(e) => {
+                self.diagnostics
+                    .error(e.span().unwrap_or(at), e.to_string());
+                None
+            }
+        }
+    }
+
+    fn value(&mut self, at: &'src str, prop: &Property<'src>) -> Option<Value> {
+        match prop {
+            Property::Str(s) => Some(Value::Str(s.clone())),
+            Property::Expr(ex) => {
+                let value = eval(ex, &self.env());
+                self.report(at, value)
+            }
+        }
+    }
+
+    /// Evaluates the `${...}` interpolations in a name. On error the name is
+    /// used as written.
+    fn interpolate(&mut self, name: &'src str) -> String {
+        let result = interpolate(name, &self.env());
+        self.report(name, result).unwrap_or_else(|| name.to_owned())
+    }
+
+    /// Evaluates a numeric property, converting it to internal units and
+    /// checking that it has dimension `dim`.
+    fn num(
+        &mut self,
+        at: &'src str,
+        prop: &Property<'src>,
+        dim: Dim,
+    ) -> Option<f64> {
+        let value = self.value(at, prop)?;
+        let result = value.as_dim(dim).map_err(|e| match prop {
+            Property::Expr(ex) => e.at(ex.span()),
+            Property::Str(_) => e,
+        });
+        self.report(at, result)
+    }
+
+    /// Derives the unset periods of the orbits of `bodies`, the bodies of
+    /// a block whose periods are in `period_spans` from `first`, around
+    /// bodies of gravitational parameter `central`. Warns about the periods
+    /// that were set but disagree with Kepler's third law.
+    fn derive_periods(
+        &mut self,
+        bodies: &mut [BodySpec],
+        first: usize,
+        central: Option<f64>,
+    ) {
+        let spans = self.period_spans.split_off(first);
+        debug_assert_eq!(spans.len(), bodies.len());
+        for (body, span) in bodies.iter_mut().zip(spans) {
+            let Some(derived) = body.kepler_period(central) else {
+                continue;
+            };
+            let (Some(period), Some(span)) = (body.orbit_period, span) else {
+                body.orbit_period = Some(derived);
+                continue;
+            };
+            let error = (period - derived).abs() / derived;
+            // A period that changes over time cannot always agree.
+            if PERIOD_TOLERANCE < error
+                && !body.animated.contains_key("orbit_period")
+            {
+                // Four significant digits.
+                let decimals = (3. - derived.log10().floor()).max(0.) as usize;
+                self.diagnostics.warning(
+                    span,
+                    format!(
+                        "`orbit_period` is {period} d, but Kepler's third \
+                         law gives {derived:.decimals$} d for the masses of \
+                         `{}` and its parent",
+                        body.name
+                    ),
+                );
+            }
+        }
+    }
+
+    fn property_error(&mut self, error: PropertyError<'src>) {
+        self.diagnostics
+            .push(error.level(), error.span(), error.to_string());
+    }
+
+    /// Evaluates property `name` of the body being loaded and sets it on
+    /// `spec`, checking it against the schema. Returns the name the schema
+    /// gives the property, if it was set.
+    fn property(
+        &mut self,
+        spec: &mut BodySpec,
+        name: &'src str,
+        prop: &Property<'src>,
+    ) -> Option<&'static str> {
+        let Some((schema, deprecated)) = lookup(self.schema, name) else {
+            self.property_error(PropertyError::unknown(self.schema, name));
+            return None;
+        };
+        if deprecated {
+            self.property_error(PropertyError::Deprecated {
+                alias: name,
+                name: schema.name,
+            });
+        }
+        // A choice can be written as a bare word rather than a variable.
+        let word;
+        let prop = match (schema.ty, prop) {
+            (
+                PropType::Choice(choices),
+                Property::Expr(Expression::Ident(ident)),
+            ) if choices.contains(ident) => {
+                word = Property::Str(ident.to_string());
+                &word
+            }
+            _ => prop,
+        };
+        if let Property::Expr(ex) = prop {
+            let resolved = resolve(ex, &self.env(), &[TIME]);
+            if let Some(resolved) = self.report(name, resolved)? {
+                return self.animate(spec, name, schema, &resolved);
+            }
+        }
+        // The property may have changed over time in a template.
+        spec.animated.remove(schema.name);
+        // The value as `self.name` sees it, with its dimension.
+        let value = match schema.ty {
+            PropType::Number(dim) => {
+                let value = self.num(name, prop, dim)?;
+                if !in_range(value, &schema.range) {
+                    self.property_error(PropertyError::OutOfRange {
+                        name,
+                        value,
+                        range: schema.range,
+                        unit: unit_of(schema),
+                    });
+                    return None;
+                }
+                spec.set(schema.name, Value::Num(value));
+                Value::quantity(value, dim)
+            }
+            PropType::Bool => {
+                // Numbers are accepted for compatibility with scenes written
+                // before booleans existed.
+                let value = match self.value(name, prop)? {
+                    Value::Num(value) => value != 0.,
+                    value => self.report(name, value.as_bool())?,
+                };
+                spec.set(schema.name, Value::Bool(value));
+                Value::Bool(value)
+            }
+            PropType::Str => {
+                let value = self.value(name, prop)?;
+                let value = self.report(name, value.into_string())?;
+                spec.set(schema.name, Value::Str(value.clone()));
+                Value::Str(value)
+            }
+            PropType::Choice(choices) => {
+                let value = self.value(name, prop)?;
+                let value = self.report(name, value.into_string())?;
+                if !choices.contains(&value.as_str()) {
+                    self.property_error(PropertyError::NotAChoice {
+                        name,
+                        value,
+                        choices,
+                    });
+                    return None;
+                }
+                spec.set(schema.name, Value::Str(value.clone()));
+                Value::Str(value)
+            }
+            PropType::Vec3 | PropType::Color | PropType::List(_) => {
+                let value = self.value(name, prop)?;
+                let result = schema.ty.check(value).map_err(|e| match prop {
+                    Property::Expr(ex) => e.at(ex.span()),
+                    Property::Str(_) => e,
+                });
+                let value = self.report(name, result)?;
+                spec.set(schema.name, value.clone());
+                value
+            }
+        };
+        self.evaluated.push(Evaluated {
+            name,
+            body: spec.name.clone(),
+            schema,
+            value: value.clone(),
+            animated: false,
+        });
+        self.set_property(schema.name, value);
+        Some(schema.name)
+    }
+
+    /// Sets a property whose expression depends on [`TIME`] to its value
+    /// at `t = 0`, and keeps the expression to evaluate at every frame.
+    fn animate(
+        &mut self,
+        spec: &mut BodySpec,
+        name: &'src str,
+        schema: &'static PropertySchema,
+        resolved: &Expression<'src>,
+    ) -> Option<&'static str> {
+        let source = format_expression(resolved);
+        let value = Animation::new(schema, &source, self.functions)
+            .and_then(|animation| animation.eval(0.));
+        let value = match value {
+            Ok(value) => value,
+            Err(e) => {
+                self.diagnostics.error(name, e);
+                return None;
+            }
+        };
+        let PropType::Number(dim) = schema.ty else {
+            unreachable!("`{}` is not a number", schema.name);
+        };
+        spec.set(schema.name, Value::Num(value));
+        spec.animated.insert(schema.name.to_owned(), source);
+        let value = Value::quantity(value, dim);
+        self.evaluated.push(Evaluated {
+            name,
+            body: spec.name.clone(),
+            schema,
+            value: value.clone(),
+            animated: true,
+        });
+        self.set_property(schema.name, value);
+        Some(schema.name)
+    }
+
+    /// Reports an error if `name` is [`TIME`], which variables cannot be
+    /// called.
+    fn reserved(&mut self, name: &'src str) -> bool {
+        if name != TIME {
+            return false;
+        }
+        self.diagnostics.error(
+            name,
+            format!("`{TIME}` is the simulation time and cannot be defined"),
+        );
+        true
+    }
+
+    /// Handles `name = expr`, which assigns to the innermost variable called
+    /// `name`, or defines one in the current block if there is none.
+    fn assign(&mut self, name: &'src str, expr: &Expression<'src>) {
+        if self.reserved(name) {
+            return;
+        }
+        let value = eval(expr, &self.env());
+        let Some(value) = self.report(name, value) else {
+            return;
+        };
+        let scope = match self
+            .scopes
+            .iter_mut()
+            .rev()
+            .find(|scope| scope.vars.contains_key(name))
+        {
+            Some(scope) if scope.constants.contains(name) => {
+                self.diagnostics
+                    .error(name, format!("cannot assign to constant `{name}`"));
+                return;
+            }
+            Some(scope) => scope,
+            None => self.scope(),
+        };
+        scope.vars.insert(name.to_owned(), value);
+    }
+
+    /// Handles `let name = expr` and `const name = expr`, which define a
+    /// variable in the current block.
+    fn declare(
+        &mut self,
+        name: &'src str,
+        expr: &Expression<'src>,
+        constant: bool,
+    ) {
+        if self.reserved(name) {
+            return;
+        }
+        let value = eval(expr, &self.env());
+        let Some(value) = self.report(name, value) else {
+            return;
+        };
+        let (scope, outer) = self.scopes.split_last_mut().unwrap();
+        if scope.constants.contains(name) {
+            self.diagnostics
+                .error(name, format!("cannot redefine constant `{name}`"));
+            return;
+        } else if scope.vars.contains_key(name) {
+            self.diagnostics.warning(
+                name,
+                format!("`{name}` is already defined in this block"),
+            );
+        } else if outer.iter().any(|scope| scope.vars.contains_key(name)) {
+            self.diagnostics.warning(
+                name,
+                format!("`{name}` shadows a variable of an enclosing block"),
+            );
+        }
+        if constant {
+            scope.constants.insert(name.to_owned());
+        }
+        scope.vars.insert(name.to_owned(), value);
+    }
+
+    fn define_fn(
+        &mut self,
+        name: &'src str,
+        params: &[&'src str],
+        body: &Expression<'src>,
+    ) {
+        if name == "if" || self.functions.contains(name) {
+            self.diagnostics.error(
+                name,
+                format!("cannot redefine built-in function `{name}`"),
+            );
+            return;
+        }
+        for (i, param) in params.iter().enumerate() {
+            if params[..i].contains(param) {
+                self.diagnostics.error(
+                    param,
+                    format!("parameter `{param}` is declared more than once"),
+                );
+                return;
+            }
+        }
+        self.scope().functions.insert(
+            name.to_owned(),
+            Function {
+                params: params.to_vec(),
+                body: body.clone(),
+            },
+        );
+    }
+
+    /// Returns the commands of template `name`, followed by `block`, so that
+    /// the properties in `block` override the template's.
+    fn inherit(
+        &mut self,
+        name: &'src str,
+        block: &[Command<'src>],
+    ) -> Vec<Command<'src>> {
+        let mut commands = if let Some(template) = self.templates.get(name) {
+            template.clone()
+        } else {
+            self.diagnostics
+                .error(name, format!("unknown template `{name}`"));
+            vec![]
+        };
+        commands.extend_from_slice(block);
+        commands
+    }
+
+    fn define_template(
+        &mut self,
+        name: &'src str,
+        base: Option<&'src str>,
+        block: &[Command<'src>],
+    ) {
+        let commands = match base {
+            Some(base) => self.inherit(base, block),
+            None => block.to_vec(),
+        };
+        self.templates.insert(name.to_owned(), commands);
+    }
+
+    /// Handles the commands that define names, which may appear both at the
+    /// top level and inside `astro` blocks.
+    fn define_command(&mut self, command: &Command<'src>) {
+        match command {
+            Command::Def(name, expr) => self.assign(name, expr),
+            Command::Let(name, expr) => self.declare(name, expr, false),
+            Command::Const(name, expr) => self.declare(name, expr, true),
+            Command::FnDef(name, params, body) => {
+                self.define_fn(name, params, body)
+            }
+            Command::Com(args) => {
+                if let Some((name, base, block)) =
+                    parse_template_command(command)
+                {
+                    self.define_template(name, base, block);
+                } else if parse_astro_command(command).is_none() {
+                    self.unknown_command(args);
+                }
+            }
+            // Properties of bodies are loaded with their block.
+            Command::Prop(name, _) => self.diagnostics.error(
+                name,
+                format!(
+                    "unknown command `{name}`; properties can only be set \
+                     in `astro` and `template` blocks"
+                ),
+            ),
+            _ => (),
+        }
+    }
+
+    /// Reports a command that is neither an `astro` nor a `template` block.
+    fn unknown_command(&mut self, args: &[Arg<'src>]) {
+        let (at, message) = match args {
+            [Arg::Str(keyword @ ("astro" | "template")), ..] => (
+                *keyword,
+                format!("`{keyword}` must be followed by a name and a block"),
+            ),
+            [Arg::Str(word) | Arg::Quoted(word) | Arg::Base(word), ..] => {
+                (*word, format!("unknown command `{word}`"))
+            }
+            // A block on its own has no name to point at.
+            _ => return,
+        };
+        self.diagnostics.error(at, message);
+    }
+}
+
+/// Evaluates parsed commands into a scene, reporting problems to
+/// `diagnostics`.
+pub(crate) fn load_scene<'src>(
+    commands: &[Command<'src>],
+    diagnostics: &mut Diagnostics<'src>,
+) -> Scene {
+    load_scene_with_functions(
+        commands,
+        diagnostics,
+        FunctionRegistry::builtins(),
+    )
+}
+
+/// Like [`load_scene`], with functions an application registered in
+/// addition to, or instead of, the built-in ones.
+pub fn load_scene_with_functions<'src>(
+    commands: &[Command<'src>],
+    diagnostics: &mut Diagnostics<'src>,
+    functions: &FunctionRegistry,
+) -> Scene {
+    let mut builder = SceneBuilder::new(diagnostics, functions);
+    Scene {
+        bodies: load_root(commands, &mut builder),
+    }
+}
+
+/// Like [`load_scene`], also returning every property as it was set, for
+/// the language server.
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn load_scene_traced<'src>(
+    commands: &[Command<'src>],
+    diagnostics: &mut Diagnostics<'src>,
+) -> (Scene, Vec<Evaluated<'src>>) {
+    let mut builder =
+        SceneBuilder::new(diagnostics, FunctionRegistry::builtins());
+    let bodies = load_root(commands, &mut builder);
+    (Scene { bodies }, builder.evaluated)
+}
+
+/// Loads the bodies at the top level, which orbit the bodies at the center.
+fn load_root<'src>(
+    commands: &[Command<'src>],
+    builder: &mut SceneBuilder<'_, 'src>,
+) -> Vec<BodySpec> {
+    let mut bodies = load_bodies(commands, builder);
+    let central = central_gm(&bodies);
+    builder.derive_periods(&mut bodies, 0, central);
+    bodies
+}
+
+fn load_bodies<'src>(
+    commands: &[Command<'src>],
+    builder: &mut SceneBuilder<'_, 'src>,
+) -> Vec<BodySpec> {
+    let mut bodies = vec![];
+    for command in commands {
+        if let Command::For(var, start, end, body) = command {
+            bodies.extend(load_loop(var, start, end, body, builder));
+            continue;
+        }
+        if let Some(body) = load_body(command, builder) {
+            bodies.push(body);
+        }
+        builder.define_command(command);
+    }
+    bodies
+}
+
+/// Loops with more iterations than this are assumed to be mistakes.
+const MAX_LOOP_ITERATIONS: usize = 100_000;
+
+/// Loads the bodies in a `for` block once for each value of the loop
+/// variable.
+fn load_loop<'src>(
+    var: &'src str,
+    start: &Expression<'src>,
+    end: &Expression<'src>,
+    body: &[Command<'src>],
+    builder: &mut SceneBuilder<'_, 'src>,
+) -> Vec<BodySpec> {
+    if builder.reserved(var) {
+        return vec![];
+    }
+    let start = eval(start, &builder.env()).and_then(|v| v.as_num());
+    let end = eval(end, &builder.env()).and_then(|v| v.as_num());
+    let (Some(start), Some(end)) =
+        (builder.report(var, start), builder.report(var, end))
+    else {
+        return vec![];
+    };
+    let count = (end - start).ceil().max(0.);
+    if count > MAX_LOOP_ITERATIONS as f64 {
+        builder.diagnostics.error(
+            var,
+            format!("loop runs more than {MAX_LOOP_ITERATIONS} times"),
+        );
+        return vec![];
+    }
+    let mut bodies = vec![];
+    for i in 0..count as usize {
+        // Each iteration gets its own block, where the loop variable is a
+        // constant.
+        builder.push_scope(None);
+        let scope = builder.scope();
+        scope
+            .vars
+            .insert(var.to_owned(), Value::Num(start + i as f64));
+        scope.constants.insert(var.to_owned());
+        bodies.extend(load_bodies(body, builder));
+        builder.pop_scope();
+    }
+    bodies
+}
+
+fn load_body<'src>(
+    command: &Command<'src>,
+    builder: &mut SceneBuilder<'_, 'src>,
+) -> Option<BodySpec> {
+    let (name_span, base, block) = parse_astro_command(command)?;
+    let name = builder.interpolate(name_span);
+    let inherited;
+    let block = match base {
+        Some(base) => {
+            inherited = builder.inherit(base, block);
+            &inherited[..]
+        }
+        None => block,
+    };
+    builder.push_scope(Some(HashMap::new()));
+    let mut spec = BodySpec {
+        name,
+        ..BodySpec::default()
+    };
+    let mut set = vec![];
+    let mut period_span = None;
+    let first_child = builder.period_spans.len();
+    for com in block {
+        match com {
+            Command::Prop(prop, value) => {
+                let name = builder.property(&mut spec, prop, value);
+                if name == Some("orbit_period") {
+                    period_span = Some(*prop);
+                }
+                set.extend(name);
+            }
+            Command::Com(_) => {
+                if let Some(child) = load_body(com, builder) {
+                    spec.children.push(child);
+                }
+                builder.define_command(com);
+            }
+            Command::For(var, start, end, body) => {
+                spec.children
+                    .extend(load_loop(var, start, end, body, builder));
+            }
+            Command::Def(..)
+            | Command::Let(..)
+            | Command::Const(..)
+            | Command::FnDef(..) => {
+                builder.define_command(com);
+            }
+            // Includes have already been expanded by `Sources::parse`.
+            Command::Include(_)
+            | Command::Comment(_)
+            | Command::TrailingComment(_)
+            | Command::Error(_) => (),
+        }
+    }
+    builder.pop_scope();
+    for prop in builder.schema {
+        if prop.required && !set.contains(&prop.name) {
+            builder.property_error(PropertyError::Missing {
+                body: name_span,
+                name: prop.name,
+            });
+        }
+    }
+    // The mass may be set after the children.
+    let gm = spec.gravitational_parameter();
+    builder.derive_periods(&mut spec.children, first_child, gm);
+    builder.period_spans.push(period_span);
+    Some(spec)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{functions::Arity, parser::parse_scene};
+
+    /// Loads a scene, returning it with the messages of any diagnostics.
+    fn load(source: &str) -> (Scene, Vec<String>) {
+        let mut diagnostics = Diagnostics::new("test.txt", source);
+        let commands = parse_scene(source, &mut diagnostics);
+        let scene = load_scene(&commands, &mut diagnostics);
+        let messages = diagnostics
+            .items()
+            .iter()
+            .map(|item| item.message.clone())
+            .collect();
+        (scene, messages)
+    }
+
+    #[test]
+    fn test_load_scene() {
+        let (scene, messages) = load(
+            "astro Earth {
+                radius: 6371 km
+                orbit_period: 1 yr
+                texture: \"earth.jpg\"
+                astro Moon {
+                    semimajor_axis: 0.25
+                }
+            }",
+        );
+        assert_eq!(messages, Vec::<String>::new());
+        let earth = &scene.bodies[0];
+        assert_eq!(earth.name, "Earth");
+        assert!((earth.radius - 4.2587e-5).abs() < 1e-8);
+        assert_eq!(earth.orbit_period, Some(365.25));
+        assert_eq!(earth.children[0].semimajor_axis, 0.25);
+        assert_eq!(earth.children[0].radius, BodySpec::default().radius);
+        assert_eq!(scene.textures(), ["earth.jpg"]);
+    }
+
+    #[test]
+    fn test_dimension_error() {
+        let (scene, messages) = load("astro Rock {\n  radius: 3 days\n}");
+        assert_eq!(messages, ["expected a length, found a time"]);
+        assert_eq!(scene.bodies[0].radius, BodySpec::default().radius);
+    }
+
+    #[test]
+    fn test_unknown_command() {
+        let (scene, messages) = load(
+            "astr Sun {
+                radius: 1
+            }
+            radius: 2
+            astro Earth {
+                moon Luna {
+                }
+            }
+            template {
+            }",
+        );
+        assert_eq!(
+            messages,
+            [
+                "unknown command `astr`",
+                "unknown command `radius`; properties can only be set in \
+                 `astro` and `template` blocks",
+                "unknown command `moon`",
+                "`template` must be followed by a name and a block",
+            ]
+        );
+        assert_eq!(scene.bodies.len(), 1);
+        assert!(scene.bodies[0].children.is_empty());
+    }
+
+    #[test]
+    fn test_property_schema() {
+        let (scene, messages) = load(
+            "astro Comet {
+                raduis: 0.1
+                period: 30
+                semimajor_axis: -1
+                texture: 3
+                omega: 0.001
+                eccentricity: -0.5
+                inclination: 162.3 deg
+                radius: 1 / 0
+                prime_meridian: 0 / 0
+            }",
+        );
+        assert_eq!(
+            messages,
+            [
+                "unknown property `raduis`; did you mean `radius`?",
+                "`period` is deprecated; use `orbit_period` instead",
+                "`semimajor_axis` must be at least 0, found -1 AU",
+                "expected a string, found a number",
+                "unknown property `omega`",
+                "`eccentricity` must be at least 0, found -0.5",
+                "`radius` must be a finite number, found inf AU",
+                "`prime_meridian` must be a finite number, found NaN",
+            ]
+        );
+        let comet = &scene.bodies[0];
+        assert!((comet.inclination - 162.3f64.to_radians()).abs() < 1e-12);
+        assert_eq!(comet.orbit_period, Some(30.));
+        assert_eq!(comet.semimajor_axis, BodySpec::default().semimajor_axis);
+        assert_eq!(comet.texture, None);
+    }
+
+    #[test]
+    fn test_required_property() {
+        const SCHEMA: &[PropertySchema] = &[PropertySchema {
+            name: "mass",
+            ty: PropType::Number(Dim::NONE),
+            range: (std::ops::Bound::Unbounded, std::ops::Bound::Unbounded),
+            required: true,
+            default: None,
+            aliases: &[],
+            description: "",
+        }];
+        let source = "astro Rock {\n}\n";
+        let mut diagnostics = Diagnostics::new("test.txt", source);
+        let commands = parse_scene(source, &mut diagnostics);
+        let mut builder =
+            SceneBuilder::new(&mut diagnostics, FunctionRegistry::builtins());
+        builder.schema = SCHEMA;
+        load_bodies(&commands, &mut builder);
+        let item = &diagnostics.items()[0];
+        assert_eq!(item.message, "`Rock` is missing required property `mass`");
+        assert_eq!(item.span.map(|span| span.start), Some(6));
+    }
+
+    #[test]
+    fn test_validate() {
+        let mut scene = Scene {
+            bodies: vec![BodySpec::default()],
+        };
+        assert_eq!(scene.validate(), Ok(()));
+        scene.bodies[0].name = "Moon".to_owned();
+        scene.bodies[0].orbit_period = Some(0.);
+        assert_eq!(
+            scene.validate(),
+            Err(
+                "body `Moon`: `orbit_period` must be greater than 0, found 0 d"
+                    .to_owned()
+            )
+        );
+    }
+
+    #[test]
+    fn test_templates() {
+        let (scene, messages) = load(
+            "template GasGiant {
+                radius: 0.5
+                star: false
+                astro Ring {
+                    semimajor_axis: 0.1
+                }
+            }
+            template HotGiant : GasGiant {
+                star: true
+            }
+            astro Jupiter : HotGiant {
+                radius: 0.4
+            }
+            astro Nope : Missing {}",
+        );
+        assert_eq!(messages, ["unknown template `Missing`"]);
+        let jupiter = &scene.bodies[0];
+        assert_eq!(jupiter.radius, 0.4);
+        assert!(jupiter.star);
+        assert_eq!(jupiter.children[0].name, "Ring");
+        assert_eq!(scene.bodies[1].name, "Nope");
+    }
+
+    #[test]
+    fn test_colon_only_names_a_template_after_a_name() {
+        let (scene, messages) = load(
+            "astro Io {
+                orbit_period: w d
+            }
+            for i in 0..2 { astro \"R${i}\" { radius: i * 1 AU } }",
+        );
+        // A property whose value cannot be parsed is not a command.
+        assert_eq!(messages, ["could not parse this statement"]);
+        assert_eq!(scene.bodies[0].orbit_period, None);
+        assert_eq!(scene.bodies[2].radius, 1.);
+    }
+
+    #[test]
+    fn test_loop() {
+        let (scene, messages) = load(
+            "for i in 0..3 {
+                astro \"Rock${i}\" {
+                    semimajor_axis: 2.2 + i * 0.5
+                }
+            }",
+        );
+        assert_eq!(messages, Vec::<String>::new());
+        let names: Vec<_> = scene.bodies.iter().map(|b| &b.name).collect();
+        assert_eq!(names, ["Rock0", "Rock1", "Rock2"]);
+        assert_eq!(scene.bodies[2].semimajor_axis, 3.2);
+    }
+
+    #[test]
+    fn test_scopes() {
+        let (scene, messages) = load(
+            "const AU = 1
+            astro Earth {
+                let r = 0.05
+                radius: r
+                astro Moon {
+                    radius: parent.radius / 4
+                    semimajor_axis: self.radius * 20
+                }
+            }
+            astro Mars {
+                radius: r
+                let AU = 2
+            }
+            AU = 3",
+        );
+        assert_eq!(
+            messages,
+            [
+                "unknown name `r`",
+                "`AU` shadows a variable of an enclosing block",
+                "cannot assign to constant `AU`",
+            ]
+        );
+        let moon = &scene.bodies[0].children[0];
+        assert_eq!(moon.radius, 0.0125);
+        assert_eq!(moon.semimajor_axis, 0.25);
+    }
+
+    #[test]
+    fn test_time_dependent() {
+        let (scene, messages) = load(
+            "fn pulse(p) = sin(2 * pi * t / p)
+            let base = 0.1
+            astro Mira {
+                radius: base * (1 + 0.5 * pulse(2 d))
+                semimajor_axis: 2 AU - 0.01 AU * t / 1 d
+                astro Moon {
+                    semimajor_axis: parent.radius * 2
+                }
+            }
+            astro Rock {
+                star: t > 1 d
+                radius: -1 AU + 1 AU * t / 1 d
+                orbit_period: t
+            }
+            let t = 1",
+        );
+        assert_eq!(
+            messages,
+            [
+                "`star` cannot change over time",
+                "`radius` must be at least 0, found -1 AU",
+                "`orbit_period` must be greater than 0, found 0 d",
+                "`t` is the simulation time and cannot be defined",
+            ]
+        );
+        let mira = &scene.bodies[0];
+        // The fields hold the values at `t = 0`, which is also what
+        // `parent.radius` reads.
+        assert_eq!(mira.radius, 0.1);
+        assert_eq!(mira.children[0].semimajor_axis, 0.2);
+        let animations = mira.animations(FunctionRegistry::builtins()).unwrap();
+        let value = |name, t| {
+            let animation = animations
+                .iter()
+                .find(|animation| animation.schema.name == name)
+                .unwrap();
+            animation.eval(t).unwrap()
+        };
+        assert!((value("radius", 0.5) - 0.15).abs() < 1e-12);
+        assert!((value("semimajor_axis", 10.) - 1.9).abs() < 1e-12);
+        assert!(scene.bodies[1].animated.is_empty());
+        assert_eq!(scene.validate(), Ok(()));
+        // The animations own their expressions.
+        drop(scene);
+        assert!((value("radius", 0.5) - 0.15).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_animations_with_functions() {
+        let source = "astro Mira {
+            radius: double(t / 1 d) * 1 AU
+        }";
+        let mut functions = FunctionRegistry::with_builtins();
+        functions.register("double", Arity::exact(1), |args| {
+            Ok(Value::Num(2. * args[0].as_num()?))
+        });
+        let mut diagnostics = Diagnostics::new("test.txt", source);
+        let commands = parse_scene(source, &mut diagnostics);
+        let scene =
+            load_scene_with_functions(&commands, &mut diagnostics, &functions);
+        assert!(diagnostics.items().is_empty());
+        let mira = &scene.bodies[0];
+        let animations = mira.animations(&functions).unwrap();
+        assert_eq!(animations[0].eval(1.5), Ok(3.));
+        assert_eq!(
+            mira.animations(FunctionRegistry::builtins())
+                .err()
+                .as_deref(),
+            Some("at t = 0: unknown function `double`")
+        );
+    }
+
+    #[test]
+    fn test_value_types() {
+        let (scene, messages) = load(
+            "let dust = #c8b89a
+            astro Saturn {
+                orbit_color: #e8d8a0 # pale yellow
+                spin_axis: vec3(0.5, 1, 0) * 2
+                ring_colors: [#6b5e4a80, dust, rgba(200, 184, 154, 0.6)]
+            }
+            astro Rock {
+                orbit_color: 1
+                spin_axis: vec3(1 AU, 0 AU, 0 AU)
+                ring_colors: [dust, 2]
+            }
+            astro Comet {
+                orbit_color: t > 1 d ? #fff : #000
+            }",
+        );
+        assert_eq!(
+            messages,
+            [
+                "expected a color, found a number",
+                "expected a number, found a length",
+                "expected a color, found a number",
+                "`orbit_color` cannot change over time",
+            ]
+        );
+        let saturn = &scene.bodies[0];
+        assert_eq!(saturn.orbit_color, Some(Color::rgba(232, 216, 160, 255)));
+        assert_eq!(saturn.spin_axis, Some([1., 2., 0.]));
+        assert_eq!(
+            saturn.ring_colors,
+            [
+                Color::rgba(107, 94, 74, 128),
+                Color::rgba(200, 184, 154, 255),
+                Color::rgba(200, 184, 154, 153),
+            ]
+        );
+        let rock = &scene.bodies[1];
+        assert_eq!(rock.orbit_color, None);
+        assert_eq!(rock.spin_axis, None);
+        assert!(rock.ring_colors.is_empty());
+        assert_eq!(scene.validate(), Ok(()));
+    }
+
+    #[test]
+    fn test_choices() {
+        let (scene, messages) = load(
+            r#"const laplace = "parent_equator"
+            astro Saturn {
+                laplace_radius: 0.0146 AU
+                astro Titan {
+                    reference_plane: laplace
+                }
+                astro Rhea {
+                    reference_plane: "parent_equator"
+                }
+                astro Phoebe {
+                    reference_plane: ecliptic
+                }
+                astro Iapetus {
+                    reference_plane: equator
+                }
+                astro Hyperion {
+                    reference_plane: "Laplace"
+                }
+            }"#,
+        );
+        assert_eq!(
+            messages,
+            [
+                "unknown name `equator`",
+                "`reference_plane` must be one of `ecliptic`, \
+                    `parent_equator`, `laplace`, found `Laplace`",
+            ]
+        );
+        let saturn = &scene.bodies[0];
+        assert_eq!(saturn.laplace_radius, Some(0.0146));
+        // Bare words are choices before variables.
+        let planes: Vec<_> =
+            saturn.children.iter().map(|c| c.reference_plane).collect();
+        assert_eq!(
+            planes,
+            [
+                Some(ReferencePlane::Laplace),
+                Some(ReferencePlane::ParentEquator),
+                Some(ReferencePlane::Ecliptic),
+                None,
+                None,
+            ]
+        );
+        assert_eq!(scene.validate(), Ok(()));
+    }
+
+    #[test]
+    fn test_kepler_periods() {
+        let (scene, messages) = load(
+            "astro Sun {
+                semimajor_axis: 0
+                mass: 1 Msun
+            }
+            astro Earth {
+                semimajor_axis: 1 AU
+                astro Moon {
+                    semimajor_axis: 384400 km
+                    mass: 0.0123 Mearth
+                    orbit_period: 27.32 d
+                }
+                astro Moonlet {
+                    semimajor_axis: 384400 km
+                    orbit_period: 30 d
+                }
+                astro Probe {
+                    semimajor_axis: 7000 km
+                    eccentricity: 1.5
+                }
+                gm: 398600.4418 * (1 km)^3 / (1 s)^2
+            }
+            astro Rogue {
+                semimajor_axis: 2 AU
+            }",
+        );
+        assert_eq!(
+            messages,
+            [
+                "`orbit_period` is 30 d, but Kepler's third law gives 27.45 \
+                 d for the masses of `Moonlet` and its parent"
+            ]
+        );
+        let [sun, earth, rogue] = &scene.bodies[..] else {
+            panic!("{:?}", scene.bodies);
+        };
+        // A body at the center has no orbit, and the others orbit it.
+        assert_eq!(sun.orbit_period, None);
+        let year = earth.orbit_period.unwrap();
+        assert!((year - 365.2564).abs() < 1e-4, "{year}");
+        let period = rogue.orbit_period.unwrap();
+        assert!((period / year - 2f64.powf(1.5)).abs() < 1e-4);
+        // The mass of the parent counts although set after the children.
+        assert_eq!(earth.children[0].orbit_period, Some(27.32));
+        let probe = earth.children[2].orbit_period.unwrap();
+        let a = 7000. / (1.5 - 1.);
+        let mean_motion = (398_600.441_8_f64 / (a * a * a)).sqrt() * 86_400.;
+        assert!(
+            (probe * mean_motion / std::f64::consts::TAU - 1.).abs() < 1e-9
+        );
+    }
+}
@@ -0,0 +1,678 @@
+use std::{
+    fmt::{self, Display},
+    ops::Bound::{self, Excluded, Included, Unbounded},
+};
+
+use crate::{
+    diagnostics::Level,
+    parser::{EvalError, Value},
+    units::Dim,
+};
+
+/// The type of value a property takes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum PropType {
+    /// A number or quantity of the given dimension, stored in internal
+    /// units.
+    Number(Dim),
+    Bool,
+    Str,
+    /// A vector of plain numbers, such as a direction.
+    Vec3,
+    Color,
+    /// A list of values of the given type.
+    List(&'static PropType),
+    /// One of the given words, written bare or as a string.
+    Choice(&'static [&'static str]),
+}
+
+impl PropType {
+    /// Checks that `value` has this type, returning it with numbers in
+    /// internal units.
+    pub fn check<'src>(self, value: Value) -> Result<Value, EvalError<'src>> {
+        match self {
+            Self::Number(dim) => {
+                value.as_dim(dim).map(|value| Value::quantity(value, dim))
+            }
+            Self::Bool => value.as_bool().map(Value::Bool),
+            // Whether the string is one of the choices is a matter of its
+            // range, which the caller reports.
+            Self::Str | Self::Choice(_) => value.into_string().map(Value::Str),
+            Self::Vec3 => {
+                let v = value.as_vec3(Dim::NONE)?;
+                Ok(Value::Vec3(v, Dim::NONE))
+            }
+            Self::Color => value.as_color().map(Value::Color),
+            Self::List(item) => {
+                let items = value.into_list()?.into_iter();
+                let items = items.map(|value| item.check(value));
+                Ok(Value::List(items.collect::<Result<_, _>>()?))
+            }
+        }
+    }
+}
+
+impl Display for PropType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Number(dim) => write!(f, "{dim}"),
+            Self::Bool => write!(f, "boolean"),
+            Self::Str => write!(f, "string"),
+            Self::Vec3 => write!(f, "vector"),
+            Self::Color => write!(f, "color"),
+            Self::List(item) => write!(f, "list of {item}s"),
+            Self::Choice(_) => write!(f, "word"),
+        }
+    }
+}
+
+/// The value a property has when a block does not set it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Literal {
+    Num(f64),
+    Bool(bool),
+}
+
+impl From<Literal> for Value {
+    fn from(literal: Literal) -> Self {
+        match literal {
+            Literal::Num(n) => Self::Num(n),
+            Literal::Bool(b) => Self::Bool(b),
+        }
+    }
+}
+
+impl Display for Literal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Num(n) => write!(f, "{n}"),
+            Self::Bool(b) => write!(f, "{b}"),
+        }
+    }
+}
+
+/// Describes a property that can be set in an `astro` block.
+#[derive(Debug)]
+pub(crate) struct PropertySchema {
+    pub name: &'static str,
+    pub ty: PropType,
+    /// The values a number may take, in internal units.
+    pub range: (Bound<f64>, Bound<f64>),
+    pub required: bool,
+    /// `None` if the property is unset by default.
+    pub default: Option<Literal>,
+    /// Deprecated names, which are still accepted with a warning.
+    pub aliases: &'static [&'static str],
+    pub description: &'static str,
+}
+
+const ANY: (Bound<f64>, Bound<f64>) = (Unbounded, Unbounded);
+const NON_NEGATIVE: (Bound<f64>, Bound<f64>) = (Included(0.), Unbounded);
+const POSITIVE: (Bound<f64>, Bound<f64>) = (Excluded(0.), Unbounded);
+
+/// The properties of bodies.
+pub(crate) const BODY_PROPERTIES: &[PropertySchema] = &[
+    PropertySchema {
+        name: "radius",
+        ty: PropType::Number(Dim::LENGTH),
+        range: NON_NEGATIVE,
+        required: false,
+        default: Some(Literal::Num(0.1)),
+        aliases: &[],
+        description: "Radius of the body.",
+    },
+    PropertySchema {
+        name: "mass",
+        ty: PropType::Number(Dim::MASS),
+        range: POSITIVE,
+        required: false,
+        default: None,
+        aliases: &[],
+        description: "Mass of the body, in solar masses or with `kg`, \
+            `Mearth` or `Mjup`, from which the periods of the orbits around \
+            it follow.",
+    },
+    PropertySchema {
+        name: "gm",
+        ty: PropType::Number(Dim::GM),
+        range: POSITIVE,
+        required: false,
+        default: None,
+        aliases: &[],
+        description: "Gravitational parameter of the body, its mass times \
+            the gravitational constant, which is known more precisely than \
+            either. Overrides `mass`.",
+    },
+    PropertySchema {
+        name: "semimajor_axis",
+        ty: PropType::Number(Dim::LENGTH),
+        range: NON_NEGATIVE,
+        required: false,
+        default: Some(Literal::Num(1.)),
+        aliases: &["semi_major_axis"],
+        description: "Half the longest diameter of the orbit around the \
+            parent body, which is its radius if it is a circle. For open \
+            trajectories, with an `eccentricity` of at least 1, the \
+            distance of their closest approach instead.",
+    },
+    PropertySchema {
+        name: "eccentricity",
+        ty: PropType::Number(Dim::NONE),
+        range: NON_NEGATIVE,
+        required: false,
+        default: Some(Literal::Num(0.)),
+        aliases: &[],
+        description: "How elongated the orbit is: 0 for a circle, less \
+            than 1 for an ellipse, 1 for a parabola and more for a \
+            hyperbola.",
+    },
+    PropertySchema {
+        name: "inclination",
+        ty: PropType::Number(Dim::NONE),
+        range: ANY,
+        required: false,
+        default: Some(Literal::Num(0.)),
+        aliases: &[],
+        description: "Angle between the orbit and the reference plane, in \
+            radians or with `deg`.",
+    },
+    PropertySchema {
+        name: "longitude_of_ascending_node",
+        ty: PropType::Number(Dim::NONE),
+        range: ANY,
+        required: false,
+        default: Some(Literal::Num(0.)),
+        aliases: &[],
+        description: "Angle in the reference plane from the reference \
+            direction to where the orbit crosses the plane going north.",
+    },
+    PropertySchema {
+        name: "argument_of_periapsis",
+        ty: PropType::Number(Dim::NONE),
+        range: ANY,
+        required: false,
+        default: Some(Literal::Num(0.)),
+        aliases: &[],
+        description: "Angle along the orbit from the ascending node to the \
+            point closest to the parent body.",
+    },
+    PropertySchema {
+        name: "mean_anomaly_at_epoch",
+        ty: PropType::Number(Dim::NONE),
+        range: ANY,
+        required: false,
+        default: Some(Literal::Num(0.)),
+        aliases: &[],
+        description: "Fraction of the orbit past the periapsis at `t = 0`, \
+            as an angle.",
+    },
+    PropertySchema {
+        name: "orbit_period",
+        ty: PropType::Number(Dim::TIME),
+        range: POSITIVE,
+        required: false,
+        default: None,
+        aliases: &["period"],
+        description: "Time of one orbit. Unset, it follows from the masses \
+            of the body and its parent by Kepler's third law, or an orbit \
+            takes 2π days if the parent has no mass. For open trajectories, \
+            the time in which the mean anomaly grows by 2π.",
+    },
+    PropertySchema {
+        name: "rotation_period",
+        ty: PropType::Number(Dim::TIME),
+        range: POSITIVE,
+        required: false,
+        default: None,
+        aliases: &[],
+        description: "Time of one turn around the axis. Unset, the body \
+            does not turn.",
+    },
+    PropertySchema {
+        name: "texture",
+        ty: PropType::Str,
+        range: ANY,
+        required: false,
+        default: None,
+        aliases: &[],
+        description: "Image file in `assets` wrapped around the body.",
+    },
+    PropertySchema {
+        name: "star",
+        ty: PropType::Bool,
+        range: ANY,
+        required: false,
+        default: Some(Literal::Bool(false)),
+        aliases: &[],
+        description: "Whether the body shines rather than being lit.",
+    },
+    PropertySchema {
+        name: "orbit_color",
+        ty: PropType::Color,
+        range: ANY,
+        required: false,
+        default: None,
+        aliases: &[],
+        description: "Color of the line of the orbit. Unset, it is \
+            translucent green.",
+    },
+    PropertySchema {
+        name: "spin_axis",
+        ty: PropType::Vec3,
+        range: ANY,
+        required: false,
+        default: None,
+        aliases: &[],
+        description: "Direction of the axis the body turns around, where \
+            `vec3(0, 1, 0)` is perpendicular to the orbits. Overrides the \
+            other properties of the axis.",
+    },
+    PropertySchema {
+        name: "obliquity",
+        ty: PropType::Number(Dim::NONE),
+        range: ANY,
+        required: false,
+        default: Some(Literal::Num(0.)),
+        aliases: &[],
+        description: "Angle between the axis the body turns around and the \
+            normal of its orbit. The north pole leans towards the point of \
+            the orbit 90° past the ascending node, as the Earth's leans \
+            towards the Sun at the June solstice.",
+    },
+    PropertySchema {
+        name: "spin_axis_right_ascension",
+        ty: PropType::Number(Dim::NONE),
+        range: ANY,
+        required: false,
+        default: None,
+        aliases: &[],
+        description: "Right ascension of the north pole of the axis, with \
+            the reference plane the ecliptic of J2000. Unset, it is 0 if \
+            `spin_axis_declination` is set. Overrides `obliquity`.",
+    },
+    PropertySchema {
+        name: "spin_axis_declination",
+        ty: PropType::Number(Dim::NONE),
+        range: ANY,
+        required: false,
+        default: None,
+        aliases: &[],
+        description: "Declination of the north pole of the axis, with the \
+            reference plane the ecliptic of J2000. Unset, it is 90° if \
+            `spin_axis_right_ascension` is set. Overrides `obliquity`.",
+    },
+    PropertySchema {
+        name: "prime_meridian",
+        ty: PropType::Number(Dim::NONE),
+        range: ANY,
+        required: false,
+        default: Some(Literal::Num(0.)),
+        aliases: &[],
+        description: "Angle the body has turned around its axis at \
+            `t = 0`.",
+    },
+    PropertySchema {
+        name: "precession_period",
+        ty: PropType::Number(Dim::TIME),
+        range: POSITIVE,
+        required: false,
+        default: None,
+        aliases: &[],
+        description: "Time for the axis to turn once around the normal of \
+            the orbit, westward as the Earth's. Unset, the axis keeps its \
+            direction.",
+    },
+    PropertySchema {
+        name: "tilt_children",
+        ty: PropType::Bool,
+        range: ANY,
+        required: false,
+        default: Some(Literal::Bool(false)),
+        aliases: &[],
+        description: "Whether the children orbit with the equator as \
+            their reference plane, which tilts and precesses along with \
+            the axis, unless they set `reference_plane`.",
+    },
+    PropertySchema {
+        name: "reference_plane",
+        ty: PropType::Choice(&["ecliptic", "parent_equator", "laplace"]),
+        range: ANY,
+        required: false,
+        default: None,
+        aliases: &[],
+        description: "Plane the orbit is measured from: `ecliptic`, the \
+            reference plane of the scene; `parent_equator`, with the \
+            ascending node of the equator as the reference direction; or \
+            `laplace`, between the equator and the orbit of the parent as \
+            its `laplace_radius` sets. Unset, it is `parent_equator` if the \
+            parent sets `tilt_children`, else `ecliptic`.",
+    },
+    PropertySchema {
+        name: "laplace_radius",
+        ty: PropType::Number(Dim::LENGTH),
+        range: POSITIVE,
+        required: false,
+        default: None,
+        aliases: &[],
+        description: "Distance from the body within which the Laplace \
+            planes of orbits around it approach its equator, and beyond \
+            which its own orbit, from its flattening and the pull of its \
+            parent. Unset, the body is taken as round, and those planes are \
+            its orbit.",
+    },
+    PropertySchema {
+        name: "ring_colors",
+        ty: PropType::List(&PropType::Color),
+        range: ANY,
+        required: false,
+        default: None,
+        aliases: &[],
+        description: "Colors of the bands of a ring around the equator, \
+            from the inside out, which spans 1.2 to 2.3 times the radius.",
+    },
+];
+
+/// Finds the schema of property `name` in `schema`, and whether `name` is
+/// a deprecated alias.
+pub(crate) fn lookup<'s>(
+    schema: &'s [PropertySchema],
+    name: &str,
+) -> Option<(&'s PropertySchema, bool)> {
+    schema.iter().find_map(|prop| {
+        if prop.name == name {
+            Some((prop, false))
+        } else if prop.aliases.contains(&name) {
+            Some((prop, true))
+        } else {
+            None
+        }
+    })
+}
+
+/// Returns whether `value` is in `range`, which never holds infinities or
+/// NaN.
+pub(crate) fn in_range(value: f64, range: &(Bound<f64>, Bound<f64>)) -> bool {
+    let above = match range.0 {
+        Included(min) => min <= value,
+        Excluded(min) => min < value,
+        Unbounded => true,
+    };
+    let below = match range.1 {
+        Included(max) => value <= max,
+        Excluded(max) => value < max,
+        Unbounded => true,
+    };
+    above && below && value.is_finite()
+}
+
+/// Lists the names a string property can take, e.g. "`a`, `b`".
+fn describe_choices(choices: &[&str]) -> String {
+    let choices: Vec<_> = choices.iter().map(|c| format!("`{c}`")).collect();
+    choices.join(", ")
+}
+
+/// Describes a range, e.g. "at least 0" or "between 0 and 1".
+fn describe_range(range: &(Bound<f64>, Bound<f64>)) -> String {
+    match range {
+        (Unbounded, Unbounded) => "a finite number".to_owned(),
+        (Included(min), Unbounded) => format!("at least {min}"),
+        (Excluded(min), Unbounded) => format!("greater than {min}"),
+        (Unbounded, Included(max)) => format!("at most {max}"),
+        (Unbounded, Excluded(max)) => format!("less than {max}"),
+        (Included(min), Included(max)) => format!("between {min} and {max}"),
+        (min, max) => format!(
+            "{} and {}",
+            describe_range(&(*min, Unbounded)),
+            describe_range(&(Unbounded, *max))
+        ),
+    }
+}
+
+/// The unit numbers of dimension `dim` are stored in.
+pub(crate) fn internal_unit(dim: Dim) -> Option<&'static str> {
+    match dim {
+        Dim::LENGTH => Some("AU"),
+        Dim::TIME => Some("d"),
+        Dim::MASS => Some("Msun"),
+        Dim::GM => Some("AU³/d²"),
+        _ => None,
+    }
+}
+
+/// Returns the name in `schema` closest to a misspelled `name`, if any is
+/// close enough to be a likely typo.
+fn suggest(schema: &[PropertySchema], name: &str) -> Option<&'static str> {
+    fn distance(a: &str, b: &str) -> usize {
+        let b: Vec<char> = b.chars().collect();
+        let mut row: Vec<usize> = (0..=b.len()).collect();
+        for (i, ca) in a.chars().enumerate() {
+            let mut diagonal = row[0];
+            row[0] = i + 1;
+            for (j, cb) in b.iter().enumerate() {
+                let next = (diagonal + usize::from(ca != *cb))
+                    .min(row[j] + 1)
+                    .min(row[j + 1] + 1);
+                diagonal = row[j + 1];
+                row[j + 1] = next;
+            }
+        }
+        row[b.len()]
+    }
+    schema
+        .iter()
+        .flat_map(|prop| {
+            std::iter::once(prop.name).chain(prop.aliases.iter().copied())
+        })
+        .map(|candidate| (distance(name, candidate), candidate))
+        .filter(|(distance, _)| *distance <= name.len() / 3 + 1)
+        .min()
+        .map(|(_, candidate)| {
+            lookup(schema, candidate).map_or(candidate, |(prop, _)| prop.name)
+        })
+}
+
+/// A problem with the properties of a block.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum PropertyError<'src> {
+    Unknown {
+        name: &'src str,
+        suggestion: Option<&'static str>,
+    },
+    Deprecated {
+        alias: &'src str,
+        name: &'static str,
+    },
+    OutOfRange {
+        name: &'src str,
+        value: f64,
+        range: (Bound<f64>, Bound<f64>),
+        unit: Option<&'static str>,
+    },
+    NotAChoice {
+        name: &'src str,
+        value: String,
+        choices: &'static [&'static str],
+    },
+    Missing {
+        body: &'src str,
+        name: &'static str,
+    },
+}
+
+impl<'src> PropertyError<'src> {
+    pub fn unknown(schema: &[PropertySchema], name: &'src str) -> Self {
+        Self::Unknown {
+            name,
+            suggestion: suggest(schema, name),
+        }
+    }
+
+    /// The part of the source the problem should be reported at.
+    pub fn span(&self) -> &'src str {
+        match self {
+            Self::Unknown { name, .. }
+            | Self::Deprecated { alias: name, .. }
+            | Self::OutOfRange { name, .. }
+            | Self::NotAChoice { name, .. }
+            | Self::Missing { body: name, .. } => name,
+        }
+    }
+
+    pub fn level(&self) -> Level {
+        match self {
+            Self::Deprecated { .. } => Level::Warning,
+            _ => Level::Error,
+        }
+    }
+}
+
+impl Display for PropertyError<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unknown { name, suggestion } => {
+                write!(f, "unknown property `{name}`")?;
+                if let Some(suggestion) = suggestion {
+                    write!(f, "; did you mean `{suggestion}`?")?;
+                }
+                Ok(())
+            }
+            Self::Deprecated { alias, name } => {
+                write!(f, "`{alias}` is deprecated; use `{name}` instead")
+            }
+            Self::OutOfRange {
+                name,
+                value,
+                range,
+                unit,
+            } => {
+                let expected = if value.is_finite() {
+                    describe_range(range)
+                } else {
+                    describe_range(&(Unbounded, Unbounded))
+                };
+                write!(f, "`{name}` must be {expected}, found {value}")?;
+                if let Some(unit) = unit {
+                    write!(f, " {unit}")?;
+                }
+                Ok(())
+            }
+            Self::NotAChoice {
+                name,
+                value,
+                choices,
+            } => write!(
+                f,
+                "`{name}` must be one of {}, found `{value}`",
+                describe_choices(choices)
+            ),
+            Self::Missing { body, name } => {
+                write!(f, "`{body}` is missing required property `{name}`")
+            }
+        }
+    }
+}
+
+/// Returns a Markdown table describing every property in `schema`.
+pub(crate) fn reference(schema: &[PropertySchema]) -> String {
+    let mut out = "| Property | Type | Unit | Values | Default | Description \
+        |\n|---|---|---|---|---|---|\n"
+        .to_owned();
+    for prop in schema {
+        let unit = match prop.ty {
+            PropType::Number(dim) => internal_unit(dim).unwrap_or(""),
+            _ => "",
+        };
+        let values = match prop.ty {
+            PropType::Number(_) => describe_range(&prop.range),
+            PropType::Bool => "`true`, `false`".to_owned(),
+            PropType::Str => "any string".to_owned(),
+            PropType::Vec3 => "`vec3(x, y, z)`".to_owned(),
+            PropType::Color => "`#rrggbb`, `rgb(r, g, b)`".to_owned(),
+            PropType::List(_) => "`[a, b, ...]`".to_owned(),
+            PropType::Choice(choices) => describe_choices(choices),
+        };
+        let default = match (prop.required, prop.default) {
+            (true, _) => "required".to_owned(),
+            (false, Some(default)) => format!("`{default}`"),
+            (false, None) => "unset".to_owned(),
+        };
+        let mut description = prop.description.to_owned();
+        if !prop.aliases.is_empty() {
+            let aliases: Vec<_> = prop
+                .aliases
+                .iter()
+                .map(|alias| format!("`{alias}`"))
+                .collect();
+            description +=
+                &format!(" Deprecated names: {}.", aliases.join(", "));
+        }
+        out += &format!(
+            "| `{}` | {} | {unit} | {values} | {default} | {description} |\n",
+            prop.name, prop.ty
+        );
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_lookup() {
+        let (prop, deprecated) = lookup(BODY_PROPERTIES, "period").unwrap();
+        assert_eq!(prop.name, "orbit_period");
+        assert!(deprecated);
+        assert!(!lookup(BODY_PROPERTIES, "radius").unwrap().1);
+        assert!(lookup(BODY_PROPERTIES, "omega").is_none());
+    }
+
+    #[test]
+    fn test_range() {
+        assert!(in_range(0., &NON_NEGATIVE));
+        assert!(!in_range(0., &POSITIVE));
+        assert!(!in_range(f64::NAN, &ANY));
+        assert!(!in_range(f64::INFINITY, &ANY));
+        assert!(!in_range(f64::INFINITY, &NON_NEGATIVE));
+        assert!(in_range(1., &(Included(1.), Excluded(2.))));
+        assert!(!in_range(2., &(Included(1.), Excluded(2.))));
+    }
+
+    #[test]
+    fn test_messages() {
+        assert_eq!(
+            PropertyError::unknown(BODY_PROPERTIES, "raduis").to_string(),
+            "unknown property `raduis`; did you mean `radius`?"
+        );
+        assert_eq!(
+            PropertyError::unknown(BODY_PROPERTIES, "semimajor_axes")
+                .to_string(),
+            "unknown property `semimajor_axes`; did you mean \
+            `semimajor_axis`?"
+        );
+        assert_eq!(
+            PropertyError::unknown(BODY_PROPERTIES, "omega").to_string(),
+            "unknown property `omega`"
+        );
+        assert_eq!(
+            PropertyError::OutOfRange {
+                name: "orbit_period",
+                value: -1.,
+                range: POSITIVE,
+                unit: Some("d"),
+            }
+            .to_string(),
+            "`orbit_period` must be greater than 0, found -1 d"
+        );
+    }
+
+    #[test]
+    fn test_reference_is_documented() {
+        let readme = include_str!("../README.md");
+        assert!(
+            readme.contains(&reference(BODY_PROPERTIES)),
+            "the property table in README.md is out of date; update it \
+            with the output of `cargo run -- properties`"
+        );
+    }
+}
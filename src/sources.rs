@@ -0,0 +1,339 @@
+use std::collections::HashMap;
+
+use crate::{
+    diagnostics::Diagnostics,
+    parser::{commands, parse_scene, Arg, Command},
+};
+
+/// A scene file and all the files it includes, read before anything is
+/// parsed so that the parsed commands can borrow from every file at once.
+pub(crate) struct Sources {
+    /// Paths and contents, starting with the root file.
+    files: Vec<(String, String)>,
+    /// Files that could not be read, with the reason.
+    unreadable: HashMap<String, String>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) async fn read_file(path: &str) -> Result<String, String> {
+    std::fs::read_to_string(path).map_err(|e| e.to_string())
+}
+
+#[cfg(target_arch = "wasm32")]
+pub(crate) async fn read_file(path: &str) -> Result<String, String> {
+    let mut assets = three_d_asset::io::load_async(&[path])
+        .await
+        .map_err(|e| e.to_string())?;
+    let bytes = assets.remove(path).map_err(|e| e.to_string())?;
+    String::from_utf8(bytes).map_err(|e| e.to_string())
+}
+
+/// Resolves an included path relative to the directory of the including
+/// file, removing `.` and `..` components so that each file has a single
+/// name.
+fn resolve(including: &str, path: &str) -> String {
+    let mut parts: Vec<&str> = if path.starts_with('/') {
+        vec![""]
+    } else {
+        including.split('/').collect()
+    };
+    // Drop the including file's own name.
+    if !path.starts_with('/') {
+        parts.pop();
+    }
+    for part in path.split('/') {
+        match part {
+            "" | "." => (),
+            ".." => match parts.last() {
+                // `..` at the start of a relative path cannot be resolved.
+                Some(&last) if !last.is_empty() && last != ".." => {
+                    parts.pop();
+                }
+                _ => parts.push(part),
+            },
+            _ => parts.push(part),
+        }
+    }
+    parts.join("/")
+}
+
+/// Returns the paths included by `source`, resolved against `path`.
+fn included_paths(path: &str, source: &str) -> Vec<String> {
+    fn collect(commands: &[Command], path: &str, paths: &mut Vec<String>) {
+        for command in commands {
+            match command {
+                Command::Include(included) => {
+                    paths.push(resolve(path, included))
+                }
+                Command::Com(args) => {
+                    for arg in args {
+                        if let Arg::Block(block) = arg {
+                            collect(block, path, paths);
+                        }
+                    }
+                }
+                Command::For(_, _, _, body) => collect(body, path, paths),
+                _ => (),
+            }
+        }
+    }
+    let mut paths = vec![];
+    if let Ok((_, commands)) = commands(source) {
+        collect(&commands, path, &mut paths);
+    }
+    paths
+}
+
+impl Sources {
+    /// Reads `root` and every file it includes, directly or indirectly.
+    /// Only a failure to read `root` itself is an error; other files that
+    /// cannot be read are reported where they are included.
+    pub async fn load(root: &str) -> Result<Self, String> {
+        let source = read_file(root)
+            .await
+            .map_err(|e| format!("cannot read `{root}`: {e}"))?;
+        let mut sources = Self::new(root, source);
+        let mut next = 0;
+        while let Some(included_paths) = sources.included_paths(next) {
+            for included in included_paths {
+                if !sources.contains(&included) {
+                    let source = read_file(&included).await;
+                    sources.add(included, source);
+                }
+            }
+            next += 1;
+        }
+        Ok(sources)
+    }
+
+    /// Like [`Sources::load`], with the contents of `root` given rather
+    /// than read, as an editor has them before they are saved.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn with_root(root: &str, source: String) -> Self {
+        let mut sources = Self::new(root, source);
+        let mut next = 0;
+        while let Some(included_paths) = sources.included_paths(next) {
+            for included in included_paths {
+                if !sources.contains(&included) {
+                    let source = std::fs::read_to_string(&included)
+                        .map_err(|e| e.to_string());
+                    sources.add(included, source);
+                }
+            }
+            next += 1;
+        }
+        sources
+    }
+
+    fn new(root: &str, source: String) -> Self {
+        Self {
+            files: vec![(root.to_owned(), source)],
+            unreadable: HashMap::new(),
+        }
+    }
+
+    /// Returns the paths included by file number `index`, or `None` if
+    /// there are fewer files.
+    fn included_paths(&self, index: usize) -> Option<Vec<String>> {
+        let (path, source) = self.files.get(index)?;
+        Some(included_paths(path, source))
+    }
+
+    fn add(&mut self, path: String, source: Result<String, String>) {
+        match source {
+            Ok(source) => self.files.push((path, source)),
+            Err(e) => {
+                self.unreadable.insert(path, e);
+            }
+        }
+    }
+
+    /// Returns the contents of the root file.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn root(&self) -> &str {
+        &self.files[0].1
+    }
+
+    fn contains(&self, path: &str) -> bool {
+        self.unreadable.contains_key(path)
+            || self.files.iter().any(|(file, _)| file == path)
+    }
+
+    fn get(&self, path: &str) -> Option<&str> {
+        self.files
+            .iter()
+            .find(|(file, _)| file == path)
+            .map(|(_, source)| source.as_str())
+    }
+
+    /// Creates a diagnostics collector that knows about every file.
+    pub fn diagnostics(&self) -> Diagnostics<'_> {
+        let mut files = self.files.iter();
+        let (path, source) = files.next().unwrap();
+        let mut diagnostics = Diagnostics::new(path, source);
+        for (path, source) in files {
+            diagnostics.add_file(path, source);
+        }
+        diagnostics
+    }
+
+    /// Parses the root file, replacing each `include` with the commands of
+    /// the included file.
+    pub fn parse<'src>(
+        &'src self,
+        diagnostics: &mut Diagnostics<'src>,
+    ) -> Vec<Command<'src>> {
+        let (root, source) = &self.files[0];
+        let mut stack = vec![root.clone()];
+        let commands = parse_scene(source, diagnostics);
+        self.expand(commands, &mut stack, diagnostics)
+    }
+
+    /// `stack` holds the chain of files being included, the last one
+    /// containing `commands`, to detect files that include themselves.
+    fn expand<'src>(
+        &'src self,
+        commands: Vec<Command<'src>>,
+        stack: &mut Vec<String>,
+        diagnostics: &mut Diagnostics<'src>,
+    ) -> Vec<Command<'src>> {
+        let mut expanded = vec![];
+        for command in commands {
+            match command {
+                Command::Include(included) => {
+                    let path = resolve(stack.last().unwrap(), included);
+                    if stack.contains(&path) {
+                        stack.push(path);
+                        diagnostics.error(
+                            included,
+                            format!("include cycle: {}", stack.join(" -> ")),
+                        );
+                        stack.pop();
+                    } else if let Some(e) = self.unreadable.get(&path) {
+                        diagnostics.error(
+                            included,
+                            format!("cannot read `{path}`: {e}"),
+                        );
+                    } else if let Some(source) = self.get(&path) {
+                        let commands = parse_scene(source, diagnostics);
+                        stack.push(path);
+                        expanded.extend(self.expand(
+                            commands,
+                            stack,
+                            diagnostics,
+                        ));
+                        stack.pop();
+                    }
+                }
+                Command::Com(args) => {
+                    let args = args
+                        .into_iter()
+                        .map(|arg| match arg {
+                            Arg::Block(block) => Arg::Block(self.expand(
+                                block,
+                                stack,
+                                diagnostics,
+                            )),
+                            arg => arg,
+                        })
+                        .collect();
+                    expanded.push(Command::Com(args));
+                }
+                Command::For(var, start, end, body) => {
+                    let body = self.expand(body, stack, diagnostics);
+                    expanded.push(Command::For(var, start, end, body));
+                }
+                command => expanded.push(command),
+            }
+        }
+        expanded
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn sources(files: &[(&str, &str)]) -> Sources {
+        Sources {
+            files: files
+                .iter()
+                .map(|(path, source)| (path.to_string(), source.to_string()))
+                .collect(),
+            unreadable: HashMap::from([(
+                "assets/missing.txt".to_owned(),
+                "not found".to_owned(),
+            )]),
+        }
+    }
+
+    #[test]
+    fn test_resolve() {
+        assert_eq!(resolve("assets/sol.txt", "moons.txt"), "assets/moons.txt");
+        assert_eq!(
+            resolve("assets/sol.txt", "./jovian/../moons.txt"),
+            "assets/moons.txt"
+        );
+        assert_eq!(resolve("sol.txt", "../lib/a.txt"), "../lib/a.txt");
+        assert_eq!(resolve("assets/sol.txt", "/tmp/a.txt"), "/tmp/a.txt");
+    }
+
+    #[test]
+    fn test_included_paths() {
+        let source = "include \"a.txt\"\nastro X {\n  include \"b/c.txt\"\n}\n";
+        assert_eq!(
+            included_paths("assets/sol.txt", source),
+            ["assets/a.txt", "assets/b/c.txt"]
+        );
+    }
+
+    #[test]
+    fn test_expand() {
+        let sources = sources(&[
+            ("assets/sol.txt", "a = 1\ninclude \"moons.txt\"\nb = 2\n"),
+            ("assets/moons.txt", "astro Io {\n  include \"io.txt\"\n}\n"),
+            ("assets/io.txt", "radius: 0.01\n"),
+        ]);
+        let mut diagnostics = sources.diagnostics();
+        let commands = sources.parse(&mut diagnostics);
+        assert_eq!(diagnostics.error_count(), 0);
+        assert_eq!(commands.len(), 3);
+        let Command::Com(args) = &commands[1] else {
+            panic!("expected astro command, found {:?}", commands[1]);
+        };
+        let Arg::Block(block) = &args[2] else {
+            panic!("expected block, found {:?}", args[2]);
+        };
+        assert!(matches!(block[..], [Command::Prop("radius", _)]));
+    }
+
+    #[test]
+    fn test_include_errors() {
+        let sources = sources(&[
+            (
+                "assets/sol.txt",
+                "include \"a.txt\"\ninclude \"missing.txt\"\n",
+            ),
+            ("assets/a.txt", "x = )\ninclude \"sol.txt\"\n"),
+        ]);
+        let mut diagnostics = sources.diagnostics();
+        sources.parse(&mut diagnostics);
+        let messages: Vec<_> = diagnostics
+            .items()
+            .iter()
+            .map(|item| (item.file, item.message.as_str()))
+            .collect();
+        assert_eq!(
+            messages,
+            [
+                (1, "could not parse this statement"),
+                (
+                    1,
+                    "include cycle: assets/sol.txt -> assets/a.txt -> \
+                    assets/sol.txt"
+                ),
+                (0, "cannot read `assets/missing.txt`: not found"),
+            ]
+        );
+    }
+}
@@ -0,0 +1,194 @@
+use std::fmt::{self, Display};
+
+/// Physical dimension of a quantity, as exponents of length, time and
+/// mass.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Dim {
+    pub length: i8,
+    pub time: i8,
+    pub mass: i8,
+}
+
+impl Dim {
+    pub const NONE: Self = Self::new(0, 0, 0);
+    pub const LENGTH: Self = Self::new(1, 0, 0);
+    pub const TIME: Self = Self::new(0, 1, 0);
+    pub const MASS: Self = Self::new(0, 0, 1);
+    /// The dimension of a gravitational parameter `G M`.
+    pub const GM: Self = Self::new(3, -2, 0);
+
+    const fn new(length: i8, time: i8, mass: i8) -> Self {
+        Self { length, time, mass }
+    }
+
+    pub fn is_none(&self) -> bool {
+        *self == Self::NONE
+    }
+
+    /// Returns the dimension of a product, or `None` if an exponent
+    /// overflows.
+    pub(crate) fn checked_mul(self, other: Self) -> Option<Self> {
+        Some(Self {
+            length: self.length.checked_add(other.length)?,
+            time: self.time.checked_add(other.time)?,
+            mass: self.mass.checked_add(other.mass)?,
+        })
+    }
+
+    /// Returns the dimension of a quotient, or `None` if an exponent
+    /// overflows.
+    pub(crate) fn checked_div(self, other: Self) -> Option<Self> {
+        Some(Self {
+            length: self.length.checked_sub(other.length)?,
+            time: self.time.checked_sub(other.time)?,
+            mass: self.mass.checked_sub(other.mass)?,
+        })
+    }
+
+    /// Returns the dimension of a power, or `None` if an exponent
+    /// overflows.
+    pub(crate) fn checked_powi(self, n: i8) -> Option<Self> {
+        Some(Self {
+            length: self.length.checked_mul(n)?,
+            time: self.time.checked_mul(n)?,
+            mass: self.mass.checked_mul(n)?,
+        })
+    }
+}
+
+impl Display for Dim {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.length, self.time, self.mass) {
+            (0, 0, 0) => write!(f, "number"),
+            (1, 0, 0) => write!(f, "length"),
+            (2, 0, 0) => write!(f, "area"),
+            (3, 0, 0) => write!(f, "volume"),
+            (0, 1, 0) => write!(f, "time"),
+            (0, -1, 0) => write!(f, "frequency"),
+            (1, -1, 0) => write!(f, "speed"),
+            (0, 0, 1) => write!(f, "mass"),
+            (length, time, 0) => {
+                write!(f, "quantity of dimension length^{length} time^{time}")
+            }
+            (length, time, mass) => write!(
+                f,
+                "quantity of dimension length^{length} time^{time} \
+                 mass^{mass}"
+            ),
+        }
+    }
+}
+
+/// Lengths are measured in AU, times in days and masses in solar masses
+/// inside the simulator, so that one second of animation is one day.
+const AU_IN_METERS: f64 = 149_597_870_700.;
+const DAY_IN_SECONDS: f64 = 86_400.;
+
+/// Gravitational parameters in m³/s², from the IAU 2015 nominal values
+/// and the JPL planetary ephemerides, which are known far more precisely
+/// than the masses themselves.
+const SUN_GM: f64 = 1.327_124_4e20;
+const EARTH_GM: f64 = 3.986_004e14;
+const JUPITER_GM: f64 = 1.266_865_3e17;
+/// The gravitational constant in m³/(kg s²), from CODATA 2018.
+const G_SI: f64 = 6.674_30e-11;
+
+/// The gravitational constant in AU³/(d² solar masses), the square of the
+/// Gaussian gravitational constant.
+pub(crate) const GRAVITATIONAL_CONSTANT: f64 =
+    SUN_GM * DAY_IN_SECONDS * DAY_IN_SECONDS
+        / (AU_IN_METERS * AU_IN_METERS * AU_IN_METERS);
+
+/// Unit suffixes that can follow a number literal, with the factor that
+/// converts them to internal units.
+const UNITS: &[(&str, f64, Dim)] = &[
+    ("m", 1. / AU_IN_METERS, Dim::LENGTH),
+    ("km", 1e3 / AU_IN_METERS, Dim::LENGTH),
+    ("AU", 1., Dim::LENGTH),
+    ("au", 1., Dim::LENGTH),
+    ("ly", 9_460_730_472_580_800. / AU_IN_METERS, Dim::LENGTH),
+    ("pc", 648_000. / std::f64::consts::PI, Dim::LENGTH),
+    ("s", 1. / DAY_IN_SECONDS, Dim::TIME),
+    ("min", 60. / DAY_IN_SECONDS, Dim::TIME),
+    ("h", 3600. / DAY_IN_SECONDS, Dim::TIME),
+    ("d", 1., Dim::TIME),
+    ("day", 1., Dim::TIME),
+    ("days", 1., Dim::TIME),
+    ("yr", 365.25, Dim::TIME),
+    ("year", 365.25, Dim::TIME),
+    ("years", 365.25, Dim::TIME),
+    ("kg", G_SI / SUN_GM, Dim::MASS),
+    ("Msun", 1., Dim::MASS),
+    ("Mearth", EARTH_GM / SUN_GM, Dim::MASS),
+    ("Mjup", JUPITER_GM / SUN_GM, Dim::MASS),
+    // Angles are dimensionless and measured in radians.
+    ("deg", std::f64::consts::PI / 180., Dim::NONE),
+    ("rad", 1., Dim::NONE),
+];
+
+/// Returns the factor converting `name` to internal units, and its
+/// dimension.
+pub(crate) fn lookup(name: &str) -> Option<(f64, Dim)> {
+    UNITS
+        .iter()
+        .find(|(unit, _, _)| *unit == name)
+        .map(|(_, factor, dim)| (*factor, *dim))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_lookup() {
+        let (factor, dim) = lookup("km").unwrap();
+        assert_eq!(dim, Dim::LENGTH);
+        assert!((AU_IN_METERS / 1e3 * factor - 1.).abs() < 1e-12);
+        assert_eq!(lookup("h"), Some((1. / 24., Dim::TIME)));
+        assert!((lookup("pc").unwrap().0 - 206_264.806).abs() < 1e-3);
+        assert_eq!(lookup("furlong"), None);
+        // About 333 000 Earths or 1.989e30 kg make the Sun.
+        let (earth, dim) = lookup("Mearth").unwrap();
+        assert_eq!(dim, Dim::MASS);
+        assert!((1. / earth - 332_946.).abs() < 1.);
+        assert!((1. / lookup("kg").unwrap().0 / 1.9884e30 - 1.).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_gravitational_constant() {
+        // The Gaussian gravitational constant.
+        let k = GRAVITATIONAL_CONSTANT.sqrt();
+        assert!((k - 0.017_202_098_95).abs() < 1e-10);
+    }
+
+    #[test]
+    fn test_dim_overflow() {
+        let most = Dim::LENGTH.checked_powi(127).unwrap();
+        assert_eq!(most.checked_mul(Dim::LENGTH), None);
+        assert_eq!(most.checked_powi(2), None);
+        let least = Dim::LENGTH.checked_powi(-128).unwrap();
+        assert_eq!(least.checked_div(Dim::LENGTH), None);
+        assert_eq!(
+            least.checked_mul(most),
+            Some(Dim::LENGTH.checked_powi(-1).unwrap())
+        );
+    }
+
+    #[test]
+    fn test_dim_display() {
+        assert_eq!(Dim::LENGTH.to_string(), "length");
+        assert_eq!(
+            Dim::LENGTH.checked_div(Dim::TIME).unwrap().to_string(),
+            "speed"
+        );
+        assert_eq!(
+            Dim::GM.to_string(),
+            "quantity of dimension length^3 time^-2"
+        );
+        assert_eq!(Dim::MASS.to_string(), "mass");
+        assert_eq!(
+            Dim::GM.checked_div(Dim::MASS).unwrap().to_string(),
+            "quantity of dimension length^3 time^-2 mass^-1"
+        );
+    }
+}